@@ -190,7 +190,7 @@ fn stream(c: &mut Criterion) {
             let mut values: Vec<u16> = Vec::new();
             let _listener = sink
                 .stream()
-                .filter(|v: &u16| *v % 3 == 0 && *v % 5 == 0)
+                .filter(|v: &u16| v.is_multiple_of(3) && v.is_multiple_of(5))
                 .listen(move |v: &u16| values.push(black_box(*v)));
 
             for v in 0_u16..1000 {
@@ -207,7 +207,7 @@ fn stream(c: &mut Criterion) {
             let _listener = sink
                 .stream()
                 .map(|v: &u16| *v + 1)
-                .filter(|v: &u16| *v % 3 == 0 && *v % 5 == 0)
+                .filter(|v: &u16| v.is_multiple_of(3) && v.is_multiple_of(5))
                 .listen(move |v: &u16| values.push(black_box(*v)));
 
             for v in 0_u16..1000 {
@@ -224,7 +224,7 @@ fn stream(c: &mut Criterion) {
             let _listener = sink
                 .stream()
                 .map(|v: &u16| *v + 1)
-                .filter(|v: &u16| *v % 3 == 0 && *v % 5 == 0)
+                .filter(|v: &u16| v.is_multiple_of(3) && v.is_multiple_of(5))
                 .map(|v: &u16| *v - 4)
                 .listen(move |v: &u16| values.push(black_box(*v)));
 
@@ -242,9 +242,9 @@ fn stream(c: &mut Criterion) {
             let _listener = sink
                 .stream()
                 .map(|v: &u16| *v + 1)
-                .filter(|v: &u16| *v % 3 == 0)
+                .filter(|v: &u16| v.is_multiple_of(3))
                 .map(|v: &u16| *v - 4)
-                .filter(|v: &u16| *v % 5 == 0)
+                .filter(|v: &u16| v.is_multiple_of(5))
                 .listen(move |v: &u16| values.push(black_box(*v)));
 
             for v in 0_u16..1000 {
@@ -470,5 +470,32 @@ fn snapshot(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, snapshot, cell, stream);
+fn propagation(c: &mut Criterion) {
+    let mut propagation = c.benchmark_group("propagation");
+    propagation.bench_function("map chain 1000", |b| {
+        let ctx = SodiumCtx::new();
+        let sink = ctx.new_stream_sink();
+        let mut s = sink.stream();
+        for _ in 0..1000 {
+            s = s.map(|v: &u32| black_box(*v) + 1);
+        }
+        let mut values: Vec<u32> = Vec::new();
+        let _listener = s.listen(move |v: &u32| values.push(black_box(*v)));
+        b.iter(|| sink.send(black_box(0)));
+    });
+    propagation.bench_function("wide merge 256", |b| {
+        let ctx = SodiumCtx::new();
+        let sink = ctx.new_stream_sink();
+        let mut merged = sink.stream().map(|v: &u32| *v);
+        for i in 1..256 {
+            let branch = sink.stream().map(move |v: &u32| black_box(*v) + i);
+            merged = merged.merge(&branch, |a: &u32, b: &u32| *a + *b);
+        }
+        let mut values: Vec<u32> = Vec::new();
+        let _listener = merged.listen(move |v: &u32| values.push(black_box(*v)));
+        b.iter(|| sink.send(black_box(0)));
+    });
+}
+
+criterion_group!(benches, snapshot, cell, stream, propagation);
 criterion_main!(benches);
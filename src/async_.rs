@@ -0,0 +1,184 @@
+//! Bridges between Sodium and `std` futures, enabled with the `async`
+//! feature.
+
+use crate::Cell;
+use crate::Listener;
+use crate::Stream;
+use crate::StreamSink;
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+/// How an [`AsyncStream`] buffers events that have not been taken yet.
+///
+/// Listeners can't block a transaction, so a bounded buffer drops
+/// events once it is full rather than pushing back on the sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsyncBuffer {
+    /// Keep every event.
+    Unbounded,
+    /// Keep at most this many events, dropping the oldest to make room
+    /// for a new one.
+    DropOldest(usize),
+    /// Keep at most this many events, dropping new events while full.
+    DropNewest(usize),
+}
+
+struct AsyncStreamState<A> {
+    queue: VecDeque<A>,
+    waker: Option<Waker>,
+}
+
+/// A [`futures_core::Stream`] of the events of a Sodium [`Stream`],
+/// returned by [`Stream::to_async_stream`].
+///
+/// The stream never ends. Dropping it unlistens from the Sodium
+/// stream.
+pub struct AsyncStream<A> {
+    state: Arc<Mutex<AsyncStreamState<A>>>,
+    listener: Listener,
+}
+
+impl<A: Clone + Send + 'static> AsyncStream<A> {
+    pub(crate) fn new(s: &Stream<A>, buffer: AsyncBuffer) -> AsyncStream<A> {
+        let state = Arc::new(Mutex::new(AsyncStreamState {
+            queue: VecDeque::new(),
+            waker: None,
+        }));
+        let listener;
+        {
+            let state = state.clone();
+            listener = s.listen(move |a: &A| {
+                let waker_op;
+                {
+                    let mut state = state.lock();
+                    match buffer {
+                        AsyncBuffer::Unbounded => state.queue.push_back(a.clone()),
+                        AsyncBuffer::DropOldest(capacity) => {
+                            if state.queue.len() >= capacity {
+                                state.queue.pop_front();
+                            }
+                            if capacity > 0 {
+                                state.queue.push_back(a.clone());
+                            }
+                        }
+                        AsyncBuffer::DropNewest(capacity) => {
+                            if state.queue.len() < capacity {
+                                state.queue.push_back(a.clone());
+                            }
+                        }
+                    }
+                    waker_op = state.waker.take();
+                }
+                if let Some(waker) = waker_op {
+                    waker.wake();
+                }
+            });
+        }
+        AsyncStream { state, listener }
+    }
+}
+
+impl<A> futures_core::Stream for AsyncStream<A> {
+    type Item = A;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<A>> {
+        let mut state = self.state.lock();
+        match state.queue.pop_front() {
+            Some(a) => Poll::Ready(Some(a)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<A> Drop for AsyncStream<A> {
+    fn drop(&mut self) {
+        self.listener.unlisten();
+    }
+}
+
+struct ChangedState<A> {
+    value_op: Option<A>,
+    waker: Option<Waker>,
+}
+
+/// A future resolving to the next value of a [`Cell`], returned by
+/// [`Cell::changed`].
+///
+/// Dropping it unlistens from the cell.
+pub struct Changed<A> {
+    state: Arc<Mutex<ChangedState<A>>>,
+    listener: Listener,
+}
+
+impl<A: Clone + Send + 'static> Changed<A> {
+    pub(crate) fn new(c: &Cell<A>) -> Changed<A> {
+        let state = Arc::new(Mutex::new(ChangedState {
+            value_op: None,
+            waker: None,
+        }));
+        let listener;
+        {
+            let state = state.clone();
+            listener = c.updates().listen(move |a: &A| {
+                let waker_op;
+                {
+                    let mut state = state.lock();
+                    state.value_op = Some(a.clone());
+                    waker_op = state.waker.take();
+                }
+                if let Some(waker) = waker_op {
+                    waker.wake();
+                }
+            });
+        }
+        Changed { state, listener }
+    }
+}
+
+impl<A> Future for Changed<A> {
+    type Output = A;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<A> {
+        let value_op = {
+            let mut state = self.state.lock();
+            let value_op = state.value_op.take();
+            if value_op.is_none() {
+                state.waker = Some(cx.waker().clone());
+            }
+            value_op
+        };
+        match value_op {
+            Some(a) => {
+                self.listener.unlisten();
+                Poll::Ready(a)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<A> Drop for Changed<A> {
+    fn drop(&mut self) {
+        self.listener.unlisten();
+    }
+}
+
+pub(crate) async fn feed<A, S>(sink: StreamSink<A>, stream: S)
+where
+    A: Clone + Send + 'static,
+    S: futures_core::Stream<Item = A>,
+{
+    use futures_util::StreamExt;
+    futures_util::pin_mut!(stream);
+    while let Some(a) = stream.next().await {
+        sink.send(a);
+    }
+}
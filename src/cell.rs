@@ -1,3 +1,6 @@
+#[cfg(feature = "async")]
+use crate::async_::Changed;
+use crate::cell_tuple::CellTuple;
 use crate::impl_::cell::Cell as CellImpl;
 use crate::impl_::lambda::IsLambda1;
 use crate::impl_::lambda::IsLambda2;
@@ -6,11 +9,21 @@ use crate::impl_::lambda::IsLambda4;
 use crate::impl_::lambda::IsLambda5;
 use crate::impl_::lambda::IsLambda6;
 use crate::impl_::lazy::Lazy;
+use crate::impl_::name::CallerLocation;
 use crate::listener::Listener;
 use crate::sodium_ctx::SodiumCtx;
 use crate::stream::Stream;
+use crate::timer::TimerSystem;
 use crate::Dep;
 
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A function from `A` to `B` that can be held in a [`Cell`], for
+/// [`Cell::apply`].
+pub type CellFn<A, B> = Arc<dyn Fn(&A) -> B + Send + Sync>;
+
 /// Represents a value of type `A` that changes over time.
 ///
 /// In other Functional Reactive Programming (FRP) systems this is
@@ -31,7 +44,9 @@ impl<A> Clone for Cell<A> {
 
 impl<A: Clone + Send + 'static> Cell<A> {
     /// Create a `Cell` with a constant value.
+    #[track_caller]
     pub fn new(sodium_ctx: &SodiumCtx, value: A) -> Cell<A> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: CellImpl::new(&sodium_ctx.impl_, value),
         }
@@ -69,6 +84,25 @@ impl<A: Clone + Send + 'static> Cell<A> {
         self.impl_.to_dep()
     }
 
+    /// Attach a label to the node behind this cell, and return the
+    /// cell.
+    ///
+    /// See [`Stream::named`].
+    pub fn named(&self, label: &str) -> Cell<A> {
+        self.impl_.node().gc_node.set_label(label);
+        self.clone()
+    }
+
+    /// Let [`SodiumCtx::graph`] show the value of this cell, and return
+    /// the cell.
+    pub fn debug_values(&self) -> Cell<A>
+    where
+        A: fmt::Debug,
+    {
+        self.impl_.debug_values();
+        self.clone()
+    }
+
     /// Return a [`Stream`] that gives the updates/steps for a `Cell`.
     ///
     /// ## Important
@@ -78,7 +112,9 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// cell updates/steps. The rule with this primitive is that you
     /// should only use it in functions that don't allow the caller to
     /// detect the `Cell` updates.
+    #[track_caller]
     pub fn updates(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.updates(),
         }
@@ -97,7 +133,9 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// cell updates/steps. The rule with this primitive is that you
     /// should only use it in functions that don't allow the caller to
     /// detect the `Cell` updates.
+    #[track_caller]
     pub fn value(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.value(),
         }
@@ -108,18 +146,45 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// The returned `Cell` always reflects the value produced by the
     /// function applied to the input `Cell`s value. The given
     /// function _must_ be referentially transparent.
+    #[track_caller]
     pub fn map<B: Clone + Send + 'static, FN: IsLambda1<A, B> + Send + Sync + 'static>(
         &self,
         f: FN,
     ) -> Cell<B> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.map(f),
         }
     }
 
+    /// Return a `Cell` with the same value as this one, whose
+    /// [`updates`][Cell::updates] only fire when the value changes.
+    ///
+    /// Updates to an equal value stop at this cell, so the cells,
+    /// streams and listeners that depend on it do no work for them.
+    #[track_caller]
+    pub fn calm(&self) -> Cell<A>
+    where
+        A: PartialEq,
+    {
+        let _caller = CallerLocation::enter();
+        self.calm_by(|a: &A, b: &A| a == b)
+    }
+
+    /// A variant of [`calm`][Cell::calm] that compares values with
+    /// `eq`.
+    #[track_caller]
+    pub fn calm_by<EQ: IsLambda2<A, A, bool> + Send + Sync + 'static>(&self, eq: EQ) -> Cell<A> {
+        let _caller = CallerLocation::enter();
+        Cell {
+            impl_: self.impl_.calm_by(eq),
+        }
+    }
+
     /// Lift a binary function into cells so the returned [`Cell`]
     /// always reflects the specified function applied to the input
     /// cells' values.
+    #[track_caller]
     pub fn lift2<
         B: Clone + Send + 'static,
         C: Clone + Send + 'static,
@@ -129,6 +194,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
         cb: &Cell<B>,
         f: FN,
     ) -> Cell<C> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.lift2(&cb.impl_, f),
         }
@@ -137,6 +203,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// Lift a ternary function into cells so the returned [`Cell`]
     /// always reflects the specified function applied to the input
     /// cells' values.
+    #[track_caller]
     pub fn lift3<
         B: Clone + Send + 'static,
         C: Clone + Send + 'static,
@@ -148,6 +215,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
         cc: &Cell<C>,
         f: FN,
     ) -> Cell<D> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.lift3(&cb.impl_, &cc.impl_, f),
         }
@@ -156,6 +224,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// Lift a quaternary function into cells so the returned [`Cell`]
     /// always reflects the specified function applied to the input
     /// cells' values.
+    #[track_caller]
     pub fn lift4<
         B: Clone + Send + 'static,
         C: Clone + Send + 'static,
@@ -169,6 +238,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
         cd: &Cell<D>,
         f: FN,
     ) -> Cell<E> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.lift4(&cb.impl_, &cc.impl_, &cd.impl_, f),
         }
@@ -177,6 +247,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// Lift a five-argument function into cells so the returned
     /// [`Cell`] always reflects the specified function applied to the
     /// input cells' values.
+    #[track_caller]
     pub fn lift5<
         B: Clone + Send + 'static,
         C: Clone + Send + 'static,
@@ -192,6 +263,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
         ce: &Cell<E>,
         f: FN,
     ) -> Cell<F> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self
                 .impl_
@@ -202,6 +274,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
     /// Lift a six argument function into cells so the returned
     /// [`Cell`] always reflects the specified function applied to the
     /// input cells' values.
+    #[track_caller]
     pub fn lift6<
         B: Clone + Send + 'static,
         C: Clone + Send + 'static,
@@ -219,6 +292,7 @@ impl<A: Clone + Send + 'static> Cell<A> {
         cf: &Cell<F>,
         f: FN,
     ) -> Cell<G> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self
                 .impl_
@@ -226,23 +300,136 @@ impl<A: Clone + Send + 'static> Cell<A> {
         }
     }
 
+    /// Lift a function of the values of a tuple of up to 12 cells into
+    /// cells, so the returned [`Cell`] always reflects the function
+    /// applied to the cells' values.
+    ///
+    /// The function is given a tuple of the values:
+    ///
+    /// ```
+    /// use sodium_rust::{Cell, SodiumCtx};
+    ///
+    /// let sodium_ctx = SodiumCtx::new();
+    /// let ca = sodium_ctx.new_cell_sink(1);
+    /// let cb = sodium_ctx.new_cell(2);
+    /// let cc = sodium_ctx.new_cell(3);
+    /// let sum = Cell::lift_n((&ca.cell(), &cb, &cc), |(a, b, c): &(i32, i32, i32)| {
+    ///     a + b + c
+    /// });
+    /// ca.send(10);
+    /// assert_eq!(sum.sample(), 15);
+    /// ```
+    #[track_caller]
+    pub fn lift_n<CS: CellTuple, FN: IsLambda1<CS::Values, A> + Send + Sync + 'static>(
+        cells: CS,
+        f: FN,
+    ) -> Cell<A> {
+        let _caller = CallerLocation::enter();
+        cells.combine().map(f)
+    }
+
+    /// Combine a runtime-sized collection of cells into a [`Cell`] of
+    /// their values, in the same order.
+    ///
+    /// This is a single node however many cells there are. Each change
+    /// gives a new `Vec` of all the values, so it costs a copy of every
+    /// value; use [`Cell::lift_all`] to look at the values without
+    /// copying them. If `cells` is empty the returned cell holds an
+    /// empty `Vec` for good.
+    #[track_caller]
+    pub fn sequence(sodium_ctx: &SodiumCtx, cells: Vec<Cell<A>>) -> Cell<Vec<A>> {
+        let _caller = CallerLocation::enter();
+        Cell::lift_all(sodium_ctx, &cells, |values: &Vec<A>| values.clone())
+    }
+
+    /// Lift a function of the values of a runtime-sized collection of
+    /// cells into cells, so the returned [`Cell`] always reflects the
+    /// function applied to the cells' values, given in the same order.
+    ///
+    /// Like [`Cell::sequence`] this is a single node. It keeps the
+    /// values of the cells and only copies in the new values of the
+    /// cells that changed, then gives `f` a reference to all of them,
+    /// so a change costs a check of each cell plus whatever `f` does
+    /// with the values. If `cells` is empty the returned cell holds `f`
+    /// applied to an empty `Vec` for good.
+    #[track_caller]
+    pub fn lift_all<B: Clone + Send + 'static, FN: IsLambda1<Vec<A>, B> + Send + Sync + 'static>(
+        sodium_ctx: &SodiumCtx,
+        cells: &[Cell<A>],
+        f: FN,
+    ) -> Cell<B> {
+        let _caller = CallerLocation::enter();
+        let cells: Vec<CellImpl<A>> = cells.iter().map(|c| c.impl_.clone()).collect();
+        Cell {
+            impl_: CellImpl::lift_all(&sodium_ctx.impl_, &cells, f),
+        }
+    }
+
+    /// Apply the function held in `cf` to the value of `ca`, so the
+    /// returned [`Cell`] always reflects the current function applied
+    /// to the current value.
+    ///
+    /// The functions held in `cf` should not sample cells, as their
+    /// dependencies can't be tracked.
+    #[track_caller]
+    pub fn apply<B: Clone + Send + 'static>(cf: &Cell<CellFn<A, B>>, ca: &Cell<A>) -> Cell<B> {
+        let _caller = CallerLocation::enter();
+        cf.lift2(ca, |f: &CellFn<A, B>, a: &A| f(a))
+    }
+
     /// Unwrap a [`Stream`] in a `Cell` to give a time-varying stream implementation.
+    #[track_caller]
     pub fn switch_s(csa: &Cell<Stream<A>>) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: CellImpl::switch_s(&csa.map(|sa: &Stream<A>| sa.impl_.clone()).impl_),
         }
     }
 
     /// Unwrap a `Cell` in another `Cell` to give a time-varying cell implementation.
+    #[track_caller]
     pub fn switch_c(cca: &Cell<Cell<A>>) -> Cell<A> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: CellImpl::switch_c(&cca.map(|ca: &Cell<A>| ca.impl_.clone()).impl_),
         }
     }
 
+    /// Return a future that resolves to the next value this cell
+    /// changes to, for use from async code.
+    ///
+    /// The listener is registered straight away, so a change made
+    /// before the future is first polled is not missed. Dropping the
+    /// future deregisters its listener.
+    #[cfg(feature = "async")]
+    #[track_caller]
+    pub fn changed(&self) -> Changed<A> {
+        let _caller = CallerLocation::enter();
+        Changed::new(self)
+    }
+
+    /// Return a stream that outputs the value of this cell every
+    /// `period`, on the ticks of
+    /// [`TimerSystem::periodic`][crate::timer::TimerSystem::periodic].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    #[track_caller]
+    pub fn sample_every<TS: TimerSystem + ?Sized>(
+        &self,
+        timer_system: &TS,
+        period: Duration,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        timer_system.periodic(period).snapshot1(self)
+    }
+
     /// A variant of [`listen`][Cell::listen] that will deregister the
     /// listener automatically if the listener is garbage-collected.
+    #[track_caller]
     pub fn listen_weak<K: FnMut(&A) + Send + Sync + 'static>(&self, k: K) -> Listener {
+        let _caller = CallerLocation::enter();
         Listener {
             impl_: self.impl_.listen_weak(k),
         }
@@ -256,7 +443,9 @@ impl<A: Clone + Send + 'static> Cell<A> {
     ///
     /// This is an operational mechanism for interfacing between the
     /// world of I/O and FRP.
+    #[track_caller]
     pub fn listen<K: IsLambda1<A, ()> + Send + Sync + 'static>(&self, k: K) -> Listener {
+        let _caller = CallerLocation::enter();
         Listener {
             impl_: self.impl_.listen(k),
         }
@@ -1,5 +1,7 @@
 use crate::impl_::cell_loop::CellLoop as CellLoopImpl;
+use crate::impl_::name::CallerLocation;
 use crate::Cell;
+use crate::LoopError;
 use crate::SodiumCtx;
 
 /// A forward reference for a [`Cell`] for creating dependency loops.
@@ -8,7 +10,9 @@ use crate::SodiumCtx;
 /// referenced [`Cell`] by calling [`loop_`][CellLoop::loop_] _must_
 /// occur within the same transaction, whether that is created by
 /// calling [`SodiumCtx::transaction`] or
-/// [`Transaction::new`][crate::Transaction::new].
+/// [`Transaction::new`][crate::Transaction::new]. Closing the
+/// transaction without looping it panics with a
+/// [`LoopError::NotLooped`] naming the loop.
 pub struct CellLoop<A> {
     impl_: CellLoopImpl<A>,
 }
@@ -23,12 +27,21 @@ impl<A> Clone for CellLoop<A> {
 
 impl<A: Send + Clone + 'static> CellLoop<A> {
     /// Create a new `CellLoop` in the given context.
+    #[track_caller]
     pub fn new(sodium_ctx: &SodiumCtx) -> CellLoop<A> {
+        let _caller = CallerLocation::enter();
         CellLoop {
             impl_: CellLoopImpl::new(&sodium_ctx.impl_),
         }
     }
 
+    /// Attach a label to this loop, to be shown with its source location
+    /// in [`LoopError`]s, and return the loop.
+    pub fn named(self, label: &str) -> CellLoop<A> {
+        self.impl_.stream_loop.gc_node.set_label(label);
+        self
+    }
+
     /// Return a [`Cell`] that is equivalent to this `CellLoop` once it
     /// has been resolved by calling [`loop_`][CellLoop::loop_].
     ///
@@ -49,7 +62,20 @@ impl<A: Send + Clone + 'static> CellLoop<A> {
     /// an explicit transaction, either with
     /// [`SodiumCtx::transaction`] or
     /// [`Transaction::new`][crate::Transaction::new].
+    ///
+    /// # Panics
+    ///
+    /// Panics if this `CellLoop` has already been looped, or if `ca`
+    /// belongs to another [`SodiumCtx`]. See
+    /// [`try_loop_`][CellLoop::try_loop_].
     pub fn loop_(&self, ca: &Cell<A>) {
         self.impl_.loop_(&ca.impl_);
     }
+
+    /// A variant of [`loop_`][CellLoop::loop_] that returns an error
+    /// rather than panicking if this `CellLoop` has already been looped
+    /// or `ca` belongs to another [`SodiumCtx`].
+    pub fn try_loop_(&self, ca: &Cell<A>) -> Result<(), LoopError> {
+        self.impl_.try_loop_(&ca.impl_)
+    }
 }
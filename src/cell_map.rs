@@ -0,0 +1,445 @@
+use crate::cell_vec::{CellVec, VecDiff};
+use crate::impl_::cell_collection::{CellCollection, Changes, Collection, Transactional};
+use crate::impl_::lambda::{lambda1, lambda1_deps, IsLambda1};
+use crate::impl_::name::{CallerLocation, NoCallerLocation};
+use crate::impl_::sodium_ctx::SodiumCtx;
+use crate::Cell;
+use crate::Dep;
+use crate::Stream;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+/// A change to the entries of a [`CellMap`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapDiff<K, V> {
+    /// Insert `value` under `key`, replacing any value already there.
+    Insert { key: K, value: V },
+    /// Remove the entry for `key`, if there is one.
+    Remove { key: K },
+    /// Replace all of the entries.
+    Replace { values: BTreeMap<K, V> },
+}
+
+impl<K: Ord + Clone + Send + 'static, V: Clone + Send + 'static> Collection for BTreeMap<K, V> {
+    type Diff = MapDiff<K, V>;
+
+    fn apply(&mut self, diff: MapDiff<K, V>) -> MapDiff<K, V> {
+        match diff {
+            MapDiff::Insert { key, value } => match self.insert(key.clone(), value) {
+                Some(value) => MapDiff::Insert { key, value },
+                None => MapDiff::Remove { key },
+            },
+            MapDiff::Remove { key } => match self.remove(&key) {
+                Some(value) => MapDiff::Insert { key, value },
+                None => MapDiff::Remove { key },
+            },
+            MapDiff::Replace { values } => MapDiff::Replace {
+                values: mem::replace(self, values),
+            },
+        }
+    }
+}
+
+/// A map from keys of type `K` to values of type `V` that changes over
+/// time, one [`MapDiff`] at a time.
+///
+/// This is the keyed counterpart of [`CellVec`]: it is changed in
+/// place, and its [`updates`][CellMap::updates] say which entries
+/// changed. The entries are kept in key order.
+pub struct CellMap<K, V>
+where
+    K: Ord + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    pub(crate) impl_: CellCollection<BTreeMap<K, V>>,
+}
+
+impl<K: Ord + Clone + Send + 'static, V: Clone + Send + 'static> Clone for CellMap<K, V> {
+    fn clone(&self) -> Self {
+        CellMap {
+            impl_: self.impl_.clone(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for CellMap<K, V>
+where
+    K: Ord + Clone + Send + fmt::Debug + 'static,
+    V: Clone + Send + fmt::Debug + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.impl_
+            .with_value(|values: &BTreeMap<K, V>| f.debug_tuple("CellMap").field(values).finish())
+    }
+}
+
+impl<K: Ord + Clone + Send + 'static, V: Clone + Send + 'static> CellMap<K, V> {
+    /// Create a `CellMap` that starts with `values` and is changed by
+    /// the diffs fired by `diffs`.
+    ///
+    /// As with [`Stream::hold`], the diffs fired in a transaction take
+    /// effect at its end.
+    #[track_caller]
+    pub fn hold(diffs: &Stream<Vec<MapDiff<K, V>>>, values: BTreeMap<K, V>) -> CellMap<K, V> {
+        let _caller = CallerLocation::enter();
+        CellMap {
+            impl_: CellCollection::hold(values, &diffs.impl_),
+        }
+    }
+
+    /// Create a `CellMap` that follows the value of `ca`.
+    ///
+    /// Each new map is compared with the one before it, and only the
+    /// entries that differ are inserted or removed.
+    #[track_caller]
+    pub fn from_cell(ca: &Cell<BTreeMap<K, V>>) -> CellMap<K, V>
+    where
+        V: PartialEq,
+    {
+        let _caller = CallerLocation::enter();
+        ca.impl_.sodium_ctx().transaction(|| {
+            let diffs = ca
+                .updates()
+                .snapshot(ca, |new: &BTreeMap<K, V>, old: &BTreeMap<K, V>| {
+                    map_diffs(old, new)
+                })
+                .filter(|diffs: &Vec<MapDiff<K, V>>| !diffs.is_empty());
+            CellMap::hold(&diffs, ca.sample())
+        })
+    }
+
+    /// Return a [`Cell`] of the whole map, which is copied on every
+    /// change.
+    #[track_caller]
+    pub fn to_cell(&self) -> Cell<BTreeMap<K, V>> {
+        let _caller = CallerLocation::enter();
+        self.sodium_ctx().transaction(|| {
+            self.updates().accum(
+                self.sample(),
+                |diffs: &Vec<MapDiff<K, V>>, values: &BTreeMap<K, V>| {
+                    let mut values = values.clone();
+                    for diff in diffs {
+                        values.apply(diff.clone());
+                    }
+                    values
+                },
+            )
+        })
+    }
+
+    /// A stream of the changes to this `CellMap`, with all of the diffs
+    /// of a transaction in one firing.
+    pub fn updates(&self) -> Stream<Vec<MapDiff<K, V>>> {
+        Stream {
+            impl_: self.impl_.updates(),
+        }
+    }
+
+    // use as dependency to lambda1, lambda2, etc.
+    #[doc(hidden)]
+    pub fn to_dep(&self) -> Dep {
+        self.impl_.to_dep()
+    }
+
+    /// Sample a copy of the entries.
+    ///
+    /// As with [`Cell::sample`], this gives the entries from before any
+    /// changes in the current transaction.
+    pub fn sample(&self) -> BTreeMap<K, V> {
+        self.impl_
+            .with_value(|values: &BTreeMap<K, V>| values.clone())
+    }
+
+    /// Call `f` with the entries, without copying them.
+    pub fn sample_with<R>(&self, f: impl FnOnce(&BTreeMap<K, V>) -> R) -> R {
+        // operators run their lambdas on the current values through here
+        // while they are being built
+        let _caller = NoCallerLocation::enter();
+        self.impl_.with_value(f)
+    }
+
+    /// A [`Cell`] of the number of entries, whose
+    /// [`updates`][Cell::updates] only fire when the number changes.
+    #[track_caller]
+    pub fn len(&self) -> Cell<usize> {
+        let _caller = CallerLocation::enter();
+        self.sodium_ctx().transaction(|| {
+            let len = self.sample_with(|values: &BTreeMap<K, V>| values.len());
+            // the entries from before the transaction, for telling
+            // whether a key was already there
+            let self_ = self.clone();
+            self.updates()
+                .map(lambda1(
+                    move |diffs: &Vec<MapDiff<K, V>>| {
+                        self_.sample_with(|values: &BTreeMap<K, V>| len_after(values, diffs))
+                    },
+                    vec![self.to_dep()],
+                ))
+                .hold_calm(len)
+        })
+    }
+
+    /// Transform each value with `f`, which is only called for the
+    /// values that are inserted.
+    #[track_caller]
+    pub fn map<W: Clone + Send + 'static, FN: IsLambda1<V, W> + Send + Sync + 'static>(
+        &self,
+        mut f: FN,
+    ) -> CellMap<K, W> {
+        let _caller = CallerLocation::enter();
+        self.sodium_ctx().transaction(|| {
+            let values: BTreeMap<K, W> = self.sample_with(|values: &BTreeMap<K, V>| {
+                values
+                    .iter()
+                    .map(|(key, value)| (key.clone(), f.call(value)))
+                    .collect()
+            });
+            let f_deps = lambda1_deps(&f);
+            let diffs = self.updates().map(lambda1(
+                move |diffs: &Vec<MapDiff<K, V>>| -> Vec<MapDiff<K, W>> {
+                    diffs
+                        .iter()
+                        .map(|diff| match diff {
+                            MapDiff::Insert { key, value } => MapDiff::Insert {
+                                key: key.clone(),
+                                value: f.call(value),
+                            },
+                            MapDiff::Remove { key } => MapDiff::Remove { key: key.clone() },
+                            MapDiff::Replace { values } => MapDiff::Replace {
+                                values: values
+                                    .iter()
+                                    .map(|(key, value)| (key.clone(), f.call(value)))
+                                    .collect(),
+                            },
+                        })
+                        .collect()
+                },
+                f_deps,
+            ));
+            CellMap::hold(&diffs, values)
+        })
+    }
+
+    /// Keep only the entries whose values `pred` returns `true` for.
+    #[track_caller]
+    pub fn filter<PRED: IsLambda1<V, bool> + Send + Sync + 'static>(
+        &self,
+        mut pred: PRED,
+    ) -> CellMap<K, V> {
+        let _caller = CallerLocation::enter();
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            let values: BTreeMap<K, V> = self.sample_with(|values: &BTreeMap<K, V>| {
+                values
+                    .iter()
+                    .filter(|(_, value)| pred.call(value))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            });
+            // the keys that are kept
+            let kept: BTreeMap<K, ()> = values.keys().map(|key| (key.clone(), ())).collect();
+            let pred_deps = lambda1_deps(&pred);
+            let state = Transactional::new(&sodium_ctx, kept);
+            let sodium_ctx = sodium_ctx.clone();
+            let diffs = self
+                .updates()
+                .map(lambda1(
+                    move |diffs: &Vec<MapDiff<K, V>>| -> Vec<MapDiff<K, V>> {
+                        state.update(&sodium_ctx, |changes: &mut Changes<BTreeMap<K, ()>>| {
+                            let mut out = Vec::new();
+                            for diff in diffs {
+                                filter_diff(changes, &mut pred, diff.clone(), &mut out);
+                            }
+                            out
+                        })
+                    },
+                    pred_deps,
+                ))
+                .filter(|diffs: &Vec<MapDiff<K, V>>| !diffs.is_empty());
+            CellMap::hold(&diffs, values)
+        })
+    }
+
+    /// The values in key order, as a [`CellVec`]. Use this to
+    /// [`sort_by`][CellVec::sort_by] or [`group_by`][CellVec::group_by]
+    /// the values of a map.
+    #[track_caller]
+    pub fn values(&self) -> CellVec<V> {
+        let _caller = CallerLocation::enter();
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            let (keys, values): (BTreeMap<K, ()>, Vec<V>) =
+                self.sample_with(|values: &BTreeMap<K, V>| {
+                    (
+                        values.keys().map(|key| (key.clone(), ())).collect(),
+                        values.values().cloned().collect(),
+                    )
+                });
+            let state = Transactional::new(&sodium_ctx, keys);
+            let sodium_ctx = sodium_ctx.clone();
+            let diffs = self
+                .updates()
+                .map(move |diffs: &Vec<MapDiff<K, V>>| {
+                    state.update(&sodium_ctx, |changes: &mut Changes<BTreeMap<K, ()>>| {
+                        let mut out = Vec::new();
+                        for diff in diffs {
+                            values_diff(changes, diff.clone(), &mut out);
+                        }
+                        out
+                    })
+                })
+                .filter(|diffs: &Vec<VecDiff<V>>| !diffs.is_empty());
+            CellVec::hold(&diffs, values)
+        })
+    }
+
+    fn sodium_ctx(&self) -> SodiumCtx {
+        self.impl_.sodium_ctx()
+    }
+}
+
+// The number of entries after `diffs` are applied to `values`.
+fn len_after<'a, K: Ord, V>(values: &'a BTreeMap<K, V>, diffs: &'a [MapDiff<K, V>]) -> usize {
+    let mut base = values;
+    // whether each key changed since `base` is in the map
+    let mut has_key: BTreeMap<&K, bool> = BTreeMap::new();
+    let mut len = base.len();
+    for diff in diffs {
+        match diff {
+            MapDiff::Insert { key, .. } => {
+                let had_key = has_key
+                    .insert(key, true)
+                    .unwrap_or_else(|| base.contains_key(key));
+                if !had_key {
+                    len += 1;
+                }
+            }
+            MapDiff::Remove { key } => {
+                let had_key = has_key
+                    .insert(key, false)
+                    .unwrap_or_else(|| base.contains_key(key));
+                if had_key {
+                    len -= 1;
+                }
+            }
+            MapDiff::Replace { values } => {
+                base = values;
+                has_key.clear();
+                len = values.len();
+            }
+        }
+    }
+    len
+}
+
+// The diffs that turn `old` into `new`.
+fn map_diffs<K: Ord + Clone, V: Clone + PartialEq>(
+    old: &BTreeMap<K, V>,
+    new: &BTreeMap<K, V>,
+) -> Vec<MapDiff<K, V>> {
+    let mut diffs: Vec<MapDiff<K, V>> = old
+        .keys()
+        .filter(|key| !new.contains_key(key))
+        .map(|key| MapDiff::Remove { key: key.clone() })
+        .collect();
+    for (key, value) in new {
+        if old.get(key) != Some(value) {
+            diffs.push(MapDiff::Insert {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    diffs
+}
+
+fn keys<K>(keys: &mut BTreeMap<K, ()>) -> &mut BTreeMap<K, ()> {
+    keys
+}
+
+fn filter_diff<K, V, PRED>(
+    changes: &mut Changes<BTreeMap<K, ()>>,
+    pred: &mut PRED,
+    diff: MapDiff<K, V>,
+    out: &mut Vec<MapDiff<K, V>>,
+) where
+    K: Ord + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+    PRED: IsLambda1<V, bool>,
+{
+    match diff {
+        MapDiff::Insert { key, value } => {
+            let was_kept = changes.state().contains_key(&key);
+            if pred.call(&value) {
+                changes.apply(
+                    keys,
+                    MapDiff::Insert {
+                        key: key.clone(),
+                        value: (),
+                    },
+                );
+                out.push(MapDiff::Insert { key, value });
+            } else if was_kept {
+                changes.apply(keys, MapDiff::Remove { key: key.clone() });
+                out.push(MapDiff::Remove { key });
+            }
+        }
+        MapDiff::Remove { key } => {
+            if changes.state().contains_key(&key) {
+                changes.apply(keys, MapDiff::Remove { key: key.clone() });
+                out.push(MapDiff::Remove { key });
+            }
+        }
+        MapDiff::Replace { values } => {
+            let values: BTreeMap<K, V> = values
+                .into_iter()
+                .filter(|(_, value)| pred.call(value))
+                .collect();
+            changes.apply(
+                keys,
+                MapDiff::Replace {
+                    values: values.keys().map(|key| (key.clone(), ())).collect(),
+                },
+            );
+            out.push(MapDiff::Replace { values });
+        }
+    }
+}
+
+fn values_diff<K: Ord + Clone + Send + 'static, V: Clone + Send + 'static>(
+    changes: &mut Changes<BTreeMap<K, ()>>,
+    diff: MapDiff<K, V>,
+    out: &mut Vec<VecDiff<V>>,
+) {
+    match diff {
+        MapDiff::Insert { key, value } => {
+            let index = changes.state().range(..&key).count();
+            if changes.state().contains_key(&key) {
+                out.push(VecDiff::Update { index, value });
+            } else {
+                changes.apply(keys, MapDiff::Insert { key, value: () });
+                out.push(VecDiff::Insert { index, value });
+            }
+        }
+        MapDiff::Remove { key } => {
+            if changes.state().contains_key(&key) {
+                let index = changes.state().range(..&key).count();
+                changes.apply(keys, MapDiff::Remove { key });
+                out.push(VecDiff::Remove { index });
+            }
+        }
+        MapDiff::Replace { values } => {
+            changes.apply(
+                keys,
+                MapDiff::Replace {
+                    values: values.keys().map(|key| (key.clone(), ())).collect(),
+                },
+            );
+            out.push(VecDiff::Replace {
+                values: values.into_values().collect(),
+            });
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::cell::Cell;
 use crate::impl_::cell_sink::CellSink as CellSinkImpl;
+use crate::impl_::name::CallerLocation;
 use crate::sodium_ctx::SodiumCtx;
 
 /// A [`Cell`] that allows values to be pushed into it, acting as a
@@ -21,7 +22,9 @@ impl<A> Clone for CellSink<A> {
 
 impl<A: Clone + Send + 'static> CellSink<A> {
     /// Create a new `CellSink` in the given context.
+    #[track_caller]
     pub fn new(sodium_ctx: &SodiumCtx, a: A) -> CellSink<A> {
+        let _caller = CallerLocation::enter();
         CellSink {
             impl_: CellSinkImpl::new(&sodium_ctx.impl_, a),
         }
@@ -39,12 +42,33 @@ impl<A: Clone + Send + 'static> CellSink<A> {
     /// Send a value, modifying the value of the cell.
     ///
     /// This method may not be called in handlers registered with
-    /// [`Stream::listen`][crate::Stream::listen] or [`Cell::listen`].
+    /// [`Stream::listen`][crate::Stream::listen] or [`Cell::listen`],
+    /// or in the lambdas passed to combinators. Use
+    /// [`send_later`][CellSink::send_later] there instead.
     ///
     /// `CellSink` is an operational primitive, meant for interfacing
     /// I/O to FRP only. You aren't meant to use this to define your
     /// own primitives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from a listener or lambda while the context is
+    /// updating the graph.
+    ///
+    /// Panics if a lambda or listener run for the new value panics. The
+    /// cell then keeps its old value; see
+    /// [`SodiumCtx::transaction`][crate::SodiumCtx::transaction].
     pub fn send(&self, a: A) {
         self.impl_.send(a);
     }
+
+    /// Set the value of the cell in a new transaction, once the current
+    /// transaction is over, or at once if there is no current
+    /// transaction.
+    ///
+    /// This may be called from listeners and lambdas. If it is called
+    /// more than once in a transaction, the last value wins.
+    pub fn send_later(&self, a: A) {
+        self.impl_.send_later(a);
+    }
 }
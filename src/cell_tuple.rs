@@ -0,0 +1,90 @@
+use crate::impl_::name::CallerLocation;
+use crate::Cell;
+use crate::Lazy;
+use crate::Stream;
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// A tuple of 1 to 12 cell references, such as `(&ca, &cb, &cc)`,
+/// whose values can be combined with [`Cell::lift_n`] or captured with
+/// [`Stream::snapshot_n`].
+pub trait CellTuple {
+    /// The tuple of the values of the cells.
+    type Values: Clone + Send + 'static;
+
+    /// Return a [`Cell`] of the tuple of the values of all the cells.
+    fn combine(&self) -> Cell<Self::Values>;
+}
+
+macro_rules! impl_cell_tuple {
+    ($($T:ident $i:tt),+) => {
+        impl<$($T: Clone + Send + 'static),+> CellTuple for ($(&Cell<$T>,)+) {
+            type Values = ($($T,)+);
+
+            #[track_caller]
+            fn combine(&self) -> Cell<Self::Values> {
+                let _caller = CallerLocation::enter();
+                let sodium_ctx = self.0.impl_.sodium_ctx();
+                sodium_ctx.transaction(|| {
+                    let values = ($(self.$i.sample_lazy(),)+);
+                    let init: Lazy<Self::Values>;
+                    {
+                        let values = values.clone();
+                        init = Lazy::new(move || ($(values.$i.run(),)+));
+                    }
+                    // Each cell stores its new value here until the end of
+                    // the transaction, and the result is read back once all
+                    // of them have. If the transaction is rolled back the
+                    // new values are dropped.
+                    let state = Arc::new(Mutex::new((values, ($(None::<$T>,)+))));
+                    let updates: Vec<Stream<()>> = vec![$({
+                        let state = state.clone();
+                        let sodium_ctx = sodium_ctx.clone();
+                        self.$i.updates().map(move |a: &$T| {
+                            state.lock().1.$i = Some(a.clone());
+                            {
+                                let state = state.clone();
+                                sodium_ctx.on_rollback(move || {
+                                    state.lock().1.$i = None;
+                                });
+                            }
+                            let state = state.clone();
+                            sodium_ctx.post(move || {
+                                let mut state = state.lock();
+                                if let Some(a) = state.1.$i.take() {
+                                    state.0.$i = Lazy::of_value(a);
+                                }
+                            });
+                        })
+                    }),+];
+                    let mut s = updates[0].clone();
+                    for update in &updates[1..] {
+                        s = s.or_else(update);
+                    }
+                    s.map(move |_: &()| {
+                        let state = state.lock();
+                        ($(match state.1.$i {
+                            Some(ref a) => a.clone(),
+                            None => state.0.$i.run(),
+                        },)+)
+                    })
+                    .hold_lazy(init)
+                })
+            }
+        }
+    };
+}
+
+impl_cell_tuple!(A 0);
+impl_cell_tuple!(A 0, B 1);
+impl_cell_tuple!(A 0, B 1, C 2);
+impl_cell_tuple!(A 0, B 1, C 2, D 3);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_cell_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
@@ -0,0 +1,829 @@
+use crate::cell_map::{CellMap, MapDiff};
+use crate::impl_::cell_collection::{
+    CellCollection, CellCollectionForwardRef, Changes, Collection, Transactional,
+};
+use crate::impl_::lambda::{lambda1, lambda1_deps, lambda2_deps, IsLambda1, IsLambda2};
+use crate::impl_::name::{CallerLocation, NoCallerLocation};
+use crate::impl_::sodium_ctx::SodiumCtx;
+use crate::Cell;
+use crate::Dep;
+use crate::Stream;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+
+/// A change to the values of a [`CellVec`].
+///
+/// Indexes are those of the values as they are just before the change
+/// is applied. A diff with an index out of range panics when applied,
+/// as the matching `Vec` method would.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VecDiff<A> {
+    /// Insert `value` at `index`, shifting the values after it up.
+    Insert { index: usize, value: A },
+    /// Remove the value at `index`, shifting the values after it down.
+    Remove { index: usize },
+    /// Replace the value at `index` with `value`.
+    Update { index: usize, value: A },
+    /// Remove the value at `from`, then insert it again at `to`.
+    Move { from: usize, to: usize },
+    /// Replace all of the values.
+    Replace { values: Vec<A> },
+}
+
+impl<A: Clone + Send + 'static> Collection for Vec<A> {
+    type Diff = VecDiff<A>;
+
+    fn apply(&mut self, diff: VecDiff<A>) -> VecDiff<A> {
+        match diff {
+            VecDiff::Insert { index, value } => {
+                self.insert(index, value);
+                VecDiff::Remove { index }
+            }
+            VecDiff::Remove { index } => VecDiff::Insert {
+                index,
+                value: self.remove(index),
+            },
+            VecDiff::Update { index, value } => VecDiff::Update {
+                index,
+                value: mem::replace(&mut self[index], value),
+            },
+            VecDiff::Move { from, to } => {
+                let value = self.remove(from);
+                self.insert(to, value);
+                VecDiff::Move { from: to, to: from }
+            }
+            VecDiff::Replace { values } => VecDiff::Replace {
+                values: mem::replace(self, values),
+            },
+        }
+    }
+}
+
+/// A list of values of type `A` that changes over time, one
+/// [`VecDiff`] at a time.
+///
+/// Where a `Cell<Vec<A>>` gives a whole new vector for every change,
+/// a `CellVec` is changed in place, and its [`updates`][CellVec::updates]
+/// say what changed. The operators on it ([`map`][CellVec::map],
+/// [`filter`][CellVec::filter], [`sort_by`][CellVec::sort_by],
+/// [`group_by`][CellVec::group_by] and [`len`][CellVec::len]) only call
+/// their functions for the values that changed. Keeping track of
+/// positions can still take time in the length of the list, such as
+/// when `filter` or `sort_by` move the values after a change.
+pub struct CellVec<A: Clone + Send + 'static> {
+    impl_: CellCollection<Vec<A>>,
+}
+
+impl<A: Clone + Send + 'static> Clone for CellVec<A> {
+    fn clone(&self) -> Self {
+        CellVec {
+            impl_: self.impl_.clone(),
+        }
+    }
+}
+
+impl<A: Clone + Send + fmt::Debug + 'static> fmt::Debug for CellVec<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.impl_
+            .with_value(|values: &Vec<A>| f.debug_tuple("CellVec").field(values).finish())
+    }
+}
+
+impl<A: Clone + Send + 'static> CellVec<A> {
+    /// Create a `CellVec` that starts with `values` and is changed by
+    /// the diffs fired by `diffs`.
+    ///
+    /// As with [`Stream::hold`], the diffs fired in a transaction take
+    /// effect at its end.
+    #[track_caller]
+    pub fn hold(diffs: &Stream<Vec<VecDiff<A>>>, values: Vec<A>) -> CellVec<A> {
+        let _caller = CallerLocation::enter();
+        CellVec {
+            impl_: CellCollection::hold(values, &diffs.impl_),
+        }
+    }
+
+    /// Create a `CellVec` that follows the value of `ca`.
+    ///
+    /// Each new vector is compared with the one before it, and the
+    /// values from the first to the last that differ are replaced.
+    #[track_caller]
+    pub fn from_cell(ca: &Cell<Vec<A>>) -> CellVec<A>
+    where
+        A: PartialEq,
+    {
+        let _caller = CallerLocation::enter();
+        ca.impl_.sodium_ctx().transaction(|| {
+            let diffs = ca
+                .updates()
+                .snapshot(ca, |new: &Vec<A>, old: &Vec<A>| vec_diffs(old, new))
+                .filter(|diffs: &Vec<VecDiff<A>>| !diffs.is_empty());
+            CellVec::hold(&diffs, ca.sample())
+        })
+    }
+
+    /// Return a [`Cell`] of the whole vector, which is copied on every
+    /// change.
+    #[track_caller]
+    pub fn to_cell(&self) -> Cell<Vec<A>> {
+        let _caller = CallerLocation::enter();
+        self.sodium_ctx().transaction(|| {
+            self.updates()
+                .accum(self.sample(), |diffs: &Vec<VecDiff<A>>, values: &Vec<A>| {
+                    let mut values = values.clone();
+                    for diff in diffs {
+                        values.apply(diff.clone());
+                    }
+                    values
+                })
+        })
+    }
+
+    /// A stream of the changes to this `CellVec`, with all of the diffs
+    /// of a transaction in one firing.
+    pub fn updates(&self) -> Stream<Vec<VecDiff<A>>> {
+        Stream {
+            impl_: self.impl_.updates(),
+        }
+    }
+
+    // use as dependency to lambda1, lambda2, etc.
+    #[doc(hidden)]
+    pub fn to_dep(&self) -> Dep {
+        self.impl_.to_dep()
+    }
+
+    /// Sample a copy of the values.
+    ///
+    /// As with [`Cell::sample`], this gives the values from before any
+    /// changes in the current transaction.
+    pub fn sample(&self) -> Vec<A> {
+        self.impl_.with_value(|values: &Vec<A>| values.clone())
+    }
+
+    /// Call `f` with the values, without copying them.
+    pub fn sample_with<R>(&self, f: impl FnOnce(&[A]) -> R) -> R {
+        // operators run their lambdas on the current values through here
+        // while they are being built
+        let _caller = NoCallerLocation::enter();
+        self.impl_.with_value(|values: &Vec<A>| f(values))
+    }
+
+    /// A [`Cell`] of the number of values, whose
+    /// [`updates`][Cell::updates] only fire when the number changes.
+    #[track_caller]
+    pub fn len(&self) -> Cell<usize> {
+        let _caller = CallerLocation::enter();
+        self.sodium_ctx().transaction(|| {
+            let len = self.sample_with(|values: &[A]| values.len());
+            self.updates()
+                .accum(len, |diffs: &Vec<VecDiff<A>>, len: &usize| {
+                    diffs.iter().fold(*len, |len, diff| match diff {
+                        VecDiff::Insert { .. } => len + 1,
+                        VecDiff::Remove { .. } => len - 1,
+                        VecDiff::Update { .. } | VecDiff::Move { .. } => len,
+                        VecDiff::Replace { values } => values.len(),
+                    })
+                })
+                .calm()
+        })
+    }
+
+    /// Transform each value with `f`, which is only called for the
+    /// values that are inserted or updated.
+    #[track_caller]
+    pub fn map<B: Clone + Send + 'static, FN: IsLambda1<A, B> + Send + Sync + 'static>(
+        &self,
+        mut f: FN,
+    ) -> CellVec<B> {
+        let _caller = CallerLocation::enter();
+        self.sodium_ctx().transaction(|| {
+            let values: Vec<B> = self
+                .sample_with(|values: &[A]| values.iter().map(|value: &A| f.call(value)).collect());
+            let f_deps = lambda1_deps(&f);
+            let diffs = self.updates().map(lambda1(
+                move |diffs: &Vec<VecDiff<A>>| -> Vec<VecDiff<B>> {
+                    diffs
+                        .iter()
+                        .map(|diff| match diff {
+                            VecDiff::Insert { index, value } => VecDiff::Insert {
+                                index: *index,
+                                value: f.call(value),
+                            },
+                            VecDiff::Remove { index } => VecDiff::Remove { index: *index },
+                            VecDiff::Update { index, value } => VecDiff::Update {
+                                index: *index,
+                                value: f.call(value),
+                            },
+                            VecDiff::Move { from, to } => VecDiff::Move {
+                                from: *from,
+                                to: *to,
+                            },
+                            VecDiff::Replace { values } => VecDiff::Replace {
+                                values: values.iter().map(|value: &A| f.call(value)).collect(),
+                            },
+                        })
+                        .collect()
+                },
+                f_deps,
+            ));
+            CellVec::hold(&diffs, values)
+        })
+    }
+
+    /// Keep only the values for which `pred` returns `true`, in their
+    /// order in this `CellVec`.
+    #[track_caller]
+    pub fn filter<PRED: IsLambda1<A, bool> + Send + Sync + 'static>(
+        &self,
+        mut pred: PRED,
+    ) -> CellVec<A> {
+        let _caller = CallerLocation::enter();
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            // whether each value of this CellVec is kept
+            let kept: Vec<bool> = self.sample_with(|values: &[A]| {
+                values.iter().map(|value: &A| pred.call(value)).collect()
+            });
+            let values: Vec<A> = self.sample_with(|values: &[A]| {
+                values
+                    .iter()
+                    .zip(&kept)
+                    .filter(|(_, kept)| **kept)
+                    .map(|(value, _)| value.clone())
+                    .collect()
+            });
+            let pred_deps = lambda1_deps(&pred);
+            let state = Transactional::new(&sodium_ctx, kept);
+            let sodium_ctx = sodium_ctx.clone();
+            let diffs = self
+                .updates()
+                .map(lambda1(
+                    move |diffs: &Vec<VecDiff<A>>| -> Vec<VecDiff<A>> {
+                        state.update(&sodium_ctx, |changes: &mut Changes<Vec<bool>>| {
+                            let mut out = Vec::new();
+                            for diff in diffs {
+                                filter_diff(changes, &mut pred, diff.clone(), &mut out);
+                            }
+                            out
+                        })
+                    },
+                    pred_deps,
+                ))
+                .filter(|diffs: &Vec<VecDiff<A>>| !diffs.is_empty());
+            CellVec::hold(&diffs, values)
+        })
+    }
+
+    /// Sort the values with `cmp`.
+    ///
+    /// The sort is stable: values that compare equal keep the order
+    /// they were added in.
+    #[track_caller]
+    pub fn sort_by<CMP: IsLambda2<A, A, Ordering> + Send + Sync + 'static>(
+        &self,
+        mut cmp: CMP,
+    ) -> CellVec<A> {
+        let _caller = CallerLocation::enter();
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            let (values, positions) =
+                self.sample_with(|values: &[A]| sort_values(values, &mut cmp));
+            let cmp_deps = lambda2_deps(&cmp);
+            // where each value of this CellVec is among the sorted values
+            let state = Transactional::new(&sodium_ctx, positions);
+            // the sorted values are only kept by the result
+            let sorted = CellCollectionForwardRef::new();
+            let sorted_diffs;
+            {
+                let sorted = sorted.clone();
+                let sodium_ctx = sodium_ctx.clone();
+                sorted_diffs = self.updates().map(lambda1(
+                    move |diffs: &Vec<VecDiff<A>>| -> Vec<VecDiff<A>> {
+                        state.update(&sodium_ctx, |changes: &mut Changes<Vec<usize>>| {
+                            sorted.with_value(|sorted: &Vec<A>| {
+                                let mut out = Vec::new();
+                                for diff in diffs {
+                                    sort_diff(changes, &mut cmp, sorted, diff.clone(), &mut out);
+                                }
+                                out
+                            })
+                        })
+                    },
+                    cmp_deps,
+                ));
+            }
+            let diffs = sorted_diffs.filter(|diffs: &Vec<VecDiff<A>>| !diffs.is_empty());
+            let result = CellVec::hold(&diffs, values);
+            sorted.assign(&result.impl_, &sorted_diffs.impl_);
+            result
+        })
+    }
+
+    /// Group the values by the key `key` gives them, as a [`CellMap`]
+    /// from each key to its values, in their order in this `CellVec`.
+    ///
+    /// A key is in the map while it has at least one value. Only the
+    /// groups that change are copied.
+    #[track_caller]
+    pub fn group_by<K, KEY>(&self, mut key: KEY) -> CellMap<K, Vec<A>>
+    where
+        K: Ord + Clone + Send + 'static,
+        KEY: IsLambda1<A, K> + Send + Sync + 'static,
+    {
+        let _caller = CallerLocation::enter();
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            let (keys, groups) = self.sample_with(|values: &[A]| grouped(values, &mut key));
+            let key_deps = lambda1_deps(&key);
+            // the key of each value of this CellVec
+            let state = Transactional::new(&sodium_ctx, keys);
+            // the groups are only kept by the result
+            let groups_ref = CellCollectionForwardRef::new();
+            let group_diffs;
+            {
+                let groups_ref = groups_ref.clone();
+                let sodium_ctx = sodium_ctx.clone();
+                group_diffs = self.updates().map(lambda1(
+                    move |diffs: &Vec<VecDiff<A>>| -> Vec<MapDiff<K, Vec<A>>> {
+                        state.update(&sodium_ctx, |changes: &mut Changes<Vec<K>>| {
+                            let mut groups = GroupChanges::new(&groups_ref);
+                            for diff in diffs {
+                                group_diff(changes, &mut key, diff.clone(), &mut groups);
+                            }
+                            groups.into_diffs()
+                        })
+                    },
+                    key_deps,
+                ));
+            }
+            let diffs = group_diffs.filter(|diffs: &Vec<MapDiff<K, Vec<A>>>| !diffs.is_empty());
+            let result = CellMap::hold(&diffs, groups);
+            groups_ref.assign(&result.impl_, &group_diffs.impl_);
+            result
+        })
+    }
+
+    fn sodium_ctx(&self) -> SodiumCtx {
+        self.impl_.sodium_ctx()
+    }
+}
+
+// The diffs that turn `old` into `new`: the values between the common
+// prefix and suffix are updated, then the extra ones removed or inserted.
+fn vec_diffs<A: Clone + PartialEq>(old: &[A], new: &[A]) -> Vec<VecDiff<A>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+    let mut diffs = Vec::new();
+    for (i, (a, b)) in old.iter().zip(new).enumerate() {
+        if a != b {
+            diffs.push(VecDiff::Update {
+                index: prefix + i,
+                value: b.clone(),
+            });
+        }
+    }
+    let common = old.len().min(new.len());
+    for _ in common..old.len() {
+        diffs.push(VecDiff::Remove {
+            index: prefix + common,
+        });
+    }
+    for (i, value) in new[common..].iter().enumerate() {
+        diffs.push(VecDiff::Insert {
+            index: prefix + common + i,
+            value: value.clone(),
+        });
+    }
+    diffs
+}
+
+fn kept_before(kept: &[bool], index: usize) -> usize {
+    kept[..index].iter().filter(|kept| **kept).count()
+}
+
+fn filter_diff<A: Clone + Send + 'static, PRED: IsLambda1<A, bool>>(
+    changes: &mut Changes<Vec<bool>>,
+    pred: &mut PRED,
+    diff: VecDiff<A>,
+    out: &mut Vec<VecDiff<A>>,
+) {
+    fn kept(kept: &mut Vec<bool>) -> &mut Vec<bool> {
+        kept
+    }
+    match diff {
+        VecDiff::Insert { index, value } => {
+            let is_kept = pred.call(&value);
+            changes.apply(
+                kept,
+                VecDiff::Insert {
+                    index,
+                    value: is_kept,
+                },
+            );
+            if is_kept {
+                out.push(VecDiff::Insert {
+                    index: kept_before(changes.state(), index),
+                    value,
+                });
+            }
+        }
+        VecDiff::Remove { index } => {
+            let was_kept = changes.state()[index];
+            let out_index = kept_before(changes.state(), index);
+            changes.apply(kept, VecDiff::Remove { index });
+            if was_kept {
+                out.push(VecDiff::Remove { index: out_index });
+            }
+        }
+        VecDiff::Update { index, value } => {
+            let was_kept = changes.state()[index];
+            let is_kept = pred.call(&value);
+            let out_index = kept_before(changes.state(), index);
+            changes.apply(
+                kept,
+                VecDiff::Update {
+                    index,
+                    value: is_kept,
+                },
+            );
+            match (was_kept, is_kept) {
+                (true, true) => out.push(VecDiff::Update {
+                    index: out_index,
+                    value,
+                }),
+                (true, false) => out.push(VecDiff::Remove { index: out_index }),
+                (false, true) => out.push(VecDiff::Insert {
+                    index: out_index,
+                    value,
+                }),
+                (false, false) => (),
+            }
+        }
+        VecDiff::Move { from, to } => {
+            let was_kept = changes.state()[from];
+            let out_from = kept_before(changes.state(), from);
+            changes.apply(kept, VecDiff::Move { from, to });
+            if was_kept {
+                let out_to = kept_before(changes.state(), to);
+                if out_from != out_to {
+                    out.push(VecDiff::Move {
+                        from: out_from,
+                        to: out_to,
+                    });
+                }
+            }
+        }
+        VecDiff::Replace { values } => {
+            let is_kept: Vec<bool> = values.iter().map(|value: &A| pred.call(value)).collect();
+            let values = values
+                .into_iter()
+                .zip(&is_kept)
+                .filter(|(_, is_kept)| **is_kept)
+                .map(|(value, _)| value)
+                .collect();
+            changes.apply(kept, VecDiff::Replace { values: is_kept });
+            out.push(VecDiff::Replace { values });
+        }
+    }
+}
+
+// The values sorted, and where each of them is among the sorted values.
+fn sort_values<A: Clone, CMP: IsLambda2<A, A, Ordering>>(
+    values: &[A],
+    cmp: &mut CMP,
+) -> (Vec<A>, Vec<usize>) {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a: &usize, b: &usize| cmp.call(&values[*a], &values[*b]));
+    let mut positions = vec![0; values.len()];
+    for (position, index) in order.iter().enumerate() {
+        positions[*index] = position;
+    }
+    (
+        order.iter().map(|index| values[*index].clone()).collect(),
+        positions,
+    )
+}
+
+fn shift_positions(positions: &mut [usize], from: usize, except: Option<usize>, up: bool) {
+    for (index, position) in positions.iter_mut().enumerate() {
+        if *position >= from && Some(index) != except {
+            if up {
+                *position += 1;
+            } else {
+                *position -= 1;
+            }
+        }
+    }
+}
+
+// The value at `position` once `diffs` are applied to `values`, found
+// without applying them.
+fn value_at<'a, A>(values: &'a [A], diffs: &'a [VecDiff<A>], mut position: usize) -> &'a A {
+    for diff in diffs.iter().rev() {
+        match diff {
+            VecDiff::Insert { index, value } => {
+                if position == *index {
+                    return value;
+                }
+                if position > *index {
+                    position -= 1;
+                }
+            }
+            VecDiff::Remove { index } => {
+                if position >= *index {
+                    position += 1;
+                }
+            }
+            VecDiff::Update { index, value } => {
+                if position == *index {
+                    return value;
+                }
+            }
+            VecDiff::Move { from, to } => {
+                if position == *to {
+                    position = *from;
+                } else {
+                    if position > *to {
+                        position -= 1;
+                    }
+                    if position >= *from {
+                        position += 1;
+                    }
+                }
+            }
+            VecDiff::Replace { values } => return &values[position],
+        }
+    }
+    &values[position]
+}
+
+// Applies `diff` to the positions, giving the diffs to the sorted values
+// in `out`. `sorted` are the sorted values from before the transaction,
+// which the diffs already in `out` apply to.
+fn sort_diff<A: Clone + Send + 'static, CMP: IsLambda2<A, A, Ordering>>(
+    changes: &mut Changes<Vec<usize>>,
+    cmp: &mut CMP,
+    sorted: &[A],
+    diff: VecDiff<A>,
+    out: &mut Vec<VecDiff<A>>,
+) {
+    fn positions(positions: &mut Vec<usize>) -> &mut Vec<usize> {
+        positions
+    }
+    // moves the positions at or above `from` up or down by one, except
+    // the position of the source value at `except`, with a single undo
+    fn shift(changes: &mut Changes<Vec<usize>>, from: usize, except: Option<usize>, up: bool) {
+        // The position a shift moves towards is free, so after it the
+        // shifted positions are exactly those at or above `undo_from`.
+        let undo_from = if up { from + 1 } else { from - 1 };
+        changes.change(
+            move |positions: &mut Vec<usize>| shift_positions(positions, from, except, up),
+            move |positions: &mut Vec<usize>| shift_positions(positions, undo_from, except, !up),
+        );
+    }
+    // the position after the first `len` sorted values that compare less
+    // than or equal to `value`
+    fn insertion_point<A, CMP: IsLambda2<A, A, Ordering>>(
+        sorted: &[A],
+        out: &[VecDiff<A>],
+        len: usize,
+        cmp: &mut CMP,
+        value: &A,
+    ) -> usize {
+        let (mut low, mut high) = (0, len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if cmp.call(value_at(sorted, out, middle), value) != Ordering::Greater {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+    match diff {
+        VecDiff::Insert { index, value } => {
+            let len = changes.state().len();
+            let position = insertion_point(sorted, out, len, cmp, &value);
+            shift(changes, position, None, true);
+            changes.apply(
+                positions,
+                VecDiff::Insert {
+                    index,
+                    value: position,
+                },
+            );
+            out.push(VecDiff::Insert {
+                index: position,
+                value,
+            });
+        }
+        VecDiff::Remove { index } => {
+            let position = changes.state()[index];
+            changes.apply(positions, VecDiff::Remove { index });
+            shift(changes, position + 1, None, false);
+            out.push(VecDiff::Remove { index: position });
+        }
+        VecDiff::Update { index, value } => {
+            let old_position = changes.state()[index];
+            let len = changes.state().len();
+            shift(changes, old_position + 1, Some(index), false);
+            // found among the sorted values without the old one
+            out.push(VecDiff::Remove {
+                index: old_position,
+            });
+            let position = insertion_point(sorted, out, len - 1, cmp, &value);
+            out.pop();
+            shift(changes, position, Some(index), true);
+            changes.apply(
+                positions,
+                VecDiff::Update {
+                    index,
+                    value: position,
+                },
+            );
+            if position != old_position {
+                out.push(VecDiff::Move {
+                    from: old_position,
+                    to: position,
+                });
+            }
+            out.push(VecDiff::Update {
+                index: position,
+                value,
+            });
+        }
+        VecDiff::Move { from, to } => {
+            changes.apply(positions, VecDiff::Move { from, to });
+        }
+        VecDiff::Replace { values } => {
+            let (values, new_positions) = sort_values(&values, cmp);
+            changes.apply(
+                positions,
+                VecDiff::Replace {
+                    values: new_positions,
+                },
+            );
+            out.push(VecDiff::Replace { values });
+        }
+    }
+}
+
+// The key of each value, and the groups.
+fn grouped<A: Clone, K: Ord + Clone, KEY: IsLambda1<A, K>>(
+    values: &[A],
+    key: &mut KEY,
+) -> (Vec<K>, BTreeMap<K, Vec<A>>) {
+    let keys: Vec<K> = values.iter().map(|value: &A| key.call(value)).collect();
+    let mut groups: BTreeMap<K, Vec<A>> = BTreeMap::new();
+    for (key, value) in keys.iter().zip(values) {
+        groups.entry(key.clone()).or_default().push(value.clone());
+    }
+    (keys, groups)
+}
+
+// The groups changed by the diffs of a transaction, over the groups from
+// before it. Only the groups that change are copied.
+struct GroupChanges<'a, K: Ord + Clone + Send + 'static, A: Clone + Send + 'static> {
+    groups: &'a CellCollectionForwardRef<BTreeMap<K, Vec<A>>>,
+    // all of the groups, once a diff replaces them
+    replaced_op: Option<BTreeMap<K, Vec<A>>>,
+    // empty for a group that is removed
+    changed: BTreeMap<K, Vec<A>>,
+}
+
+impl<'a, K: Ord + Clone + Send + 'static, A: Clone + Send + 'static> GroupChanges<'a, K, A> {
+    fn new(groups: &'a CellCollectionForwardRef<BTreeMap<K, Vec<A>>>) -> GroupChanges<'a, K, A> {
+        GroupChanges {
+            groups,
+            replaced_op: None,
+            changed: BTreeMap::new(),
+        }
+    }
+
+    fn group(&mut self, key: &K) -> &mut Vec<A> {
+        if !self.changed.contains_key(key) {
+            let group = match self.replaced_op {
+                Some(ref groups) => groups.get(key).cloned(),
+                None => self
+                    .groups
+                    .with_value(|groups: &BTreeMap<K, Vec<A>>| groups.get(key).cloned()),
+            };
+            self.changed.insert(key.clone(), group.unwrap_or_default());
+        }
+        self.changed.get_mut(key).unwrap()
+    }
+
+    fn replace(&mut self, groups: BTreeMap<K, Vec<A>>) {
+        self.replaced_op = Some(groups);
+        self.changed.clear();
+    }
+
+    fn into_diffs(self) -> Vec<MapDiff<K, Vec<A>>> {
+        match self.replaced_op {
+            Some(mut groups) => {
+                for (key, group) in self.changed {
+                    if group.is_empty() {
+                        groups.remove(&key);
+                    } else {
+                        groups.insert(key, group);
+                    }
+                }
+                vec![MapDiff::Replace { values: groups }]
+            }
+            None => self
+                .changed
+                .into_iter()
+                .map(|(key, group)| {
+                    if group.is_empty() {
+                        MapDiff::Remove { key }
+                    } else {
+                        MapDiff::Insert { key, value: group }
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// Applies `diff` to the keys and the groups.
+fn group_diff<A, K, KEY>(
+    changes: &mut Changes<Vec<K>>,
+    key: &mut KEY,
+    diff: VecDiff<A>,
+    groups: &mut GroupChanges<K, A>,
+) where
+    A: Clone + Send + 'static,
+    K: Ord + Clone + Send + 'static,
+    KEY: IsLambda1<A, K>,
+{
+    fn keys<K>(keys: &mut Vec<K>) -> &mut Vec<K> {
+        keys
+    }
+    // where the value at `index` of the source is in the group of `key`
+    fn position_in_group<K: PartialEq>(keys: &[K], key: &K, index: usize) -> usize {
+        keys[..index].iter().filter(|k| *k == key).count()
+    }
+    fn insert<A, K, KEY>(
+        changes: &mut Changes<Vec<K>>,
+        key: &mut KEY,
+        index: usize,
+        value: A,
+        groups: &mut GroupChanges<K, A>,
+    ) where
+        A: Clone + Send + 'static,
+        K: Ord + Clone + Send + 'static,
+        KEY: IsLambda1<A, K>,
+    {
+        let k = key.call(&value);
+        let position = position_in_group(changes.state(), &k, index);
+        changes.apply(
+            keys,
+            VecDiff::Insert {
+                index,
+                value: k.clone(),
+            },
+        );
+        groups.group(&k).insert(position, value);
+    }
+    fn remove<A: Clone + Send + 'static, K: Ord + Clone + Send + 'static>(
+        changes: &mut Changes<Vec<K>>,
+        index: usize,
+        groups: &mut GroupChanges<K, A>,
+    ) -> A {
+        let k = changes.state()[index].clone();
+        let position = position_in_group(changes.state(), &k, index);
+        changes.apply(keys, VecDiff::Remove { index });
+        groups.group(&k).remove(position)
+    }
+    match diff {
+        VecDiff::Insert { index, value } => insert(changes, key, index, value, groups),
+        VecDiff::Remove { index } => {
+            remove(changes, index, groups);
+        }
+        VecDiff::Update { index, value } => {
+            remove(changes, index, groups);
+            insert(changes, key, index, value, groups);
+        }
+        VecDiff::Move { from, to } => {
+            let value = remove(changes, from, groups);
+            insert(changes, key, to, value, groups);
+        }
+        VecDiff::Replace { values } => {
+            let (new_keys, new_groups) = grouped(&values, key);
+            changes.apply(keys, VecDiff::Replace { values: new_keys });
+            groups.replace(new_groups);
+        }
+    }
+}
@@ -20,6 +20,7 @@ use crate::impl_::stream::WeakStream;
 
 use parking_lot::Mutex;
 use parking_lot::RwLock;
+use std::fmt;
 use std::mem;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -92,6 +93,11 @@ pub struct CellData<A> {
     next_value_op: Option<A>,
 }
 
+struct LiftAllState<A> {
+    lazies_op: Option<Vec<Lazy<A>>>,
+    values: Vec<A>,
+}
+
 impl<A> Cell<A> {
     pub fn with_data<R, K: FnOnce(&mut CellData<A>) -> R>(&self, k: K) -> R {
         let data: &mut CellData<A> = &mut self.data.lock();
@@ -159,6 +165,14 @@ impl<A: Send + 'static> Cell<A> {
                                 is_first
                             });
                             if is_first {
+                                {
+                                    let c = c.clone();
+                                    sodium_ctx.on_rollback(move || {
+                                        c.with_data(|data: &mut CellData<A>| {
+                                            data.next_value_op = None;
+                                        })
+                                    });
+                                }
                                 sodium_ctx.post(move || {
                                     c.with_data(|data: &mut CellData<A>| {
                                         let mut next_value_op: Option<A> = None;
@@ -210,6 +224,18 @@ impl<A: Send + 'static> Cell<A> {
         self.with_data(|data: &mut CellData<A>| data.value.run())
     }
 
+    pub fn debug_values(&self)
+    where
+        A: Clone + fmt::Debug,
+    {
+        let data = Arc::downgrade(&self.data);
+        *self.node().data.debug_value.write() = Some(Box::new(move || {
+            let data = data.upgrade()?;
+            let value = data.lock().value.clone();
+            Some(format!("{:?}", value.run()))
+        }));
+    }
+
     pub fn sample_lazy(&self) -> Lazy<A> {
         self.with_data(|data: &mut CellData<A>| data.value.clone())
     }
@@ -242,13 +268,12 @@ impl<A: Send + 'static> Cell<A> {
         })
     }
 
-    pub fn map<B: Send + 'static, FN: IsLambda1<A, B> + Send + Sync + 'static>(
+    pub fn map<B: Send + Clone + 'static, FN: IsLambda1<A, B> + Send + Sync + 'static>(
         &self,
         f: FN,
     ) -> Cell<B>
     where
         A: Clone,
-        B: Clone,
     {
         let self_ = self.clone();
         let f_deps = lambda1_deps(&f);
@@ -448,6 +473,101 @@ impl<A: Send + 'static> Cell<A> {
         )
     }
 
+    pub fn lift_all<B: Send + Clone + 'static, FN: IsLambda1<Vec<A>, B> + Send + Sync + 'static>(
+        sodium_ctx: &SodiumCtx,
+        cells: &[Cell<A>],
+        f: FN,
+    ) -> Cell<B>
+    where
+        A: Clone,
+    {
+        let sodium_ctx = sodium_ctx.clone();
+        sodium_ctx.transaction(|| {
+            let lazies: Vec<Lazy<A>> = cells.iter().map(|c| c.sample_lazy()).collect();
+            let f_deps = lambda1_deps(&f);
+            let f = Arc::new(Mutex::new(f));
+            let init: Lazy<B>;
+            {
+                let lazies = lazies.clone();
+                let f = f.clone();
+                init = Lazy::new(move || {
+                    let values: Vec<A> = lazies.iter().map(|a| a.run()).collect();
+                    let mut f = f.lock();
+                    f.call(&values)
+                });
+            }
+            // The values are only sampled when the first of the cells
+            // changes, and after that only the slots of the cells that
+            // changed are written, in place so f is given the values
+            // without copying the rest. The old values of those slots
+            // are kept until the end of the transaction, to put back if
+            // it is rolled back.
+            let state = Arc::new(Mutex::new(LiftAllState {
+                lazies_op: Some(lazies),
+                values: Vec::new(),
+            }));
+            let updates: Vec<Stream<A>> = cells.iter().map(|c| c.updates()).collect();
+            let update_nodes: Vec<Box<dyn IsNode + Send + Sync>> =
+                updates.iter().map(|update| update.box_clone()).collect();
+            let update_deps: Vec<Dep> = updates.iter().map(|update| update.to_dep()).collect();
+            let s = Stream::_new(&sodium_ctx, |s: StreamWeakForwardRef<B>| {
+                let sodium_ctx2 = sodium_ctx.clone();
+                let node = Node::new(
+                    &sodium_ctx,
+                    NodeName::CELL_LIFT_ALL,
+                    move || {
+                        let sodium_ctx = &sodium_ctx2;
+                        let b = {
+                            let mut state_ = state.lock();
+                            let state_ = &mut *state_;
+                            if let Some(lazies) = state_.lazies_op.take() {
+                                state_.values = lazies.iter().map(|a| a.run()).collect();
+                            }
+                            let mut old_values: Vec<(usize, A)> = Vec::new();
+                            for (i, update) in updates.iter().enumerate() {
+                                update.with_firing_op(|firing_op: &mut Option<A>| {
+                                    if let Some(ref firing) = firing_op {
+                                        let old_value =
+                                            mem::replace(&mut state_.values[i], firing.clone());
+                                        old_values.push((i, old_value));
+                                    }
+                                });
+                            }
+                            if old_values.is_empty() {
+                                return;
+                            }
+                            {
+                                let state = state.clone();
+                                sodium_ctx.on_rollback(move || {
+                                    let mut state = state.lock();
+                                    for (i, old_value) in mem::take(&mut old_values) {
+                                        state.values[i] = old_value;
+                                    }
+                                });
+                            }
+                            let mut f = f.lock();
+                            f.call(&state_.values)
+                        };
+                        s.unwrap()._send(b);
+                    },
+                    update_nodes,
+                );
+                node.add_update_dependencies(f_deps);
+                node.add_update_dependencies(update_deps);
+                node
+            });
+            Cell::_new(&sodium_ctx, s, init)
+        })
+    }
+
+    pub fn calm_by<EQ: IsLambda2<A, A, bool> + Send + Sync + 'static>(&self, eq: EQ) -> Cell<A>
+    where
+        A: Clone,
+    {
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| self.updates().hold_calm_by(self.sample_lazy(), eq))
+    }
+
     pub fn switch_s(csa: &Cell<Stream<A>>) -> Stream<A>
     where
         A: Clone,
@@ -557,7 +677,6 @@ impl<A: Send + 'static> Cell<A> {
             }
             let node1_update;
             {
-                let sodium_ctx = sodium_ctx.clone();
                 let node1 = node1.clone();
                 let node2 = node2.clone();
                 let cca = cca.clone();
@@ -568,7 +687,6 @@ impl<A: Send + 'static> Cell<A> {
                         .with_firing_op(|firing_op: &mut Option<Cell<A>>| {
                             if let Some(ref firing) = firing_op {
                                 // will be overwriten by node2 firing if there is one
-                                sodium_ctx.update_node(firing.updates().node());
                                 let sa = sa.unwrap();
                                 sa._send(firing.sample());
                                 node1.data.changed.store(true, Ordering::SeqCst);
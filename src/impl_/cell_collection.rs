@@ -0,0 +1,254 @@
+use crate::impl_::dep::Dep;
+use crate::impl_::node::IsNodeExt;
+use crate::impl_::sodium_ctx::SodiumCtx;
+use crate::impl_::stream::Stream;
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+// A collection changed in place by diffs. Applying a diff returns the
+// diff that undoes it.
+pub trait Collection: Send + 'static {
+    type Diff: Clone + Send + 'static;
+
+    fn apply(&mut self, diff: Self::Diff) -> Self::Diff;
+}
+
+// The value behind a CellVec or CellMap. As with a held cell, the diffs
+// fired in a transaction are applied at its end, so sampling during the
+// transaction sees the value from before it. Unlike a held cell, they
+// are applied in place rather than replacing the value.
+pub struct CellCollection<C: Collection> {
+    data: Arc<Mutex<CellCollectionData<C>>>,
+    // records the diffs of each transaction; kept for as long as the
+    // collection is
+    hold: Stream<()>,
+}
+
+struct CellCollectionData<C: Collection> {
+    value: C,
+    next_diffs_op: Option<Vec<C::Diff>>,
+    // shared by the clones, so that the hold node can account for it
+    updates: Stream<Vec<C::Diff>>,
+}
+
+impl<C: Collection> Clone for CellCollection<C> {
+    fn clone(&self) -> Self {
+        CellCollection {
+            data: self.data.clone(),
+            hold: self.hold.clone(),
+        }
+    }
+}
+
+impl<C: Collection> CellCollection<C> {
+    pub fn hold(value: C, updates: &Stream<Vec<C::Diff>>) -> CellCollection<C> {
+        let sodium_ctx = updates.sodium_ctx();
+        sodium_ctx.clone().transaction(|| {
+            let data = Arc::new(Mutex::new(CellCollectionData {
+                value,
+                next_diffs_op: None,
+                updates: updates.clone(),
+            }));
+            let hold;
+            {
+                let data = data.clone();
+                hold = updates.map(move |diffs: &Vec<C::Diff>| {
+                    // The node can update twice in the transaction it is
+                    // made in, so the diffs are set rather than appended.
+                    let is_first = {
+                        let mut data = data.lock();
+                        let is_first = data.next_diffs_op.is_none();
+                        data.next_diffs_op = Some(diffs.clone());
+                        is_first
+                    };
+                    if is_first {
+                        {
+                            let data = data.clone();
+                            sodium_ctx.on_rollback(move || {
+                                data.lock().next_diffs_op = None;
+                            });
+                        }
+                        let data = data.clone();
+                        sodium_ctx.post(move || {
+                            let mut data = data.lock();
+                            if let Some(diffs) = data.next_diffs_op.take() {
+                                for diff in diffs {
+                                    data.value.apply(diff);
+                                }
+                            }
+                        });
+                    }
+                });
+            }
+            // As with a held cell, the updates are depended on twice, as
+            // the data keeps them too.
+            hold.node().add_update_dependencies(vec![updates.to_dep()]);
+            CellCollection { data, hold }
+        })
+    }
+
+    pub fn sodium_ctx(&self) -> SodiumCtx {
+        self.hold.sodium_ctx()
+    }
+
+    pub fn updates(&self) -> Stream<Vec<C::Diff>> {
+        self.data.lock().updates.clone()
+    }
+
+    // The hold node refers to everything the collection does, so it
+    // stands for the whole collection.
+    pub fn to_dep(&self) -> Dep {
+        self.hold.to_dep()
+    }
+
+    pub fn with_value<R>(&self, f: impl FnOnce(&C) -> R) -> R {
+        f(&self.data.lock().value)
+    }
+}
+
+// Lets the node that makes a collection's diffs read the collection,
+// which can only be made after the node.
+pub struct CellCollectionForwardRef<C: Collection> {
+    data: Arc<Mutex<Option<CellCollection<C>>>>,
+}
+
+impl<C: Collection> Clone for CellCollectionForwardRef<C> {
+    fn clone(&self) -> Self {
+        CellCollectionForwardRef {
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<C: Collection> CellCollectionForwardRef<C> {
+    pub fn new() -> CellCollectionForwardRef<C> {
+        CellCollectionForwardRef {
+            data: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // `s` is the stream whose node holds on to this reference.
+    pub fn assign<A>(&self, collection: &CellCollection<C>, s: &Stream<A>) {
+        s.node().add_update_dependencies(vec![collection.to_dep()]);
+        *self.data.lock() = Some(collection.clone());
+    }
+
+    pub fn with_value<R>(&self, f: impl FnOnce(&C) -> R) -> R {
+        let collection = self.data.lock().clone().unwrap();
+        collection.with_value(f)
+    }
+}
+
+type Undo<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+// The state of a CellVec or CellMap operator, changed as its node
+// updates. The changes made in a transaction are undone if it is rolled
+// back. A new node can update twice in one transaction, once from the
+// pre_eot that Stream::_new queues for it, so until then the result of
+// the first update is kept and given again.
+pub struct Transactional<S, B> {
+    data: Arc<Mutex<TransactionalData<S, B>>>,
+}
+
+struct TransactionalData<S, B> {
+    state: S,
+    undo: Vec<Undo<S>>,
+    is_new: bool,
+    result_op: Option<B>,
+}
+
+impl<S, B> Clone for Transactional<S, B> {
+    fn clone(&self) -> Self {
+        Transactional {
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<S: Send + 'static, B: Clone + Send + 'static> Transactional<S, B> {
+    pub fn new(sodium_ctx: &SodiumCtx, state: S) -> Transactional<S, B> {
+        let data = Arc::new(Mutex::new(TransactionalData {
+            state,
+            undo: Vec::new(),
+            is_new: true,
+            result_op: None,
+        }));
+        // queued along with the node's own pre_eot, so this runs in the
+        // same transaction as it, even for a node made by a lambda
+        {
+            let data = data.clone();
+            let sodium_ctx2 = sodium_ctx.clone();
+            sodium_ctx.pre_eot(move || {
+                let data = data.clone();
+                sodium_ctx2.post(move || {
+                    data.lock().is_new = false;
+                });
+            });
+        }
+        Transactional { data }
+    }
+
+    pub fn update(&self, sodium_ctx: &SodiumCtx, f: impl FnOnce(&mut Changes<S>) -> B) -> B {
+        let mut data = self.data.lock();
+        if let Some(ref result) = data.result_op {
+            return result.clone();
+        }
+        // registered before f runs, so a panic in f still undoes what
+        // it had changed
+        {
+            let data = self.data.clone();
+            sodium_ctx.on_rollback(move || {
+                let mut data = data.lock();
+                let data = &mut *data;
+                while let Some(undo) = data.undo.pop() {
+                    undo(&mut data.state);
+                }
+                data.result_op = None;
+            });
+        }
+        {
+            let data = self.data.clone();
+            sodium_ctx.post(move || {
+                let mut data = data.lock();
+                data.undo.clear();
+                data.result_op = None;
+            });
+        }
+        let data = &mut *data;
+        let result = f(&mut Changes {
+            state: &mut data.state,
+            undo: &mut data.undo,
+        });
+        if data.is_new {
+            data.result_op = Some(result.clone());
+        }
+        result
+    }
+}
+
+pub struct Changes<'a, S> {
+    state: &'a mut S,
+    undo: &'a mut Vec<Undo<S>>,
+}
+
+impl<S: 'static> Changes<'_, S> {
+    pub fn state(&self) -> &S {
+        self.state
+    }
+
+    // Apply a diff to the collection `field` picks out of the state.
+    pub fn apply<C: Collection>(&mut self, field: fn(&mut S) -> &mut C, diff: C::Diff) {
+        let inverse = field(self.state).apply(diff);
+        self.undo.push(Box::new(move |state: &mut S| {
+            field(state).apply(inverse);
+        }));
+    }
+
+    // Make a change to the state that isn't a diff, given the change that
+    // undoes it.
+    pub fn change(&mut self, f: impl FnOnce(&mut S), undo: impl FnOnce(&mut S) + Send + 'static) {
+        f(self.state);
+        self.undo.push(Box::new(undo));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::impl_::cell::Cell;
 use crate::impl_::lazy::Lazy;
+use crate::impl_::name::NodeName;
 use crate::impl_::sodium_ctx::SodiumCtx;
-use crate::impl_::stream_loop::StreamLoop;
+use crate::impl_::stream_loop::{LoopError, StreamLoop};
 
 use parking_lot::Mutex;
 use std::mem;
@@ -39,7 +40,7 @@ impl<A: Send + Clone + 'static> CellLoop<A> {
                 panic!("CellLoop sampled before looped.");
             });
         }
-        let stream_loop = StreamLoop::new(sodium_ctx);
+        let stream_loop = StreamLoop::new_with_name(sodium_ctx, NodeName::CELL_LOOP_NEW);
         let stream = stream_loop.stream();
         CellLoop {
             init_value_op,
@@ -53,8 +54,15 @@ impl<A: Send + Clone + 'static> CellLoop<A> {
     }
 
     pub fn loop_(&self, ca: &Cell<A>) {
-        self.stream_loop.loop_(&ca.updates());
+        if let Err(err) = self.try_loop_(ca) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_loop_(&self, ca: &Cell<A>) -> Result<(), LoopError> {
+        self.stream_loop.try_loop_(&ca.updates())?;
         let mut init_value_op = self.init_value_op.lock();
         *init_value_op = Some(ca.sample_lazy());
+        Ok(())
     }
 }
@@ -32,4 +32,8 @@ impl<A: Send + Clone + 'static> CellSink<A> {
     pub fn send(&self, a: A) {
         self.stream_sink.send(a);
     }
+
+    pub fn send_later(&self, a: A) {
+        self.stream_sink.send_later(a);
+    }
 }
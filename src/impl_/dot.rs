@@ -0,0 +1,105 @@
+use crate::impl_::gc_node::GcNode;
+use crate::impl_::sodium_ctx::SodiumCtx;
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+// Edges point from each node to what it refers to: solid for the
+// dependencies a node holds on to, dotted for the weak references to its
+// dependents, dashed for keep alives and the dependencies of lambdas that
+// are not dependencies of the node already.
+pub fn export_dot(sodium_ctx: &SodiumCtx) -> String {
+    let nodes = sodium_ctx.live_nodes();
+    let listeners = sodium_ctx.live_listeners();
+    let mut out = String::new();
+    out.push_str("digraph sodium {\n");
+    out.push_str("    node [shape=box];\n");
+    for (gc_node, _) in &nodes {
+        writeln!(
+            out,
+            "    n{} [label=\"{}\"];",
+            gc_node.id(),
+            label(gc_node, &gc_node.name().to_string())
+        )
+        .ok();
+    }
+    for (gc_node, data) in &listeners {
+        let kind = if data.lock().is_weak {
+            "weak listener"
+        } else {
+            "listener"
+        };
+        writeln!(
+            out,
+            "    n{} [label=\"{}\", shape=ellipse];",
+            gc_node.id(),
+            label(gc_node, kind)
+        )
+        .ok();
+    }
+    for (gc_node, data) in &nodes {
+        let id = gc_node.id();
+        let mut dependency_ids = HashSet::new();
+        for dependency in &*data.dependencies.read() {
+            dependency_ids.insert(dependency.gc_node().id());
+            writeln!(out, "    n{} -> n{};", id, dependency.gc_node().id()).ok();
+        }
+        for dependent in &*data.dependents.read() {
+            if dependent.data().strong_count() > 0 {
+                writeln!(
+                    out,
+                    "    n{} -> n{} [style=dotted];",
+                    id,
+                    dependent.gc_node().id()
+                )
+                .ok();
+            }
+        }
+        for gc_node in &*data.keep_alive.read() {
+            writeln!(
+                out,
+                "    n{} -> n{} [style=dashed, label=\"keep alive\"];",
+                id,
+                gc_node.id()
+            )
+            .ok();
+        }
+        for dep in &*data.update_dependencies.read() {
+            if dependency_ids.contains(&dep.gc_node().id()) {
+                continue;
+            }
+            writeln!(
+                out,
+                "    n{} -> n{} [style=dashed, label=\"lambda\"];",
+                id,
+                dep.gc_node().id()
+            )
+            .ok();
+        }
+    }
+    for (gc_node, data) in &listeners {
+        if let Some(node) = &data.lock().node_op {
+            writeln!(out, "    n{} -> n{};", gc_node.id(), node.gc_node.id()).ok();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// The kind of node, then its label and where it was made if known, then
+// its id, each on a line of its own.
+fn label(gc_node: &GcNode, kind: &str) -> String {
+    let mut lines = vec![escape(kind)];
+    if let Some(label) = gc_node.label() {
+        lines.push(escape(&format!("{:?}", label)));
+    }
+    if let Some(location) = gc_node.location() {
+        lines.push(escape(&location.to_string()));
+    }
+    lines.push(format!("#{}", gc_node.id()));
+    lines.join("\\n")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -1,23 +1,67 @@
-#![allow(clippy::only_used_in_recursion)]
-
 use parking_lot::Mutex;
 use parking_lot::RwLock;
 use std::cell::Cell;
+use std::cell::RefCell;
 use std::collections::HashSet;
+use std::fmt;
 use std::fmt::Write as _;
+use std::panic::Location;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Weak;
+use std::time::Instant;
 
-use log::trace;
+use log::{log_enabled, trace, Level};
 
-use crate::impl_::name::NodeName;
+use crate::impl_::name::{CallerLocation, NodeName};
 
 pub type Tracer<'a> = dyn FnMut(&GcNode) + 'a;
 
 pub type Trace = dyn Fn(&mut Tracer) + Send + Sync;
 
+type Deferred = Vec<Box<dyn FnOnce()>>;
+
+thread_local! {
+    static DEFERRED: RefCell<Option<Deferred>> = const { RefCell::new(None) };
+}
+
+// Runs k now, unless this thread is already inside a call to defer, in
+// which case k is queued and run by the outermost call when it is done.
+// Freeing a node drops its dependencies, which can free those in turn, so
+// this keeps freeing a long chain of nodes from growing the stack.
+pub fn defer<K: FnOnce() + 'static>(k: K) {
+    let mut k_op = Some(k);
+    DEFERRED.with(|deferred| {
+        let mut deferred = deferred.borrow_mut();
+        if let Some(deferred) = &mut *deferred {
+            deferred.push(Box::new(k_op.take().unwrap()));
+        } else {
+            *deferred = Some(Vec::new());
+        }
+    });
+    if let Some(k) = k_op {
+        struct Running;
+        impl Drop for Running {
+            fn drop(&mut self) {
+                let deferred = DEFERRED.with(|deferred| deferred.borrow_mut().take());
+                drop(deferred);
+            }
+        }
+        let _running = Running;
+        k();
+        while let Some(k) = DEFERRED.with(|deferred| {
+            deferred
+                .borrow_mut()
+                .as_mut()
+                .and_then(|deferred| deferred.pop())
+        }) {
+            k();
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 enum Color {
     Black,
@@ -44,6 +88,33 @@ impl Clone for GcNode {
     }
 }
 
+impl fmt::Display for GcNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.id, self.description())
+    }
+}
+
+// Does not keep the node's data alive, nor what its deconstructor holds on
+// to.
+#[derive(Clone)]
+pub struct WeakGcNode {
+    id: u32,
+    name: NodeName,
+    gc_ctx: GcCtx,
+    data: Weak<GcNodeData>,
+}
+
+impl WeakGcNode {
+    pub fn upgrade(&self) -> Option<GcNode> {
+        self.data.upgrade().map(|data| GcNode {
+            id: self.id,
+            name: self.name,
+            gc_ctx: self.gc_ctx.clone(),
+            data,
+        })
+    }
+}
+
 struct GcNodeData {
     freed: AtomicBool,
     ref_count: AtomicU32,
@@ -53,6 +124,8 @@ struct GcNodeData {
     buffered: AtomicBool,
     deconstructor: RwLock<Box<dyn Fn() + Send + Sync>>,
     trace: RwLock<Box<Trace>>,
+    location: Option<&'static Location<'static>>,
+    label: RwLock<Option<Arc<str>>>,
 }
 
 unsafe impl Send for GcNodeData {}
@@ -66,9 +139,17 @@ pub struct GcCtx {
 struct GcCtxData {
     next_id: u32,
     roots: Vec<GcNode>,
+    // Roots left over by a collection that ran out of budget.
+    pending: Vec<GcNode>,
     to_be_freed: Vec<GcNode>,
 }
 
+#[derive(Default)]
+pub struct GcStats {
+    pub roots_scanned: u64,
+    pub nodes_freed: u64,
+}
+
 impl Default for GcCtx {
     fn default() -> GcCtx {
         GcCtx::new()
@@ -81,6 +162,7 @@ impl GcCtx {
             data: Arc::new(Mutex::new(GcCtxData {
                 next_id: 0,
                 roots: Vec::new(),
+                pending: Vec::new(),
                 to_be_freed: Vec::new(),
             })),
         }
@@ -103,27 +185,61 @@ impl GcCtx {
         self.with_data(|data: &mut GcCtxData| data.roots.push(node));
     }
 
-    pub fn collect_cycles(&self) {
+    pub fn roots_buffered(&self) -> usize {
+        self.with_data(|data: &mut GcCtxData| data.roots.len() + data.pending.len())
+    }
+
+    pub fn collect_cycles(&self) -> GcStats {
+        self.collect_cycles_with_budget(usize::MAX, None)
+    }
+
+    // Works through the buffered roots in batches, oldest first, stopping
+    // once max_roots of them have been looked at or the deadline has
+    // passed. Whatever is left stays buffered for next time. Looking at
+    // only some of the roots at a time is safe, it just finds the cycles
+    // through the rest later.
+    pub fn collect_cycles_with_budget(
+        &self,
+        max_roots: usize,
+        deadline_op: Option<Instant>,
+    ) -> GcStats {
+        let mut stats = GcStats::default();
         loop {
-            trace!("start: collect_cycles");
-            self.mark_roots();
-            self.scan_roots();
-            self.collect_roots();
-            trace!("end: collect_cycles");
+            let batch_size = max_roots.saturating_sub(stats.roots_scanned as usize);
             let bail = self.with_data(|data: &mut GcCtxData| {
+                data.pending.append(&mut data.roots);
+                let batch_size = batch_size.min(data.pending.len());
+                data.roots = data.pending.drain(..batch_size).collect();
                 data.roots.is_empty() && data.to_be_freed.is_empty()
             });
             if bail {
                 break;
             }
+            trace!("start: collect_cycles");
+            stats.roots_scanned += self.mark_roots();
+            self.scan_roots();
+            stats.nodes_freed += self.collect_roots();
+            trace!("end: collect_cycles");
+            let out_of_budget = stats.roots_scanned as usize >= max_roots
+                || deadline_op.is_some_and(|deadline| Instant::now() >= deadline);
+            if out_of_budget {
+                break;
+            }
         }
+        self.with_data(|data: &mut GcCtxData| {
+            let mut roots = std::mem::take(&mut data.roots);
+            data.pending.append(&mut roots);
+        });
+        stats
     }
 
-    fn mark_roots(&self) {
+    // Returns the number of roots looked at.
+    fn mark_roots(&self) -> u64 {
         trace!("start: mark_roots");
         let mut old_roots: Vec<GcNode> = Vec::new();
         self.with_data(|data: &mut GcCtxData| std::mem::swap(&mut old_roots, &mut data.roots));
         self.display_graph(&old_roots);
+        let roots_scanned = old_roots.len() as u64;
         let mut new_roots: Vec<GcNode> = Vec::new();
         for root in &old_roots {
             self.reset_ref_count_adj_step_1_of_2(root);
@@ -148,9 +264,13 @@ impl GcCtx {
         }
         self.with_data(|data: &mut GcCtxData| std::mem::swap(&mut new_roots, &mut data.roots));
         trace!("end: mark_roots");
+        roots_scanned
     }
 
     fn display_graph(&self, roots: &[GcNode]) {
+        if !log_enabled!(Level::Trace) {
+            return;
+        }
         let mut stack = Vec::new();
         let mut visited: HashSet<*const GcNodeData> = HashSet::new();
         let mut show_names_for = Vec::new();
@@ -192,25 +312,28 @@ impl GcCtx {
         }
         trace!("node names:");
         for next in show_names_for {
-            trace!("{}: {}", next.id, next.name);
+            trace!("{}", next);
         }
         trace!("-- end of graph drawing --");
     }
 
     fn mark_gray(&self, s: &GcNode) {
-        if s.data.color.get() == Color::Gray {
-            return;
-        }
-        s.data.color.set(Color::Gray);
-
-        s.trace(&mut |t: &GcNode| {
-            trace!("mark_gray: gc node {} dec ref count", t.id);
-            let ref_count_adj = t.data.ref_count_adj.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| Some(x + 1)).unwrap();
-            if ref_count_adj > t.data.ref_count.load(Ordering::SeqCst) {
-                panic!("ref count adj was larger than ref count for node {} ({}) (ref adj {}) (ref cnt {})", t.id, t.name, t.data.ref_count_adj.load(Ordering::SeqCst), t.data.ref_count.load(Ordering::SeqCst));
+        let mut stack = vec![s.clone()];
+        while let Some(s) = stack.pop() {
+            if s.data.color.get() == Color::Gray {
+                continue;
             }
-            self.mark_gray(t);
-        });
+            s.data.color.set(Color::Gray);
+
+            s.trace(|t: &GcNode| {
+                trace!("mark_gray: gc node {} dec ref count", t.id);
+                let ref_count_adj = t.data.ref_count_adj.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| Some(x + 1)).unwrap();
+                if ref_count_adj > t.data.ref_count.load(Ordering::SeqCst) {
+                    panic!("ref count adj was larger than ref count for node {} (ref adj {}) (ref cnt {})", t, t.data.ref_count_adj.load(Ordering::SeqCst), t.data.ref_count.load(Ordering::SeqCst));
+                }
+                stack.push(t.clone());
+            });
+        }
     }
 
     fn scan_roots(&self) {
@@ -231,17 +354,20 @@ impl GcCtx {
     }
 
     fn scan(&self, s: &GcNode) {
-        if s.data.color.get() != Color::Gray {
-            return;
-        }
-        if s.data.ref_count_adj.load(Ordering::SeqCst) == s.data.ref_count.load(Ordering::SeqCst) {
-            s.data.color.set(Color::White);
-            trace!("scan: gc node {} became white", s.id);
-            s.trace(|t| {
-                self.scan(t);
-            });
-        } else {
-            self.scan_black(s);
+        let mut stack = vec![s.clone()];
+        while let Some(s) = stack.pop() {
+            if s.data.color.get() != Color::Gray {
+                continue;
+            }
+            if s.data.ref_count_adj.load(Ordering::SeqCst)
+                == s.data.ref_count.load(Ordering::SeqCst)
+            {
+                s.data.color.set(Color::White);
+                trace!("scan: gc node {} became white", s.id);
+                stack.extend(s.children().into_iter().rev());
+            } else {
+                self.scan_black(&s);
+            }
         }
     }
 
@@ -250,10 +376,16 @@ impl GcCtx {
             return;
         }
         s.data.visited.store(true, Ordering::SeqCst);
-        s.data.ref_count_adj.store(0, Ordering::SeqCst);
-        s.trace(|t| {
-            self.reset_ref_count_adj_step_1_of_2(t);
-        });
+        let mut stack = vec![s.clone()];
+        while let Some(s) = stack.pop() {
+            s.data.ref_count_adj.store(0, Ordering::SeqCst);
+            s.trace(|t| {
+                if !t.data.visited.load(Ordering::SeqCst) {
+                    t.data.visited.store(true, Ordering::SeqCst);
+                    stack.push(t.clone());
+                }
+            });
+        }
     }
 
     fn reset_ref_count_adj_step_2_of_2(&self, s: &GcNode) {
@@ -261,22 +393,35 @@ impl GcCtx {
             return;
         }
         s.data.visited.store(false, Ordering::SeqCst);
-        s.trace(|t| {
-            self.reset_ref_count_adj_step_2_of_2(t);
-        });
+        let mut stack = vec![s.clone()];
+        while let Some(s) = stack.pop() {
+            s.trace(|t| {
+                if t.data.visited.load(Ordering::SeqCst) {
+                    t.data.visited.store(false, Ordering::SeqCst);
+                    stack.push(t.clone());
+                }
+            });
+        }
     }
 
     fn scan_black(&self, s: &GcNode) {
         s.data.color.set(Color::Black);
         trace!("scan: gc node {} became black", s.id);
-        s.trace(|t| {
-            if t.data.color.get() != Color::Black {
-                self.scan_black(t);
-            }
-        });
+        let mut stack = vec![s.clone()];
+        while let Some(s) = stack.pop() {
+            s.trace(|t| {
+                if t.data.color.get() != Color::Black {
+                    t.data.color.set(Color::Black);
+                    trace!("scan: gc node {} became black", t.id);
+                    stack.push(t.clone());
+                }
+            });
+        }
     }
 
-    fn collect_roots(&self) {
+    // Returns the number of nodes freed.
+    fn collect_roots(&self) -> u64 {
+        let mut nodes_freed = 0;
         let mut white = Vec::new();
         let mut roots = Vec::new();
         self.with_data(|data: &mut GcCtxData| roots.append(&mut data.roots));
@@ -286,10 +431,12 @@ impl GcCtx {
         }
         for i in &white {
             if !i.data.freed.load(Ordering::SeqCst) {
-                trace!("collect_roots: freeing white node {} ({})", i.id, i.name);
+                trace!("collect_roots: freeing white node {}", i);
                 i.free();
+                nodes_freed += 1;
                 self.with_data(|data: &mut GcCtxData| {
-                    data.roots.retain(|root: &GcNode| root.id != i.id)
+                    data.roots.retain(|root: &GcNode| root.id != i.id);
+                    data.pending.retain(|root: &GcNode| root.id != i.id);
                 });
             }
         }
@@ -297,50 +444,58 @@ impl GcCtx {
         self.with_data(|data: &mut GcCtxData| to_be_freed.append(&mut data.to_be_freed));
         for i in &to_be_freed {
             if !i.data.freed.load(Ordering::SeqCst) {
-                trace!(
-                    "collect_roots: freeing to_be_freed node {} ({})",
-                    i.id,
-                    i.name
-                );
+                trace!("collect_roots: freeing to_be_freed node {}", i);
                 i.free();
+                nodes_freed += 1;
                 self.with_data(|data: &mut GcCtxData| {
-                    data.roots.retain(|root: &GcNode| root.id != i.id)
+                    data.roots.retain(|root: &GcNode| root.id != i.id);
+                    data.pending.retain(|root: &GcNode| root.id != i.id);
                 });
             }
         }
         for i in white {
             if i.ref_count() != 0 {
-                panic!(
-                    "freed node ref count did not drop to zero for node {} ({})",
-                    i.id, i.name
-                );
+                panic!("freed node ref count did not drop to zero for node {}", i);
             }
         }
         for i in to_be_freed {
             if i.ref_count() != 0 {
-                panic!(
-                    "freed node ref count did not drop to zero for node {} ({})",
-                    i.id, i.name
-                );
+                panic!("freed node ref count did not drop to zero for node {}", i);
             }
         }
+        nodes_freed
     }
 
+    // Walks in the same order the recursive version of the algorithm would,
+    // children before parents, as the order nodes are freed in matters.
     fn collect_white(&self, s: &GcNode, white: &mut Vec<GcNode>) {
-        if s.data.color.get() == Color::White
-        /*&& !s.data.buffered.get()*/
-        {
-            s.data.color.set(Color::Black);
-            s.trace(|t| {
-                self.collect_white(t, white);
-            });
-            trace!("collect_white: gc node {} added to white list", s.id);
-            white.push(s.clone());
+        let mut stack = vec![(s.clone(), false)];
+        while let Some((s, children_done)) = stack.pop() {
+            if children_done {
+                trace!("collect_white: gc node {} added to white list", s.id);
+                white.push(s);
+                continue;
+            }
+            if s.data.color.get() == Color::White
+            /*&& !s.data.buffered.get()*/
+            {
+                s.data.color.set(Color::Black);
+                stack.push((s.clone(), true));
+                stack.extend(s.children().into_iter().rev().map(|t| (t, false)));
+            }
         }
     }
 }
 
 impl GcNode {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> NodeName {
+        self.name
+    }
+
     pub fn new<
         DECONSTRUCTOR: 'static + Fn() + Send + Sync,
         TRACE: 'static + Fn(&mut Tracer) + Send + Sync,
@@ -363,10 +518,46 @@ impl GcNode {
                 buffered: AtomicBool::new(false),
                 deconstructor: RwLock::new(Box::new(deconstructor)),
                 trace: RwLock::new(Box::new(trace)),
+                location: CallerLocation::current(),
+                label: RwLock::new(None),
             }),
         }
     }
 
+    pub fn location(&self) -> Option<&'static Location<'static>> {
+        self.data.location
+    }
+
+    pub fn label(&self) -> Option<Arc<str>> {
+        self.data.label.read().clone()
+    }
+
+    pub fn set_label(&self, label: &str) {
+        *self.data.label.write() = Some(Arc::from(label));
+    }
+
+    pub fn downgrade(&self) -> WeakGcNode {
+        WeakGcNode {
+            id: self.id,
+            name: self.name,
+            gc_ctx: self.gc_ctx.clone(),
+            data: Arc::downgrade(&self.data),
+        }
+    }
+
+    // What kind of node this is, with its label and where it was made if
+    // they are known.
+    pub fn description(&self) -> String {
+        let mut description = self.name.to_string();
+        if let Some(label) = self.label() {
+            write!(description, " {:?}", label).ok();
+        }
+        if let Some(location) = self.location() {
+            write!(description, " at {}", location).ok();
+        }
+        description
+    }
+
     pub fn ref_count(&self) -> u32 {
         self.data.ref_count.load(Ordering::SeqCst)
     }
@@ -383,7 +574,7 @@ impl GcNode {
 
     pub fn inc_ref(&self) {
         if self.data.freed.load(Ordering::SeqCst) {
-            panic!("gc_node {} inc_ref on freed node ({})", self.id, self.name);
+            panic!("gc_node inc_ref on freed node {}", self);
         }
         self.data
             .ref_count
@@ -411,7 +602,7 @@ impl GcNode {
     pub fn release(&self) {
         self.data.color.set(Color::Black);
         if !self.data.buffered.load(Ordering::SeqCst) {
-            trace!("release: freeing gc_node {} ({})", self.id, self.name);
+            trace!("release: freeing gc_node {}", self);
             self.free();
         }
     }
@@ -428,14 +619,23 @@ impl GcNode {
 
     pub fn free(&self) {
         self.data.freed.store(true, Ordering::SeqCst);
-        let mut tmp: Box<dyn Fn() + Send + Sync + 'static> = Box::new(|| {});
-        {
-            let mut deconstructor = self.data.deconstructor.write();
-            std::mem::swap(&mut *deconstructor, &mut tmp);
-        }
-        tmp();
-        let mut trace = self.data.trace.write();
-        *trace = Box::new(|_tracer: &mut Tracer| {});
+        let this = self.clone();
+        defer(move || {
+            let mut tmp: Box<dyn Fn() + Send + Sync + 'static> = Box::new(|| {});
+            {
+                let mut deconstructor = this.data.deconstructor.write();
+                std::mem::swap(&mut *deconstructor, &mut tmp);
+            }
+            tmp();
+            let mut trace = this.data.trace.write();
+            *trace = Box::new(|_tracer: &mut Tracer| {});
+        });
+    }
+
+    fn children(&self) -> Vec<GcNode> {
+        let mut children = Vec::new();
+        self.trace(|t| children.push(t.clone()));
+        children
     }
 
     pub fn trace<TRACER: FnMut(&GcNode)>(&self, mut tracer: TRACER) {
@@ -1,3 +1,5 @@
+use crate::impl_::name::NoCallerLocation;
+
 use parking_lot::Mutex;
 use std::sync::Arc;
 
@@ -45,6 +47,10 @@ impl<A: Send + Clone + 'static> Lazy<A> {
         let result: A;
         match &mut *data {
             LazyData::Thunk(ref mut k) => {
+                // A thunk can be forced by a call that isn't its own, such
+                // as switch_s sampling its cell, so nodes the thunk makes
+                // mustn't get that call's location.
+                let _caller = NoCallerLocation::enter();
                 result = k();
                 next_op = Some(LazyData::Value(result.clone()));
             }
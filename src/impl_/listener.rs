@@ -78,6 +78,7 @@ impl Listener {
                 gc_node_trace,
             ),
         };
+        sodium_ctx.register_listener(&listener);
         if !is_weak {
             sodium_ctx.with_data(|data: &mut SodiumCtxData| {
                 data.keep_alive.push(listener.clone());
@@ -113,6 +114,12 @@ impl Listener {
     }
 }
 
+impl Drop for ListenerData {
+    fn drop(&mut self) {
+        self.sodium_ctx.unregister_listener(self);
+    }
+}
+
 impl fmt::Debug for Listener {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let node_op = self.node_op();
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::thread::{self, ThreadId};
+
+/// A value confined to the thread that created it, so that values
+/// that aren't `Send` can be carried through the graph of a context
+/// that only ever runs on that thread.
+///
+/// Every access checks that it is made from the owning thread and
+/// panics otherwise. A `Local` dropped on another thread leaks its
+/// value rather than dropping it there.
+pub struct Local<T> {
+    value: ManuallyDrop<T>,
+    thread_id: ThreadId,
+}
+
+// The value is only ever reached, cloned or dropped on the owning
+// thread, which every access checks.
+unsafe impl<T> Send for Local<T> {}
+unsafe impl<T> Sync for Local<T> {}
+
+impl<T> Local<T> {
+    pub fn new(value: T) -> Local<T> {
+        Local {
+            value: ManuallyDrop::new(value),
+            thread_id: thread::current().id(),
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        self.assert_owning_thread();
+        &self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.assert_owning_thread();
+        &mut self.value
+    }
+
+    pub fn into_inner(self) -> T {
+        self.assert_owning_thread();
+        let mut local = ManuallyDrop::new(self);
+        unsafe { ManuallyDrop::take(&mut local.value) }
+    }
+
+    pub fn is_owning_thread(&self) -> bool {
+        thread::current().id() == self.thread_id
+    }
+
+    fn assert_owning_thread(&self) {
+        if !self.is_owning_thread() {
+            panic!("a value of a LocalSodiumCtx was used from another thread");
+        }
+    }
+}
+
+impl<T: Clone> Clone for Local<T> {
+    fn clone(&self) -> Self {
+        Local::new(self.get().clone())
+    }
+}
+
+impl<T> Drop for Local<T> {
+    fn drop(&mut self) {
+        if self.is_owning_thread() {
+            unsafe { ManuallyDrop::drop(&mut self.value) };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Local<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_owning_thread() {
+            self.value.fmt(f)
+        } else {
+            f.write_str("<local>")
+        }
+    }
+}
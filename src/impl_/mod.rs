@@ -1,18 +1,25 @@
 #![allow(clippy::borrowed_box)]
 
 pub mod cell;
+pub mod cell_collection;
 pub mod cell_loop;
 pub mod cell_sink;
 pub mod dep;
+pub mod dot;
 pub mod gc_node;
 pub mod lambda;
 pub mod lazy;
 pub mod listener;
+pub mod local;
 pub mod name;
 pub mod node;
 pub mod router;
 pub mod sodium_ctx;
+pub mod stats;
 pub mod stream;
 pub mod stream_loop;
 pub mod stream_sink;
+pub mod thread_pool;
+pub mod timer;
+pub mod trace;
 pub mod transaction;
@@ -1,6 +1,9 @@
+use std::cell::Cell as StdCell;
 use std::fmt::{Display, Formatter, Result};
+use std::panic::Location;
 
-#[derive(Copy, Clone, Debug)]
+/// The kind of a node, telling what made it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum NodeName {
     Node(u8),
     Cell(Cell),
@@ -17,18 +20,24 @@ impl NodeName {
     pub const CELL_SWITCH_S_OUTER: NodeName = NodeName::Cell(Cell::SwitchSOuter);
     pub const CELL_SWITCH_C_INNER: NodeName = NodeName::Cell(Cell::SwitchCInner);
     pub const CELL_SWITCH_C_OUTER: NodeName = NodeName::Cell(Cell::SwitchCOuter);
+    pub const CELL_LOOP_NEW: NodeName = NodeName::Cell(Cell::LoopNew);
+    pub const CELL_LIFT_ALL: NodeName = NodeName::Cell(Cell::LiftAll);
 
     pub const STREAM_NEW: NodeName = NodeName::Stream(Stream::New);
     pub const STREAM_NEW_WITH_COALESCER: NodeName = NodeName::Stream(Stream::NewWithCoalescer);
     pub const STREAM_MAP: NodeName = NodeName::Stream(Stream::Map);
     pub const STREAM_FILTER: NodeName = NodeName::Stream(Stream::Filter);
+    pub const STREAM_CALM: NodeName = NodeName::Stream(Stream::Calm);
     pub const STREAM_MERGE: NodeName = NodeName::Stream(Stream::Merge);
+    pub const STREAM_MERGE_ALL: NodeName = NodeName::Stream(Stream::MergeAll);
+    pub const STREAM_MERGE_DYNAMIC_INNER: NodeName = NodeName::Stream(Stream::MergeDynamicInner);
+    pub const STREAM_MERGE_DYNAMIC_OUTER: NodeName = NodeName::Stream(Stream::MergeDynamicOuter);
     pub const STREAM_ONCE: NodeName = NodeName::Stream(Stream::Once);
     pub const STREAM_LISTEN: NodeName = NodeName::Stream(Stream::Listen);
     pub const STREAM_LOOP_NEW: NodeName = NodeName::Stream(Stream::LoopNew);
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Cell {
     New,
     Hold,
@@ -36,15 +45,21 @@ pub enum Cell {
     SwitchSOuter,
     SwitchCInner,
     SwitchCOuter,
+    LoopNew,
+    LiftAll,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Stream {
     New,
     NewWithCoalescer,
     Map,
     Filter,
+    Calm,
     Merge,
+    MergeAll,
+    MergeDynamicInner,
+    MergeDynamicOuter,
     Once,
     Listen,
     LoopNew,
@@ -61,6 +76,8 @@ impl Display for NodeName {
             NodeName::Cell(Cell::SwitchSOuter) => f.write_str("switch_s outer node"),
             NodeName::Cell(Cell::SwitchCInner) => f.write_str("switch_c inner node"),
             NodeName::Cell(Cell::SwitchCOuter) => f.write_str("switch_c outer node"),
+            NodeName::Cell(Cell::LoopNew) => f.write_str("CellLoop::new"),
+            NodeName::Cell(Cell::LiftAll) => f.write_str("Cell::lift_all"),
 
             NodeName::Stream(Stream::New) => f.write_str("Stream::new"),
             NodeName::Stream(Stream::NewWithCoalescer) => {
@@ -68,7 +85,11 @@ impl Display for NodeName {
             }
             NodeName::Stream(Stream::Map) => f.write_str("Stream::map"),
             NodeName::Stream(Stream::Filter) => f.write_str("Stream::filter"),
+            NodeName::Stream(Stream::Calm) => f.write_str("Stream::calm"),
             NodeName::Stream(Stream::Merge) => f.write_str("Stream::merge"),
+            NodeName::Stream(Stream::MergeAll) => f.write_str("Stream::merge_all"),
+            NodeName::Stream(Stream::MergeDynamicInner) => f.write_str("merge_dynamic inner node"),
+            NodeName::Stream(Stream::MergeDynamicOuter) => f.write_str("merge_dynamic outer node"),
             NodeName::Stream(Stream::Once) => f.write_str("Stream::once"),
             NodeName::Stream(Stream::Listen) => f.write_str("Stream::listen"),
             NodeName::Stream(Stream::LoopNew) => f.write_str("StreamLoop::new"),
@@ -79,3 +100,64 @@ impl Display for NodeName {
         }
     }
 }
+
+thread_local! {
+    static CALLER_LOCATION: StdCell<Option<&'static Location<'static>>> = const { StdCell::new(None) };
+}
+
+// Records where the user called into the public API, so nodes built for
+// that call can say where they came from. Public combinators are
+// #[track_caller] and hold one of these while they run. Only the
+// outermost one counts, as combinators are often built out of others.
+pub struct CallerLocation {
+    is_outermost: bool,
+}
+
+impl CallerLocation {
+    #[track_caller]
+    pub fn enter() -> CallerLocation {
+        let location = Location::caller();
+        let is_outermost = CALLER_LOCATION.with(|caller| {
+            if caller.get().is_none() {
+                caller.set(Some(location));
+                true
+            } else {
+                false
+            }
+        });
+        CallerLocation { is_outermost }
+    }
+
+    pub fn current() -> Option<&'static Location<'static>> {
+        CALLER_LOCATION.with(|caller| caller.get())
+    }
+}
+
+impl Drop for CallerLocation {
+    fn drop(&mut self) {
+        if self.is_outermost {
+            CALLER_LOCATION.with(|caller| caller.set(None));
+        }
+    }
+}
+
+// Clears the caller location while user code runs, such as a lambda or
+// the propagation of a transaction, so nodes built there get the
+// location of their own call rather than of whichever call ran them.
+pub struct NoCallerLocation {
+    previous: Option<&'static Location<'static>>,
+}
+
+impl NoCallerLocation {
+    pub fn enter() -> NoCallerLocation {
+        NoCallerLocation {
+            previous: CALLER_LOCATION.with(|caller| caller.take()),
+        }
+    }
+}
+
+impl Drop for NoCallerLocation {
+    fn drop(&mut self) {
+        CALLER_LOCATION.with(|caller| caller.set(self.previous));
+    }
+}
@@ -3,11 +3,13 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Weak;
 
 use crate::impl_::dep::Dep;
-use crate::impl_::gc_node::{GcNode, Tracer};
+use crate::impl_::gc_node::{defer, GcNode, Tracer};
 use crate::impl_::sodium_ctx::SodiumCtx;
 
 use super::name::NodeName;
@@ -47,6 +49,7 @@ pub trait IsNodeExt: IsNode {
             let mut dependency_dependents = dependency.data().dependents.write();
             dependency_dependents.push(self.downgrade());
         }
+        ensure_rank_above(self.node(), dependency.data().rank.load(Ordering::SeqCst));
     }
 
     fn remove_dependency<NODE: IsNode + Sync + Sync>(&self, dependency: &NODE) {
@@ -149,15 +152,22 @@ pub struct Node {
 pub struct NodeData {
     pub visited: AtomicBool,
     pub changed: AtomicBool,
+    // Every node has a higher rank than all of its dependencies, so
+    // updating nodes in rank order visits dependencies first.
+    pub rank: AtomicU64,
     pub update: RwLock<Box<dyn FnMut() + Send + Sync>>,
     pub update_dependencies: RwLock<Vec<Dep>>,
     pub dependencies: RwLock<Vec<Box<dyn IsNode + Send + Sync>>>,
     pub dependents: RwLock<Vec<Box<dyn IsWeakNode + Send + Sync>>>,
     pub keep_alive: RwLock<Vec<GcNode>>,
     pub cleanups: RwLock<Vec<Box<dyn FnMut() + Send + Sync>>>,
+    // Formats the value of the node for introspection, if asked for.
+    pub debug_value: RwLock<Option<DebugValueFn>>,
     pub sodium_ctx: SodiumCtx,
 }
 
+pub type DebugValueFn = Box<dyn Fn() -> Option<String> + Send + Sync>;
+
 #[derive(Clone)]
 pub struct WeakNode {
     pub data: Weak<NodeData>,
@@ -187,6 +197,25 @@ impl Drop for Node {
 impl Drop for NodeData {
     fn drop(&mut self) {
         self.sodium_ctx.dec_node_count();
+        self.sodium_ctx.unregister_node(self);
+        // Dropping these can drop the last reference to other nodes, so
+        // it is deferred rather than left to run recursively.
+        let dependencies = std::mem::take(self.dependencies.get_mut());
+        let update = std::mem::replace(self.update.get_mut(), Box::new(|| {}));
+        let cleanups = std::mem::take(self.cleanups.get_mut());
+        // If the node data goes before the gc node is freed, the deconstructor
+        // finds nothing to clean up, so the keep alives are released here.
+        let keep_alive = std::mem::take(self.keep_alive.get_mut());
+        defer(move || {
+            drop(dependencies);
+            drop(update);
+            for gc_node in keep_alive {
+                gc_node.dec_ref();
+            }
+            for mut cleanup in cleanups {
+                cleanup();
+            }
+        });
     }
 }
 
@@ -305,16 +334,23 @@ impl Node {
                 }
             };
         }
+        let rank = dependencies
+            .iter()
+            .map(|dependency| dependency.data().rank.load(Ordering::SeqCst) + 1)
+            .max()
+            .unwrap_or(0);
         let result = Node {
             data: Arc::new(NodeData {
                 visited: AtomicBool::new(false),
                 changed: AtomicBool::new(false),
+                rank: AtomicU64::new(rank),
                 update: RwLock::new(Box::new(update)),
                 update_dependencies: RwLock::new(Vec::new()),
                 dependencies: RwLock::new(box_clone_vec_is_node(&dependencies)),
                 dependents: RwLock::new(Vec::new()),
                 keep_alive: RwLock::new(Vec::new()),
                 cleanups: RwLock::new(Vec::new()),
+                debug_value: RwLock::new(None),
                 sodium_ctx: sodium_ctx.clone(),
             }),
             gc_node: GcNode::new(&sodium_ctx.gc_ctx(), name, deconstructor, trace),
@@ -330,6 +366,7 @@ impl Node {
         }
         sodium_ctx.inc_node_ref_count();
         sodium_ctx.inc_node_count();
+        sodium_ctx.register_node(&result);
         result
     }
 
@@ -342,6 +379,44 @@ impl Node {
     }
 }
 
+// Raises the rank of a node above limit, and then the ranks of its
+// dependents as far as needed. Ranks never go down, so removing a
+// dependency leaves them as they are. Walking back into a node that is
+// already on the current path means we went round a cycle (as switch_s
+// can create), so the walk stops there.
+pub fn ensure_rank_above(node: &Node, limit: u64) {
+    enum Step {
+        Enter(Box<dyn IsNode + Send + Sync>, u64),
+        Leave(*const NodeData),
+    }
+    let mut on_path: HashSet<*const NodeData> = HashSet::new();
+    let mut stack = vec![Step::Enter(node.box_clone(), limit)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Enter(node, limit) => {
+                let data = node.data();
+                let key = Arc::as_ptr(data);
+                if data.rank.load(Ordering::SeqCst) > limit || on_path.contains(&key) {
+                    continue;
+                }
+                let rank = limit + 1;
+                data.rank.store(rank, Ordering::SeqCst);
+                on_path.insert(key);
+                stack.push(Step::Leave(key));
+                let dependents = data.dependents.read();
+                for dependent in &*dependents {
+                    if let Some(dependent) = dependent.upgrade() {
+                        stack.push(Step::Enter(dependent, rank));
+                    }
+                }
+            }
+            Step::Leave(key) => {
+                on_path.remove(&key);
+            }
+        }
+    }
+}
+
 impl WeakNode {
     pub fn upgrade2(&self) -> Option<Node> {
         if let Some(data) = self.data.upgrade() {
@@ -412,7 +487,12 @@ impl fmt::Debug for dyn IsNode + Sync + Sync {
                 continue;
             }
             util.mark_visitied(node);
-            write!(f, "(Node {} (dependencies [", node_to_id(node))?;
+            write!(
+                f,
+                "(Node {} {} (dependencies [",
+                node_to_id(node),
+                node.gc_node().description()
+            )?;
             let dependencies = node.data().dependencies.read();
             {
                 let mut first: bool = true;
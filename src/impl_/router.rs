@@ -1,8 +1,9 @@
-use crate::impl_::node::{IsNode, IsWeakNode, Node, WeakNode};
+use crate::impl_::node::{ensure_rank_above, IsNode, IsWeakNode, Node, WeakNode};
 use crate::impl_::sodium_ctx::{SodiumCtx, SodiumCtxData};
 use crate::impl_::stream::{Stream, WeakStream};
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 
 use super::name::NodeName;
@@ -158,6 +159,7 @@ impl<A, K> Router<A, K> {
         } else {
             let s = Stream::new(&self.sodium_ctx);
             s.node().data().dependencies.write().push(self.box_clone());
+            ensure_rank_above(s.node(), self.data().rank.load(Ordering::SeqCst));
             table.insert(k.clone(), Stream::downgrade(&s));
             {
                 let table = self.table.clone();
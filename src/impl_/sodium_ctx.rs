@@ -1,17 +1,28 @@
-use crate::impl_::gc_node::GcCtx;
-use crate::impl_::listener::Listener;
-use crate::impl_::node::{
-    box_clone_vec_is_node, box_clone_vec_is_weak_node, IsNode, IsWeakNode, Node,
+use crate::impl_::dot;
+use crate::impl_::gc_node::{GcCtx, GcNode, GcStats, WeakGcNode};
+use crate::impl_::listener::{Listener, ListenerData};
+use crate::impl_::node::{box_clone_vec_is_weak_node, IsNode, IsWeakNode, Node, NodeData};
+use crate::impl_::stats::{
+    OnTransactionStats, SodiumStats, StatsCollector, Stopwatch, TransactionStats,
 };
+use crate::impl_::thread_pool::ThreadPool;
+use crate::impl_::trace::{self, TransactionSpan};
 
 use parking_lot::Mutex;
+use std::cell::Cell as StdCell;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Weak;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use super::name::NodeName;
+use super::name::{NoCallerLocation, NodeName};
 
 #[derive(Clone)]
 pub struct SodiumCtx {
@@ -20,6 +31,84 @@ pub struct SodiumCtx {
     node_count: Arc<AtomicUsize>,
     node_ref_count: Arc<AtomicUsize>,
     threaded_mode: Arc<ThreadedMode>,
+    live_op: Option<Arc<Mutex<LiveNodes>>>,
+    stats_op: Option<Arc<StatsCollector>>,
+}
+
+// Every node and listener that has not been dropped yet, keyed by the
+// address of its data, so the graph can be inspected without keeping
+// anything alive. Only kept when the context is built to track nodes, as
+// it puts a shared lock on creating and dropping every node.
+#[derive(Default)]
+pub struct LiveNodes {
+    nodes: HashMap<usize, LiveNode>,
+    listeners: HashMap<usize, LiveListener>,
+}
+
+struct LiveNode {
+    gc_node: WeakGcNode,
+    data: Weak<NodeData>,
+}
+
+struct LiveListener {
+    gc_node: WeakGcNode,
+    data: Weak<Mutex<ListenerData>>,
+}
+
+/// When a [`SodiumCtx`][crate::SodiumCtx] collects cycles of Sodium
+/// objects that are no longer reachable, set with
+/// [`SodiumCtxBuilder::gc_policy`][crate::SodiumCtxBuilder::gc_policy].
+///
+/// Objects that aren't part of a cycle are freed as soon as they are
+/// dropped whatever the policy. Dropping an object that might be part
+/// of a cycle buffers it as a root for the cycle collector instead,
+/// which then has to look at everything reachable from the root.
+/// However cycles are collected, [`SodiumCtx::collect_cycles`] can
+/// also be called at any time to collect everything buffered so far.
+///
+/// [`SodiumCtx::collect_cycles`]: crate::SodiumCtx::collect_cycles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcPolicy {
+    /// Collect cycles at the end of every transaction. This is the
+    /// default.
+    EveryTransaction,
+    /// Collect cycles at the end of every nth transaction. `0` is the
+    /// same as `Manual`.
+    EveryNTransactions(u32),
+    /// Collect cycles at the end of a transaction once more than this
+    /// many roots are buffered.
+    RootsOver(usize),
+    /// Never collect cycles automatically.
+    Manual,
+    /// Spread the collection over transactions, looking at no more than
+    /// `max_roots` roots at the end of each transaction and stopping
+    /// early once `max_time` has passed. The roots are worked through in
+    /// the order they were buffered, and whatever is left waits for the
+    /// next transaction.
+    Incremental {
+        max_roots: usize,
+        max_time: Duration,
+    },
+}
+
+pub struct SodiumCtxConfig {
+    pub threaded_mode: ThreadedMode,
+    pub gc_policy: GcPolicy,
+    pub collect_stats: bool,
+    pub on_transaction_stats: Option<OnTransactionStats>,
+    pub track_nodes: bool,
+}
+
+impl Default for SodiumCtxConfig {
+    fn default() -> SodiumCtxConfig {
+        SodiumCtxConfig {
+            threaded_mode: single_threaded_mode(),
+            gc_policy: GcPolicy::EveryTransaction,
+            collect_stats: false,
+            on_transaction_stats: None,
+            track_nodes: false,
+        }
+    }
 }
 
 pub struct SodiumCtxData {
@@ -27,16 +116,27 @@ pub struct SodiumCtxData {
     pub visited_nodes: Vec<Box<dyn IsNode>>,
     pub transaction_depth: u32,
     pub pre_eot: Vec<Box<dyn FnMut() + Send>>,
+    pub after_update: Vec<Box<dyn FnMut() + Send>>,
     pub pre_post: Vec<Box<dyn FnMut() + Send>>,
     pub post: Vec<Box<dyn FnMut() + Send>>,
+    pub rollback: Vec<Box<dyn FnMut() + Send>>,
+    pub send_later: Vec<Box<dyn FnOnce() + Send>>,
     pub keep_alive: Vec<Listener>,
     pub collecting_cycles: bool,
     pub allow_add_roots: bool,
     pub allow_collect_cycles_counter: u32,
+    pub gc_policy: GcPolicy,
+    pub transactions_since_collect_cycles: u32,
+    pub on_start: Vec<Box<dyn FnMut() -> bool + Send>>,
+    pub running_on_start: bool,
+    pub transaction_span: Option<TransactionSpan>,
 }
 
 pub struct ThreadedMode {
     pub spawner: ThreadSpawner,
+    // when set, nodes of the same rank are updated at the same time on the
+    // spawner.
+    pub parallel: bool,
 }
 
 type SpawnFn = Box<dyn FnOnce() + Send>;
@@ -92,6 +192,7 @@ pub fn single_threaded_mode() -> ThreadedMode {
                 }
             }),
         },
+        parallel: false,
     }
 }
 
@@ -103,16 +204,31 @@ pub fn simple_threaded_mode() -> ThreadedMode {
                 let h = thread::spawn(callback);
                 ThreadJoiner {
                     join_fn: Box::new(move || {
-                        h.join().unwrap();
+                        if let Err(payload) = h.join() {
+                            panic::resume_unwind(payload);
+                        }
                     }),
                 }
             }),
         },
+        parallel: true,
     }
 }
 
-// TODO:
-//pub fn thread_pool_threaded_mode(num_threads: usize) -> ThreadedMode
+pub fn thread_pool_threaded_mode(num_threads: usize) -> ThreadedMode {
+    let thread_pool = ThreadPool::new(num_threads);
+    ThreadedMode {
+        spawner: ThreadSpawner {
+            spawn_fn: Box::new(move |callback| {
+                let task = thread_pool.spawn(callback);
+                ThreadJoiner {
+                    join_fn: Box::new(move || task.join()),
+                }
+            }),
+        },
+        parallel: true,
+    }
+}
 
 impl Default for SodiumCtx {
     fn default() -> SodiumCtx {
@@ -122,6 +238,10 @@ impl Default for SodiumCtx {
 
 impl SodiumCtx {
     pub fn new() -> SodiumCtx {
+        SodiumCtx::new_with_config(SodiumCtxConfig::default())
+    }
+
+    pub fn new_with_config(config: SodiumCtxConfig) -> SodiumCtx {
         SodiumCtx {
             gc_ctx: GcCtx::new(),
             data: Arc::new(Mutex::new(SodiumCtxData {
@@ -129,19 +249,74 @@ impl SodiumCtx {
                 visited_nodes: Vec::new(),
                 transaction_depth: 0,
                 pre_eot: Vec::new(),
+                after_update: Vec::new(),
                 pre_post: Vec::new(),
                 post: Vec::new(),
+                rollback: Vec::new(),
+                send_later: Vec::new(),
                 keep_alive: Vec::new(),
                 collecting_cycles: false,
                 allow_add_roots: true,
                 allow_collect_cycles_counter: 0,
+                gc_policy: config.gc_policy,
+                transactions_since_collect_cycles: 0,
+                on_start: Vec::new(),
+                running_on_start: false,
+                transaction_span: None,
             })),
             node_count: Arc::new(AtomicUsize::new(0)),
             node_ref_count: Arc::new(AtomicUsize::new(0)),
-            threaded_mode: Arc::new(single_threaded_mode()),
+            threaded_mode: Arc::new(config.threaded_mode),
+            live_op: if config.track_nodes {
+                Some(Arc::new(Mutex::new(LiveNodes::default())))
+            } else {
+                None
+            },
+            stats_op: if config.collect_stats {
+                Some(Arc::new(StatsCollector::new(config.on_transaction_stats)))
+            } else {
+                None
+            },
         }
     }
 
+    pub fn ptr_eq(&self, other: &SodiumCtx) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.data) as usize
+    }
+
+    // True while this thread is updating the graph of this context, that
+    // is running a lambda or listener.
+    pub fn is_propagating(&self) -> bool {
+        PROPAGATING.with(|propagating| propagating.get() == self.id())
+    }
+
+    // Queues k to run once the current transaction is over, in a
+    // transaction shared with everything else queued the same way.
+    pub fn send_later<K: FnOnce() + Send + 'static>(&self, k: K) {
+        self.transaction(|| {
+            let is_first = self.with_data(|data: &mut SodiumCtxData| {
+                data.send_later.push(Box::new(k));
+                data.send_later.len() == 1
+            });
+            if is_first {
+                let sodium_ctx = self.clone();
+                self.post(move || {
+                    let send_later = sodium_ctx
+                        .with_data(|data: &mut SodiumCtxData| mem::take(&mut data.send_later));
+                    sodium_ctx.transaction(|| {
+                        for k in send_later {
+                            k();
+                        }
+                    });
+                });
+            }
+        });
+    }
+
     pub fn gc_ctx(&self) -> GcCtx {
         self.gc_ctx.clone()
     }
@@ -152,17 +327,55 @@ impl SodiumCtx {
 
     pub fn transaction<R, K: FnOnce() -> R>(&self, k: K) -> R {
         self.enter_transaction();
-        let result = k();
+        let result = match panic::catch_unwind(AssertUnwindSafe(k)) {
+            Ok(result) => result,
+            Err(payload) => {
+                self.abandon_transaction();
+                panic::resume_unwind(payload);
+            }
+        };
         self.leave_transaction();
         result
     }
 
     pub fn enter_transaction(&self) {
+        let run_on_start = self.with_data(|data: &mut SodiumCtxData| {
+            data.transaction_depth == 0 && !data.running_on_start && !data.on_start.is_empty()
+        });
+        if run_on_start {
+            self.run_on_start();
+        }
         self.with_data(|data: &mut SodiumCtxData| {
+            if data.transaction_depth == 0 {
+                data.transaction_span = Some(TransactionSpan::enter());
+            }
             data.transaction_depth += 1;
         });
     }
 
+    // Hooks run before a top level transaction starts rather than inside
+    // it, so anything they send goes into transactions of their own. A
+    // hook is dropped once it returns false.
+    pub fn on_start<K: FnMut() -> bool + Send + 'static>(&self, k: K) {
+        self.with_data(|data: &mut SodiumCtxData| data.on_start.push(Box::new(k)));
+    }
+
+    fn run_on_start(&self) {
+        let on_start = self.with_data(|data: &mut SodiumCtxData| {
+            data.running_on_start = true;
+            let mut on_start: Vec<Box<dyn FnMut() -> bool + Send>> = Vec::new();
+            mem::swap(&mut on_start, &mut data.on_start);
+            on_start
+        });
+        // the hooks are put back by the guard, so a hook that panics
+        // doesn't lose them
+        let mut guard = OnStartGuard {
+            sodium_ctx: self,
+            on_start,
+        };
+        guard.on_start.retain_mut(|k| k());
+    }
+
     pub fn leave_transaction(&self) {
         let is_end_of_transaction = self.with_data(|data: &mut SodiumCtxData| {
             data.transaction_depth -= 1;
@@ -173,6 +386,50 @@ impl SodiumCtx {
         }
     }
 
+    // Leaves a transaction that is being unwound by a panic. Nothing sent
+    // in it takes effect in the graph.
+    pub fn abandon_transaction(&self) {
+        let span_op = self.with_data(|data: &mut SodiumCtxData| {
+            data.transaction_depth -= 1;
+            if data.transaction_depth == 0 {
+                data.transaction_span.take()
+            } else {
+                None
+            }
+        });
+        if let Some(span) = span_op {
+            self.roll_back();
+            span.exit();
+        }
+    }
+
+    // Puts the graph back the way it was before the transaction: streams
+    // stop firing, cells keep their old values and nothing queued for the
+    // end of the transaction is run. Listeners that already ran are not
+    // undone.
+    fn roll_back(&self) {
+        let (changed_nodes, pre_eot, after_update, pre_post, post, rollback, send_later) = self
+            .with_data(|data: &mut SodiumCtxData| {
+                (
+                    mem::take(&mut data.changed_nodes),
+                    mem::take(&mut data.pre_eot),
+                    mem::take(&mut data.after_update),
+                    mem::take(&mut data.pre_post),
+                    mem::take(&mut data.post),
+                    mem::take(&mut data.rollback),
+                    mem::take(&mut data.send_later),
+                )
+            });
+        // dropped outside of the lock, as they may free nodes
+        drop((changed_nodes, pre_eot, after_update, post, send_later));
+        for mut k in pre_post {
+            k();
+        }
+        for mut k in rollback {
+            k();
+        }
+    }
+
     pub fn add_dependents_to_changed_nodes(&self, node: &dyn IsNode) {
         self.with_data(|data: &mut SodiumCtxData| {
             let node_dependents = node.data().dependents.read();
@@ -189,12 +446,26 @@ impl SodiumCtx {
         self.with_data(|data: &mut SodiumCtxData| data.pre_eot.push(Box::new(k)));
     }
 
+    // Run once the graph has been updated, while a panic still rolls the
+    // transaction back. Unlike pre_eot, this still runs for the current
+    // transaction when queued from a lambda or listener.
+    pub fn after_update<K: FnMut() + Send + 'static>(&self, k: K) {
+        self.with_data(|data: &mut SodiumCtxData| data.after_update.push(Box::new(k)));
+    }
+
     pub fn pre_post<K: FnMut() + Send + 'static>(&self, k: K) {
         self.with_data(|data: &mut SodiumCtxData| {
             data.pre_post.push(Box::new(k));
         });
     }
 
+    // Run instead of post if the transaction panics.
+    pub fn on_rollback<K: FnMut() + Send + 'static>(&self, k: K) {
+        self.with_data(|data: &mut SodiumCtxData| {
+            data.rollback.push(Box::new(k));
+        });
+    }
+
     pub fn post<K: FnMut() + Send + 'static>(&self, k: K) {
         self.with_data(|data: &mut SodiumCtxData| {
             data.post.push(Box::new(k));
@@ -206,6 +477,96 @@ impl SodiumCtx {
         k(&mut data)
     }
 
+    pub fn is_tracking_nodes(&self) -> bool {
+        self.live_op.is_some()
+    }
+
+    pub fn register_node(&self, node: &Node) {
+        if let Some(live) = &self.live_op {
+            let key = Arc::as_ptr(&node.data) as usize;
+            live.lock().nodes.insert(
+                key,
+                LiveNode {
+                    gc_node: node.gc_node.downgrade(),
+                    data: Arc::downgrade(&node.data),
+                },
+            );
+        }
+    }
+
+    pub fn unregister_node(&self, data: &NodeData) {
+        if let Some(live) = &self.live_op {
+            let key = data as *const NodeData as usize;
+            live.lock().nodes.remove(&key);
+        }
+    }
+
+    pub fn register_listener(&self, listener: &Listener) {
+        if let Some(live) = &self.live_op {
+            let key = listener.data.data_ptr() as usize;
+            live.lock().listeners.insert(
+                key,
+                LiveListener {
+                    gc_node: listener.gc_node.downgrade(),
+                    data: Arc::downgrade(&listener.data),
+                },
+            );
+        }
+    }
+
+    pub fn unregister_listener(&self, data: &ListenerData) {
+        if let Some(live) = &self.live_op {
+            let key = data as *const ListenerData as usize;
+            live.lock().listeners.remove(&key);
+        }
+    }
+
+    // The live nodes, ordered by id, or none if nodes aren't tracked. The
+    // lock is released before returning, since dropping what is returned
+    // can unregister it.
+    pub fn live_nodes(&self) -> Vec<(GcNode, Arc<NodeData>)> {
+        let mut nodes: Vec<_> = match &self.live_op {
+            Some(live) => live
+                .lock()
+                .nodes
+                .values()
+                .filter_map(|node| Some((node.gc_node.upgrade()?, node.data.upgrade()?)))
+                .collect(),
+            None => Vec::new(),
+        };
+        nodes.sort_by_key(|(gc_node, _)| gc_node.id());
+        nodes
+    }
+
+    pub fn live_listeners(&self) -> Vec<(GcNode, Arc<Mutex<ListenerData>>)> {
+        let mut listeners: Vec<_> = match &self.live_op {
+            Some(live) => live
+                .lock()
+                .listeners
+                .values()
+                .filter_map(|listener| {
+                    Some((listener.gc_node.upgrade()?, listener.data.upgrade()?))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        listeners.sort_by_key(|(gc_node, _)| gc_node.id());
+        listeners
+    }
+
+    pub fn export_dot(&self) -> String {
+        dot::export_dot(self)
+    }
+
+    pub fn stats(&self) -> SodiumStats {
+        let mut stats = match &self.stats_op {
+            Some(stats_collector) => *stats_collector.stats.lock(),
+            None => SodiumStats::default(),
+        };
+        stats.gc_roots_buffered = self.gc_ctx.roots_buffered();
+        stats
+    }
+
     pub fn node_count(&self) -> usize {
         self.node_count.load(Ordering::Relaxed)
     }
@@ -231,49 +592,71 @@ impl SodiumCtx {
     }
 
     pub fn end_of_transaction(&self) {
-        self.with_data(|data: &mut SodiumCtxData| {
+        let _caller = NoCallerLocation::enter();
+        // Taken out now, as transactions started from post get spans of
+        // their own.
+        let span_op = self.with_data(|data: &mut SodiumCtxData| {
             data.transaction_depth += 1;
             data.allow_collect_cycles_counter += 1;
+            data.transaction_span.take()
         });
-        // pre eot
-        {
-            let pre_eot = self.with_data(|data: &mut SodiumCtxData| {
-                let mut pre_eot: Vec<Box<dyn FnMut() + Send>> = Vec::new();
-                mem::swap(&mut pre_eot, &mut data.pre_eot);
-                pre_eot
-            });
-            for mut k in pre_eot {
-                k();
+        let mut stats = TransactionStats::default();
+        let mut stopwatch = Stopwatch::new(self.stats_op.is_some());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _propagating = Propagating::enter(self.id());
+            // pre eot
+            {
+                let pre_eot = self.with_data(|data: &mut SodiumCtxData| {
+                    let mut pre_eot: Vec<Box<dyn FnMut() + Send>> = Vec::new();
+                    mem::swap(&mut pre_eot, &mut data.pre_eot);
+                    pre_eot
+                });
+                trace::phase("pre_eot", pre_eot.len());
+                for mut k in pre_eot {
+                    k();
+                }
             }
-        }
-        //
-        loop {
-            let changed_nodes: Vec<Box<dyn IsNode>> = self.with_data(|data: &mut SodiumCtxData| {
-                let mut changed_nodes: Vec<Box<dyn IsNode>> = Vec::new();
-                mem::swap(&mut changed_nodes, &mut data.changed_nodes);
-                changed_nodes
-            });
-            if changed_nodes.is_empty() {
-                break;
+            stats.pre_eot_time = stopwatch.lap();
+            //
+            self.update_changed_nodes(&mut stats);
+            // after update
+            {
+                let after_update =
+                    self.with_data(|data: &mut SodiumCtxData| mem::take(&mut data.after_update));
+                for mut k in after_update {
+                    k();
+                }
             }
-            for node in changed_nodes {
-                self.update_node(node.node());
+        }));
+        if let Err(payload) = result {
+            self.roll_back();
+            self.with_data(|data: &mut SodiumCtxData| {
+                data.transaction_depth -= 1;
+                data.allow_collect_cycles_counter -= 1;
+            });
+            if let Some(span) = span_op {
+                span.exit();
             }
+            panic::resume_unwind(payload);
         }
         self.with_data(|data: &mut SodiumCtxData| {
             data.transaction_depth -= 1;
         });
+        stats.update_time = stopwatch.lap();
         // pre_post
         {
-            let pre_post = self.with_data(|data: &mut SodiumCtxData| {
+            let (pre_post, rollback) = self.with_data(|data: &mut SodiumCtxData| {
                 let mut pre_post: Vec<Box<dyn FnMut() + Send>> = Vec::new();
                 mem::swap(&mut pre_post, &mut data.pre_post);
-                pre_post
+                (pre_post, mem::take(&mut data.rollback))
             });
+            drop(rollback);
+            trace::phase("pre_post", pre_post.len());
             for mut k in pre_post {
                 k();
             }
         }
+        stats.pre_post_time = stopwatch.lap();
         // post
         {
             let post = self.with_data(|data: &mut SodiumCtxData| {
@@ -281,80 +664,339 @@ impl SodiumCtx {
                 mem::swap(&mut post, &mut data.post);
                 post
             });
+            trace::phase("post", post.len());
+            stats.post_callbacks = post.len() as u64;
+            // The rest still run if one panics, as cells take their new
+            // values in post.
+            let mut panic_op = None;
             for mut k in post {
-                k();
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(&mut k)) {
+                    panic_op.get_or_insert(payload);
+                }
+            }
+            if let Some(payload) = panic_op {
+                self.with_data(|data: &mut SodiumCtxData| {
+                    data.allow_collect_cycles_counter -= 1;
+                });
+                if let Some(span) = span_op {
+                    span.exit();
+                }
+                panic::resume_unwind(payload);
             }
         }
-        let allow_collect_cycles = self.with_data(|data: &mut SodiumCtxData| {
+        stats.post_time = stopwatch.lap();
+        // The most roots to look at, and when to stop, if cycles are to be
+        // collected now.
+        let gc_budget_op = self.with_data(|data: &mut SodiumCtxData| {
             data.allow_collect_cycles_counter -= 1;
-            data.allow_collect_cycles_counter == 0
+            if data.allow_collect_cycles_counter != 0 {
+                return None;
+            }
+            match data.gc_policy {
+                GcPolicy::EveryTransaction => Some((usize::MAX, None)),
+                GcPolicy::EveryNTransactions(n) => {
+                    if n == 0 {
+                        return None;
+                    }
+                    data.transactions_since_collect_cycles += 1;
+                    if data.transactions_since_collect_cycles < n {
+                        return None;
+                    }
+                    data.transactions_since_collect_cycles = 0;
+                    Some((usize::MAX, None))
+                }
+                GcPolicy::RootsOver(threshold) => {
+                    (self.gc_ctx.roots_buffered() > threshold).then_some((usize::MAX, None))
+                }
+                GcPolicy::Manual => None,
+                GcPolicy::Incremental {
+                    max_roots,
+                    max_time,
+                } => Some((max_roots, Some(Instant::now() + max_time))),
+            }
         });
-        if allow_collect_cycles {
+        if let Some((max_roots, deadline_op)) = gc_budget_op {
             // gc
-            self.collect_cycles()
+            trace::collect_cycles(self.node_count());
+            let gc_stats = self
+                .gc_ctx
+                .collect_cycles_with_budget(max_roots, deadline_op);
+            stats.gc_roots_scanned = gc_stats.roots_scanned;
+            stats.nodes_freed = gc_stats.nodes_freed;
+            stats.gc_time = stopwatch.lap();
         }
-    }
-
-    pub fn update_node(&self, node: &Node) {
-        let bail = node.data.visited.swap(true, Ordering::Release);
-        if bail {
-            return;
+        if let Some(stats_collector) = &self.stats_op {
+            stats_collector.add(&stats);
         }
-        let dependencies: Vec<Box<dyn IsNode + Send + Sync + 'static>>;
-        {
-            let dependencies2 = node.data.dependencies.read();
-            dependencies = box_clone_vec_is_node(&dependencies2);
+        if let Some(span) = span_op {
+            span.exit();
         }
-        {
-            let node = node.clone();
-            self.pre_post(move || {
-                node.data.visited.store(false, Ordering::SeqCst);
+    }
+
+    // Updates the nodes downstream of changed_nodes in rank order, so every
+    // node is visited at most once and only after all of its dependencies.
+    fn update_changed_nodes(&self, stats: &mut TransactionStats) {
+        let mut queue = UpdateQueue::new();
+        loop {
+            let changed_nodes: Vec<Box<dyn IsNode>> = self.with_data(|data: &mut SodiumCtxData| {
+                let mut changed_nodes: Vec<Box<dyn IsNode>> = Vec::new();
+                mem::swap(&mut changed_nodes, &mut data.changed_nodes);
+                changed_nodes
             });
+            for node in changed_nodes {
+                queue.mark_visited(node.node());
+                queue.schedule_dependents(node.node());
+            }
+            let nodes = queue.pop_lowest_rank();
+            if nodes.is_empty() {
+                break;
+            }
+            self.update_nodes(&nodes, stats);
+            for node in &nodes {
+                if node.data.changed.load(Ordering::SeqCst) {
+                    queue.schedule_dependents(node);
+                }
+            }
         }
-        // visit dependencies
-        let handle;
-        {
-            let dependencies = box_clone_vec_is_node(&dependencies);
-            let _self = self.clone();
-            handle = self.threaded_mode.spawn(move || {
-                for dependency in &dependencies {
-                    let visited = dependency.data().visited.load(Ordering::SeqCst);
-                    if !visited {
-                        _self.update_node(dependency.node());
+        stats.nodes_visited = queue.visited.len() as u64;
+    }
+
+    // The nodes all share the same rank, so none of them depends on
+    // another. Nodes without dependents (listeners among them) are updated
+    // last, one at a time and in scheduling order, which keeps the order of
+    // listener callbacks the same in every threaded mode.
+    fn update_nodes(&self, nodes: &[Node], stats: &mut TransactionStats) {
+        let (mut inner, leaves): (Vec<&Node>, Vec<&Node>) = nodes
+            .iter()
+            .partition(|node| !node.data.dependents.read().is_empty());
+        let parent = trace::current();
+        let id = self.id();
+        let mut updated: Vec<&Node> = Vec::new();
+        if self.threaded_mode.parallel && inner.len() > 1 {
+            // keep the last one for the current thread
+            let last_op = inner.pop();
+            let handles: Vec<(&Node, ThreadJoiner<bool>)> = inner
+                .into_iter()
+                .map(|node| {
+                    let node2 = node.clone();
+                    let parent = parent.clone();
+                    let handle = self.threaded_mode.spawn(move || {
+                        let _propagating = Propagating::enter(id);
+                        SodiumCtx::update_node(&node2, &parent)
+                    });
+                    (node, handle)
+                })
+                .collect();
+            // Every update is waited for before a panic is passed on, so
+            // none are still running while the transaction is rolled back.
+            let mut panic_op = None;
+            if let Some(last) = last_op {
+                match panic::catch_unwind(AssertUnwindSafe(|| {
+                    SodiumCtx::update_node(last, &parent)
+                })) {
+                    Ok(true) => updated.push(last),
+                    Ok(false) => {}
+                    Err(payload) => {
+                        panic_op.get_or_insert(payload);
                     }
                 }
-            });
+            }
+            for (node, handle) in handles {
+                match panic::catch_unwind(AssertUnwindSafe(|| handle.join())) {
+                    Ok(true) => updated.push(node),
+                    Ok(false) => {}
+                    Err(payload) => {
+                        panic_op.get_or_insert(payload);
+                    }
+                }
+            }
+            if let Some(payload) = panic_op {
+                panic::resume_unwind(payload);
+            }
+        } else {
+            for node in inner {
+                if SodiumCtx::update_node(node, &parent) {
+                    updated.push(node);
+                }
+            }
         }
-        handle.join();
-        // any dependencies changed?
-        let any_changed =
-            dependencies
-                .iter()
-                .any(|node: &Box<dyn IsNode + Send + Sync + 'static>| {
-                    node.node().data().changed.load(Ordering::SeqCst)
-                });
-        // if dependencies changed, then execute update on current node
+        for node in leaves {
+            if SodiumCtx::update_node(node, &parent) {
+                updated.push(node);
+            }
+        }
+        stats.node_updates += updated.len() as u64;
+        stats.listener_callbacks += updated
+            .iter()
+            .filter(|node| node.gc_node.name() == NodeName::STREAM_LISTEN)
+            .count() as u64;
+    }
+
+    // Returns true if the node's update function was run.
+    fn update_node(node: &Node, parent: &trace::Parent) -> bool {
+        let any_changed = node
+            .data
+            .dependencies
+            .read()
+            .iter()
+            .any(|dependency| dependency.data().changed.load(Ordering::SeqCst));
         if any_changed {
+            let _span = trace::update_node(node, parent);
             let mut update = node.data.update.write();
             let update: &mut Box<_> = &mut *update;
             update();
         }
-        // if self changed then update dependents
-        let changed = node.data.changed.load(Ordering::SeqCst);
-        if changed {
-            let dependents = box_clone_vec_is_weak_node(&node.data().dependents.read());
-            {
-                let _self = &self;
-                for dependent in dependents {
-                    if let Some(dependent2) = dependent.upgrade() {
-                        _self.update_node(dependent2.node());
-                    }
+        any_changed
+    }
+
+    pub fn collect_cycles(&self) -> GcStats {
+        trace::collect_cycles(self.node_count());
+        self.gc_ctx.collect_cycles()
+    }
+}
+
+thread_local! {
+    // The id of the context whose graph this thread is updating, or 0.
+    static PROPAGATING: StdCell<usize> = const { StdCell::new(0) };
+}
+
+struct Propagating {
+    previous: usize,
+}
+
+impl Propagating {
+    fn enter(id: usize) -> Propagating {
+        Propagating {
+            previous: PROPAGATING.with(|propagating| propagating.replace(id)),
+        }
+    }
+}
+
+impl Drop for Propagating {
+    fn drop(&mut self) {
+        PROPAGATING.with(|propagating| propagating.set(self.previous));
+    }
+}
+
+// Puts the on_start hooks back once they have run, along with any added
+// while they ran, even if one of them panicked.
+struct OnStartGuard<'a> {
+    sodium_ctx: &'a SodiumCtx,
+    on_start: Vec<Box<dyn FnMut() -> bool + Send>>,
+}
+
+impl Drop for OnStartGuard<'_> {
+    fn drop(&mut self) {
+        let mut on_start = mem::take(&mut self.on_start);
+        self.sodium_ctx.with_data(|data: &mut SodiumCtxData| {
+            on_start.append(&mut data.on_start);
+            data.on_start = on_start;
+            data.running_on_start = false;
+        });
+    }
+}
+
+struct UpdateQueue {
+    scheduled: BinaryHeap<ScheduledNode>,
+    next_seq: u64,
+    visited: Vec<Node>,
+}
+
+struct ScheduledNode {
+    rank: u64,
+    seq: u64,
+    node: Node,
+}
+
+impl UpdateQueue {
+    fn new() -> UpdateQueue {
+        UpdateQueue {
+            scheduled: BinaryHeap::new(),
+            next_seq: 0,
+            visited: Vec::new(),
+        }
+    }
+
+    // Returns false if the node was already visited in this transaction.
+    fn mark_visited(&mut self, node: &Node) -> bool {
+        if node.data.visited.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        self.visited.push(node.clone());
+        true
+    }
+
+    fn schedule_dependents(&mut self, node: &Node) {
+        let dependents = box_clone_vec_is_weak_node(&node.data.dependents.read());
+        for dependent in dependents.iter().flat_map(|dependent| dependent.upgrade()) {
+            if self.mark_visited(dependent.node()) {
+                let rank = dependent.data().rank.load(Ordering::SeqCst);
+                self.push(dependent.node().clone(), rank);
+            }
+        }
+    }
+
+    fn push(&mut self, node: Node, rank: u64) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.scheduled.push(ScheduledNode { rank, seq, node });
+    }
+
+    // Removes all the nodes sharing the lowest rank, in the order they were
+    // scheduled. A node whose rank went up after it was scheduled (through
+    // add_dependency) is put back under its new rank.
+    fn pop_lowest_rank(&mut self) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut rank_op: Option<u64> = None;
+        while let Some(next) = self.scheduled.peek() {
+            if let Some(rank) = rank_op {
+                if next.rank != rank {
+                    break;
                 }
             }
+            let next = self.scheduled.pop().unwrap();
+            let rank = next.node.data.rank.load(Ordering::SeqCst);
+            if rank != next.rank {
+                self.push(next.node, rank);
+                continue;
+            }
+            rank_op = Some(rank);
+            nodes.push(next.node);
         }
+        nodes
     }
 
-    pub fn collect_cycles(&self) {
-        self.gc_ctx.collect_cycles();
+    fn reset_visited(&mut self) {
+        for node in self.visited.drain(..) {
+            node.data.visited.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+// Also clears the visited flags when a node update panics.
+impl Drop for UpdateQueue {
+    fn drop(&mut self) {
+        self.reset_visited();
+    }
+}
+
+impl PartialEq for ScheduledNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.rank == other.rank && self.seq == other.seq
+    }
+}
+
+impl Eq for ScheduledNode {}
+
+impl PartialOrd for ScheduledNode {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, so the BinaryHeap pops the lowest rank first.
+impl Ord for ScheduledNode {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.rank, other.seq).cmp(&(self.rank, self.seq))
     }
 }
@@ -0,0 +1,120 @@
+use parking_lot::Mutex;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+/// What the work of closing a transaction came to, as collected by a
+/// context built with [`SodiumCtxBuilder::collect_stats`].
+///
+/// Transactions started from `post` callbacks count as transactions of
+/// their own, while the time they take also counts towards the
+/// `post_time` of the transaction that ran them.
+///
+/// [`SodiumCtxBuilder::collect_stats`]: crate::SodiumCtxBuilder::collect_stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransactionStats {
+    /// Nodes looked at while propagating changes.
+    pub nodes_visited: u64,
+    /// Nodes whose update function was run.
+    pub node_updates: u64,
+    /// Listener callbacks run.
+    pub listener_callbacks: u64,
+    /// Callbacks run after the transaction, as registered with
+    /// [`SodiumCtx::post`][crate::SodiumCtx::post].
+    pub post_callbacks: u64,
+    /// Possible roots of cycles looked at by the cycle collector.
+    pub gc_roots_scanned: u64,
+    /// Nodes freed by the cycle collector.
+    pub nodes_freed: u64,
+    /// Time spent running the callbacks due before the graph is
+    /// updated.
+    pub pre_eot_time: Duration,
+    /// Time spent updating the graph.
+    pub update_time: Duration,
+    /// Time spent clearing the values of streams that fired.
+    pub pre_post_time: Duration,
+    /// Time spent running `post` callbacks.
+    pub post_time: Duration,
+    /// Time spent collecting cycles.
+    pub gc_time: Duration,
+}
+
+impl AddAssign for TransactionStats {
+    fn add_assign(&mut self, other: TransactionStats) {
+        self.nodes_visited += other.nodes_visited;
+        self.node_updates += other.node_updates;
+        self.listener_callbacks += other.listener_callbacks;
+        self.post_callbacks += other.post_callbacks;
+        self.gc_roots_scanned += other.gc_roots_scanned;
+        self.nodes_freed += other.nodes_freed;
+        self.pre_eot_time += other.pre_eot_time;
+        self.update_time += other.update_time;
+        self.pre_post_time += other.pre_post_time;
+        self.post_time += other.post_time;
+        self.gc_time += other.gc_time;
+    }
+}
+
+/// The statistics of every transaction of a context so far, returned
+/// by [`SodiumCtx::stats`][crate::SodiumCtx::stats].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SodiumStats {
+    /// The number of transactions closed.
+    pub transactions: u64,
+    /// The statistics of all those transactions added together.
+    pub total: TransactionStats,
+    /// The number of roots waiting for the cycle collector now. This is
+    /// kept up to date whether or not statistics are being collected.
+    pub gc_roots_buffered: usize,
+}
+
+pub type OnTransactionStats = Box<dyn Fn(&TransactionStats) + Send + Sync>;
+
+pub struct StatsCollector {
+    pub stats: Mutex<SodiumStats>,
+    pub on_transaction: Option<OnTransactionStats>,
+}
+
+impl StatsCollector {
+    pub fn new(on_transaction: Option<OnTransactionStats>) -> StatsCollector {
+        StatsCollector {
+            stats: Mutex::new(SodiumStats::default()),
+            on_transaction,
+        }
+    }
+
+    pub fn add(&self, transaction_stats: &TransactionStats) {
+        {
+            let mut stats = self.stats.lock();
+            stats.transactions += 1;
+            stats.total += *transaction_stats;
+        }
+        if let Some(on_transaction) = &self.on_transaction {
+            on_transaction(transaction_stats);
+        }
+    }
+}
+
+// Measures the time between laps, if stats are being collected.
+pub struct Stopwatch {
+    last_op: Option<Instant>,
+}
+
+impl Stopwatch {
+    pub fn new(enabled: bool) -> Stopwatch {
+        Stopwatch {
+            last_op: enabled.then(Instant::now),
+        }
+    }
+
+    pub fn lap(&mut self) -> Duration {
+        match &mut self.last_op {
+            Some(last) => {
+                let now = Instant::now();
+                let lap = now - *last;
+                *last = now;
+                lap
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
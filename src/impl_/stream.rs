@@ -1,4 +1,5 @@
 use crate::impl_::cell::Cell;
+use crate::impl_::cell_loop::CellLoop;
 use crate::impl_::dep::Dep;
 use crate::impl_::lambda::IsLambda1;
 use crate::impl_::lambda::IsLambda2;
@@ -12,6 +13,7 @@ use crate::impl_::stream_sink::StreamSink;
 
 use parking_lot::Mutex;
 use parking_lot::RwLock;
+use std::fmt;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Weak;
@@ -325,6 +327,81 @@ impl<A: Send + 'static> Stream<A> {
         self.merge(s2, |lhs: &A, _rhs: &A| lhs.clone())
     }
 
+    pub fn calm_by<EQ: IsLambda2<A, A, bool> + Send + Sync + 'static>(
+        &self,
+        ca: &Cell<A>,
+        eq: EQ,
+    ) -> Stream<A>
+    where
+        A: Clone,
+    {
+        let ca = ca.clone();
+        let mut deps = lambda2_deps(&eq);
+        deps.push(Dep::new(ca.node().gc_node().clone()));
+        self._calm_by(move || Some(ca.sample()), eq, deps)
+    }
+
+    pub fn hold_calm_by<EQ: IsLambda2<A, A, bool> + Send + Sync + 'static>(
+        &self,
+        a: Lazy<A>,
+        eq: EQ,
+    ) -> Cell<A>
+    where
+        A: Clone,
+    {
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            // looped, so the calm stream keeps the cell it compares with
+            // even when only the cell's updates are kept
+            let cl = CellLoop::new(&sodium_ctx);
+            let c = self.calm_by(&cl.cell(), eq).hold_lazy(a);
+            cl.loop_(&c);
+            c
+        })
+    }
+
+    // Passes on the firings that aren't equal to the value `sample`
+    // gives. A stream that doesn't fire doesn't mark the nodes after it
+    // as changed, so they do no work.
+    fn _calm_by<
+        SAMPLE: FnMut() -> Option<A> + Send + Sync + 'static,
+        EQ: IsLambda2<A, A, bool> + Send + Sync + 'static,
+    >(
+        &self,
+        mut sample: SAMPLE,
+        mut eq: EQ,
+        deps: Vec<Dep>,
+    ) -> Stream<A>
+    where
+        A: Clone,
+    {
+        let self_ = self.clone();
+        let sodium_ctx = self.sodium_ctx();
+        Stream::_new(&sodium_ctx, |s: StreamWeakForwardRef<A>| {
+            let node = Node::new(
+                &sodium_ctx,
+                NodeName::STREAM_CALM,
+                move || {
+                    self_.with_firing_op(|firing_op: &mut Option<A>| {
+                        if let Some(ref firing) = firing_op {
+                            let is_calm = match sample() {
+                                Some(ref value) => eq.call(value, firing),
+                                None => false,
+                            };
+                            if !is_calm {
+                                s.unwrap()._send(firing.clone());
+                            }
+                        }
+                    });
+                },
+                vec![self.box_clone()],
+            );
+            node.add_update_dependencies(deps);
+            node.add_update_dependencies(vec![self.to_dep()]);
+            node
+        })
+    }
+
     pub fn merge<FN: IsLambda2<A, A, A> + Send + Sync + 'static>(
         &self,
         s2: &Stream<A>,
@@ -366,6 +443,129 @@ impl<A: Send + 'static> Stream<A> {
         })
     }
 
+    pub fn merge_all<FN: IsLambda2<A, A, A> + Send + Sync + 'static>(
+        sodium_ctx: &SodiumCtx,
+        streams: &[Stream<A>],
+        mut f: FN,
+    ) -> Stream<A>
+    where
+        A: Clone,
+    {
+        let sodium_ctx = sodium_ctx.clone();
+        let streams = streams.to_vec();
+        let stream_nodes: Vec<Box<dyn IsNode + Send + Sync>> =
+            streams.iter().map(|stream| stream.box_clone()).collect();
+        let stream_deps: Vec<Dep> = streams.iter().map(|stream| stream.to_dep()).collect();
+        Stream::_new(&sodium_ctx, |s: StreamWeakForwardRef<A>| {
+            let f_deps = lambda2_deps(&f);
+            let node = Node::new(
+                &sodium_ctx,
+                NodeName::STREAM_MERGE_ALL,
+                move || {
+                    // combined from left to right, as with chained merges
+                    let mut merged_op: Option<A> = None;
+                    for stream in &streams {
+                        stream.with_firing_op(|firing_op: &mut Option<A>| {
+                            if let Some(ref firing) = firing_op {
+                                merged_op = Some(match merged_op.take() {
+                                    Some(merged) => f.call(&merged, firing),
+                                    None => firing.clone(),
+                                });
+                            }
+                        });
+                    }
+                    if let Some(merged) = merged_op {
+                        s.unwrap()._send(merged);
+                    }
+                },
+                stream_nodes,
+            );
+            node.add_update_dependencies(f_deps);
+            node.add_update_dependencies(stream_deps);
+            node
+        })
+    }
+
+    pub fn merge_dynamic<FN: IsLambda2<A, A, A> + Send + Sync + 'static>(
+        csa: &Cell<Vec<Stream<A>>>,
+        mut f: FN,
+    ) -> Stream<A>
+    where
+        A: Clone,
+    {
+        let csa = csa.clone();
+        let sodium_ctx = csa.sodium_ctx();
+        Stream::_new(&sodium_ctx, |s: StreamWeakForwardRef<A>| {
+            let inner_ss: Arc<Mutex<Vec<WeakStream<A>>>> = Arc::new(Mutex::new(Vec::new()));
+            let f_deps = lambda2_deps(&f);
+            let node1: Node;
+            {
+                let inner_ss = inner_ss.clone();
+                node1 = Node::new(
+                    &sodium_ctx,
+                    NodeName::STREAM_MERGE_DYNAMIC_INNER,
+                    move || {
+                        // combined from left to right, as with merge_all
+                        let mut merged_op: Option<A> = None;
+                        for inner_s in inner_ss.lock().iter() {
+                            let inner_s = inner_s.upgrade().unwrap();
+                            inner_s.with_firing_op(|firing_op: &mut Option<A>| {
+                                if let Some(ref firing) = firing_op {
+                                    merged_op = Some(match merged_op.take() {
+                                        Some(merged) => f.call(&merged, firing),
+                                        None => firing.clone(),
+                                    });
+                                }
+                            });
+                        }
+                        if let Some(merged) = merged_op {
+                            s.unwrap()._send(merged);
+                        }
+                    },
+                    vec![],
+                );
+            }
+            node1.add_update_dependencies(f_deps);
+            {
+                let inner_ss = inner_ss.clone();
+                let csa = csa.clone();
+                let node1 = node1.clone();
+                sodium_ctx.pre_eot(move || {
+                    switch_merged_streams(&node1, &mut inner_ss.lock(), &csa.sample());
+                });
+            }
+            let node2: Node;
+            let csa_updates = csa.updates();
+            let csa_updates_node = csa_updates.box_clone();
+            let csa_updates_dep = csa_updates.to_dep();
+            {
+                let node1: Node = node1.clone();
+                let sodium_ctx = sodium_ctx.clone();
+                let sodium_ctx2 = sodium_ctx.clone();
+                node2 = Node::new(
+                    &sodium_ctx2,
+                    NodeName::STREAM_MERGE_DYNAMIC_OUTER,
+                    move || {
+                        csa_updates.with_firing_op(|firing_op: &mut Option<Vec<Stream<A>>>| {
+                            if let Some(ref firing) = firing_op {
+                                let firing = firing.clone();
+                                let node1 = node1.clone();
+                                let inner_ss = inner_ss.clone();
+                                sodium_ctx.pre_post(move || {
+                                    switch_merged_streams(&node1, &mut inner_ss.lock(), &firing);
+                                });
+                            }
+                        });
+                    },
+                    vec![csa_updates_node.box_clone()],
+                );
+            }
+            node2.add_update_dependencies(vec![csa_updates_dep, Dep::new(node1.gc_node().clone())]);
+            node1.add_dependency(node2);
+            node1
+        })
+    }
+
     pub fn hold(&self, a: A) -> Cell<A>
     where
         A: Clone,
@@ -437,6 +637,76 @@ impl<A: Send + 'static> Stream<A> {
         })
     }
 
+    pub fn execute_sync_io<B, F>(&self, f: F) -> Stream<B>
+    where
+        A: Clone,
+        B: Send + Clone + 'static,
+        F: FnMut(&A) -> B + Send + 'static,
+    {
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            let ss = StreamSink::new(&sodium_ctx);
+            let s = ss.stream();
+            let sodium_ctx = sodium_ctx.clone();
+            let ss = StreamSink::downgrade(&ss);
+            let f = Arc::new(Mutex::new(f));
+            let listener = self.listen_weak(move |a: &A| {
+                let ss = ss.clone();
+                let f = f.clone();
+                let mut a_op = Some(a.clone());
+                sodium_ctx.post(move || {
+                    if let Some(a) = a_op.take() {
+                        let b = (f.lock())(&a);
+                        if let Some(ss) = ss.upgrade() {
+                            ss.send(b);
+                        }
+                    }
+                })
+            });
+            s.add_keep_alive(&listener.gc_node);
+            s
+        })
+    }
+
+    pub fn execute_async_io<B, EXECUTE, F>(&self, execute: EXECUTE, f: F) -> Stream<B>
+    where
+        A: Clone,
+        B: Send + Clone + 'static,
+        EXECUTE: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static,
+        F: Fn(&A) -> B + Send + Sync + 'static,
+    {
+        let sodium_ctx = self.sodium_ctx();
+        sodium_ctx.transaction(|| {
+            let ss = StreamSink::new(&sodium_ctx);
+            let s = ss.stream();
+            let sodium_ctx = sodium_ctx.clone();
+            let ss = StreamSink::downgrade(&ss);
+            let execute = Arc::new(execute);
+            let f = Arc::new(f);
+            let listener = self.listen_weak(move |a: &A| {
+                let ss = ss.clone();
+                let execute = execute.clone();
+                let f = f.clone();
+                let mut a_op = Some(a.clone());
+                // the io is only started once the transaction is over
+                sodium_ctx.post(move || {
+                    if let Some(a) = a_op.take() {
+                        let ss = ss.clone();
+                        let f = f.clone();
+                        execute(Box::new(move || {
+                            let b = f(&a);
+                            if let Some(ss) = ss.upgrade() {
+                                ss.send(b);
+                            }
+                        }));
+                    }
+                })
+            });
+            s.add_keep_alive(&listener.gc_node);
+            s
+        })
+    }
+
     pub fn once(&self) -> Stream<A>
     where
         A: Clone,
@@ -488,7 +758,7 @@ impl<A: Send + 'static> Stream<A> {
                 NodeName::STREAM_LISTEN,
                 move || {
                     self_.with_data(|data: &mut StreamData<A>| {
-                        for firing in &data.firing_op {
+                        if let Some(firing) = &data.firing_op {
                             k.call(firing)
                         }
                     });
@@ -541,6 +811,18 @@ impl<A: Send + 'static> Stream<A> {
         });
     }
 
+    pub fn debug_values(&self)
+    where
+        A: fmt::Debug,
+    {
+        let data = Arc::downgrade(&self.data);
+        *self.node().data.debug_value.write() = Some(Box::new(move || {
+            let data = data.upgrade()?;
+            let data = data.lock();
+            data.firing_op.as_ref().map(|a| format!("{:?}", a))
+        }));
+    }
+
     pub fn downgrade(this: &Self) -> WeakStream<A> {
         WeakStream {
             data: Arc::downgrade(&this.data),
@@ -561,3 +843,27 @@ impl<A> WeakStream<A> {
         Some(Stream { data, node })
     }
 }
+
+// Makes the merge_dynamic inner node depend on `streams` in place of the
+// streams it merged before.
+fn switch_merged_streams<A: Send + 'static>(
+    node: &Node,
+    inner_ss: &mut Vec<WeakStream<A>>,
+    streams: &[Stream<A>],
+) {
+    for inner_s in inner_ss.iter() {
+        if let Some(inner_s) = inner_s.upgrade() {
+            node.remove_dependency(&inner_s);
+        }
+    }
+    for (i, stream) in streams.iter().enumerate() {
+        // a stream listed twice fires twice, but is only depended on once
+        if !streams[..i]
+            .iter()
+            .any(|s| Arc::ptr_eq(s.data(), stream.data()))
+        {
+            node.add_dependency(stream.clone());
+        }
+    }
+    *inner_ss = streams.iter().map(Stream::downgrade).collect();
+}
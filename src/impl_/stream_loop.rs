@@ -1,9 +1,11 @@
 use crate::impl_::gc_node::{GcNode, Tracer};
 use crate::impl_::node::IsNode;
-use crate::impl_::sodium_ctx::SodiumCtx;
+use crate::impl_::sodium_ctx::{SodiumCtx, SodiumCtxData};
 use crate::impl_::stream::Stream;
 
 use parking_lot::Mutex;
+use std::error::Error;
+use std::fmt;
 use std::sync::Arc;
 
 use super::name::NodeName;
@@ -19,6 +21,47 @@ pub struct StreamLoopData<A> {
     pub looped: bool,
 }
 
+/// A mistake in the use of a [`StreamLoop`][crate::StreamLoop] or
+/// [`CellLoop`][crate::CellLoop].
+///
+/// Each variant carries a description of the loop, with its label and
+/// the source location it was created at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoopError {
+    /// `loop_` was called on a loop that had already been looped.
+    AlreadyLooped { description: String },
+    /// The loop was looped to a stream or cell from another
+    /// [`SodiumCtx`][crate::SodiumCtx].
+    WrongContext { description: String },
+    /// The transaction the loop was created in closed before the loop
+    /// was looped.
+    NotLooped { description: String },
+}
+
+impl fmt::Display for LoopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoopError::AlreadyLooped { description } => {
+                write!(f, "{} was already looped", description)
+            }
+            LoopError::WrongContext { description } => {
+                write!(
+                    f,
+                    "{} was looped to a node of another SodiumCtx",
+                    description
+                )
+            }
+            LoopError::NotLooped { description } => write!(
+                f,
+                "{} was not looped before the transaction it was created in closed",
+                description
+            ),
+        }
+    }
+}
+
+impl Error for LoopError {}
+
 impl<A> Clone for StreamLoop<A> {
     fn clone(&self) -> Self {
         self.gc_node.inc_ref();
@@ -37,6 +80,10 @@ impl<A> Drop for StreamLoop<A> {
 
 impl<A: Clone + Send + 'static> StreamLoop<A> {
     pub fn new(sodium_ctx: &SodiumCtx) -> StreamLoop<A> {
+        StreamLoop::new_with_name(sodium_ctx, NodeName::STREAM_LOOP_NEW)
+    }
+
+    pub fn new_with_name(sodium_ctx: &SodiumCtx, name: NodeName) -> StreamLoop<A> {
         let stream_loop_data = Arc::new(Mutex::new(StreamLoopData {
             stream: Stream::new(sodium_ctx),
             looped: false,
@@ -68,14 +115,33 @@ impl<A: Clone + Send + 'static> StreamLoop<A> {
                 tracer(stream_loop_data.stream.gc_node());
             };
         }
+        let gc_node = GcNode::new(
+            &sodium_ctx.gc_ctx(),
+            name,
+            gc_node_destructor,
+            gc_node_trace,
+        );
+        // A loop created inside a transaction has to be looped before that
+        // transaction closes. Loops created outside of one can't be
+        // checked. The check runs after the update, so that it covers
+        // loops created by lambdas and listeners too.
+        if sodium_ctx.with_data(|data: &mut SodiumCtxData| data.transaction_depth > 0) {
+            let stream_loop_data = stream_loop_data.clone();
+            let gc_node = gc_node.clone();
+            sodium_ctx.after_update(move || {
+                if !stream_loop_data.lock().looped {
+                    panic!(
+                        "{}",
+                        LoopError::NotLooped {
+                            description: gc_node.description()
+                        }
+                    );
+                }
+            });
+        }
         StreamLoop {
             data: stream_loop_data,
-            gc_node: GcNode::new(
-                &sodium_ctx.gc_ctx(),
-                NodeName::STREAM_LOOP_NEW,
-                gc_node_destructor,
-                gc_node_trace,
-            ),
+            gc_node,
         }
     }
 
@@ -84,9 +150,22 @@ impl<A: Clone + Send + 'static> StreamLoop<A> {
     }
 
     pub fn loop_(&self, s: &Stream<A>) {
+        if let Err(err) = self.try_loop_(s) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn try_loop_(&self, s: &Stream<A>) -> Result<(), LoopError> {
         self.with_data(|data: &mut StreamLoopData<A>| {
             if data.looped {
-                panic!("StreamLoop already looped.");
+                return Err(LoopError::AlreadyLooped {
+                    description: self.gc_node.description(),
+                });
+            }
+            if !data.stream.sodium_ctx().ptr_eq(&s.sodium_ctx()) {
+                return Err(LoopError::WrongContext {
+                    description: self.gc_node.description(),
+                });
             }
             data.looped = true;
             data.stream.add_dependency(s.clone());
@@ -103,6 +182,7 @@ impl<A: Clone + Send + 'static> StreamLoop<A> {
                     });
                 });
             }
+            Ok(())
         })
     }
 
@@ -25,6 +25,15 @@ impl<A> Clone for StreamSink<A> {
     }
 }
 
+impl<A> Clone for WeakStreamSink<A> {
+    fn clone(&self) -> Self {
+        WeakStreamSink {
+            stream: self.stream.clone(),
+            sodium_ctx: self.sodium_ctx.clone(),
+        }
+    }
+}
+
 impl<A: Send + 'static> StreamSink<A> {
     pub fn new(sodium_ctx: &SodiumCtx) -> StreamSink<A> {
         StreamSink {
@@ -52,6 +61,12 @@ impl<A: Send + 'static> StreamSink<A> {
     }
 
     pub fn send(&self, a: A) {
+        if self.sodium_ctx.is_propagating() {
+            panic!(
+                "StreamSink::send called from a lambda or listener while its SodiumCtx was \
+                 updating the graph; use send_later to send in the next transaction"
+            );
+        }
         self.sodium_ctx.transaction(|| {
             let node = self.stream_ref();
             node.data().changed.store(true, Ordering::SeqCst);
@@ -62,6 +77,11 @@ impl<A: Send + 'static> StreamSink<A> {
         });
     }
 
+    pub fn send_later(&self, a: A) {
+        let self_ = self.clone();
+        self.sodium_ctx.send_later(move || self_.send(a));
+    }
+
     pub fn downgrade(this: &Self) -> WeakStreamSink<A> {
         WeakStreamSink {
             stream: Stream::downgrade(&this.stream),
@@ -0,0 +1,131 @@
+use parking_lot::Condvar;
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+type PanicPayload = Box<dyn Any + Send>;
+
+pub struct ThreadPool {
+    shared: Arc<ThreadPoolShared>,
+}
+
+struct ThreadPoolShared {
+    queue: Mutex<ThreadPoolQueue>,
+    queue_cond: Condvar,
+}
+
+struct ThreadPoolQueue {
+    tasks: VecDeque<Arc<Task>>,
+    shutdown: bool,
+}
+
+pub struct Task {
+    job_op: Mutex<Option<Job>>,
+    result_op: Mutex<Option<Result<(), PanicPayload>>>,
+    result_cond: Condvar,
+}
+
+impl ThreadPool {
+    pub fn new(num_threads: usize) -> ThreadPool {
+        if num_threads == 0 {
+            panic!("ThreadPool requires at least one thread.");
+        }
+        let shared = Arc::new(ThreadPoolShared {
+            queue: Mutex::new(ThreadPoolQueue {
+                tasks: VecDeque::new(),
+                shutdown: false,
+            }),
+            queue_cond: Condvar::new(),
+        });
+        for i in 0..num_threads {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("sodium-worker-{}", i))
+                .spawn(move || shared.run_worker())
+                .expect("failed to spawn sodium worker thread");
+        }
+        ThreadPool { shared }
+    }
+
+    pub fn spawn(&self, job: Job) -> Arc<Task> {
+        let task = Arc::new(Task {
+            job_op: Mutex::new(Some(job)),
+            result_op: Mutex::new(None),
+            result_cond: Condvar::new(),
+        });
+        {
+            let mut queue = self.shared.queue.lock();
+            queue.tasks.push_back(task.clone());
+        }
+        self.shared.queue_cond.notify_one();
+        task
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        {
+            let mut queue = self.shared.queue.lock();
+            queue.shutdown = true;
+        }
+        self.shared.queue_cond.notify_all();
+    }
+}
+
+impl ThreadPoolShared {
+    fn run_worker(&self) {
+        loop {
+            let task;
+            {
+                let mut queue = self.queue.lock();
+                loop {
+                    if let Some(task2) = queue.tasks.pop_front() {
+                        task = task2;
+                        break;
+                    }
+                    if queue.shutdown {
+                        return;
+                    }
+                    self.queue_cond.wait(&mut queue);
+                }
+            }
+            task.run();
+        }
+    }
+}
+
+impl Task {
+    fn run(&self) -> bool {
+        let job_op = self.job_op.lock().take();
+        if let Some(job) = job_op {
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            let mut result_op = self.result_op.lock();
+            *result_op = Some(result);
+            self.result_cond.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+
+    // If no worker has picked up the task yet, it is run on the calling
+    // thread instead. This means a join from inside a worker never waits
+    // on a task that is still sitting in the queue, so nested spawns can
+    // not starve the pool.
+    pub fn join(&self) {
+        self.run();
+        let mut result_op = self.result_op.lock();
+        while result_op.is_none() {
+            self.result_cond.wait(&mut result_op);
+        }
+        if let Some(Err(payload)) = result_op.take() {
+            panic::resume_unwind(payload);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::impl_::cell::Cell;
+use crate::impl_::cell_sink::CellSink;
+use crate::impl_::node::IsNodeExt;
+use crate::impl_::sodium_ctx::SodiumCtx;
+use crate::impl_::stream::Stream;
+use crate::impl_::stream_sink::{StreamSink, WeakStreamSink};
+
+use parking_lot::{Condvar, Mutex};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub type Time = Duration;
+
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Time;
+
+    // Called whenever an alarm is set, so a clock that waits for the next
+    // alarm can wake up early.
+    fn alarms_changed(&self) {}
+}
+
+type AlarmKey = (Time, u64);
+
+#[derive(Clone)]
+pub struct TimerSystem {
+    pub sodium_ctx: SodiumCtx,
+    pub data: Arc<TimerSystemData>,
+}
+
+pub struct TimerSystemData {
+    clock: Box<dyn Clock>,
+    time: CellSink<Time>,
+    alarms: Mutex<Alarms>,
+}
+
+struct Alarms {
+    queue: BTreeMap<AlarmKey, WeakStreamSink<Time>>,
+    next_seq: u64,
+    last_time: Time,
+}
+
+impl TimerSystem {
+    pub fn new<CLOCK: Clock>(sodium_ctx: &SodiumCtx, clock: CLOCK) -> TimerSystem {
+        let now = clock.now();
+        let data = Arc::new(TimerSystemData {
+            clock: Box::new(clock),
+            time: CellSink::new(sodium_ctx, now),
+            alarms: Mutex::new(Alarms {
+                queue: BTreeMap::new(),
+                next_seq: 0,
+                last_time: now,
+            }),
+        });
+        {
+            let data = Arc::downgrade(&data);
+            sodium_ctx.on_start(move || {
+                if let Some(data) = data.upgrade() {
+                    data.fire_due_alarms();
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+        TimerSystem {
+            sodium_ctx: sodium_ctx.clone(),
+            data,
+        }
+    }
+
+    pub fn time(&self) -> Cell<Time> {
+        self.data.time.cell()
+    }
+
+    pub fn at(&self, alarm: &Cell<Option<Time>>) -> Stream<Time> {
+        let sodium_ctx = &self.sodium_ctx;
+        sodium_ctx.transaction(|| {
+            let ss = StreamSink::new(sodium_ctx);
+            let s = ss.stream();
+            let ss = StreamSink::downgrade(&ss);
+            let sodium_ctx = sodium_ctx.clone();
+            let data = self.data.clone();
+            let mut current_op: Option<AlarmKey> = None;
+            let listener = alarm.value().listen_weak(move |t_op: &Option<Time>| {
+                if let Some(current) = current_op.take() {
+                    data.alarms.lock().queue.remove(&current);
+                }
+                if let Some(t) = *t_op {
+                    current_op = Some(data.set_alarm(&sodium_ctx, t, ss.clone()));
+                }
+            });
+            s.add_keep_alive(&listener.gc_node);
+            s
+        })
+    }
+
+    pub fn now(&self) -> Time {
+        self.data.clock.now()
+    }
+
+    pub fn next_alarm(&self) -> Option<Time> {
+        self.data.next_alarm()
+    }
+}
+
+impl TimerSystemData {
+    fn set_alarm(&self, sodium_ctx: &SodiumCtx, t: Time, ss: WeakStreamSink<Time>) -> AlarmKey {
+        let key;
+        {
+            let mut alarms = self.alarms.lock();
+            key = (t, alarms.next_seq);
+            alarms.next_seq += 1;
+            alarms.queue.insert(key, ss);
+        }
+        if t <= self.clock.now() {
+            // already due, so fire it straight after this transaction
+            let sodium_ctx2 = sodium_ctx.clone();
+            sodium_ctx.post(move || sodium_ctx2.transaction(|| {}));
+        } else {
+            self.clock.alarms_changed();
+        }
+        key
+    }
+
+    fn next_alarm(&self) -> Option<Time> {
+        let alarms = self.alarms.lock();
+        alarms.queue.keys().next().map(|(t, _)| *t)
+    }
+
+    // Each due alarm fires in a transaction of its own, straight after the
+    // time cell has been moved forward to the time of the alarm.
+    fn fire_due_alarms(self: &Arc<Self>) {
+        loop {
+            let now = self.clock.now();
+            let due_op = {
+                let mut alarms = self.alarms.lock();
+                let key_op = alarms.queue.keys().next().copied();
+                match key_op {
+                    Some(key) if key.0 <= now => {
+                        let ss = alarms.queue.remove(&key).unwrap();
+                        let advance_time = key.0 > alarms.last_time;
+                        if advance_time {
+                            alarms.last_time = key.0;
+                        }
+                        Some((key, ss, advance_time))
+                    }
+                    _ => None,
+                }
+            };
+            let (key, weak_ss, advance_time) = match due_op {
+                Some(due) => due,
+                None => break,
+            };
+            let t = key.0;
+            if advance_time {
+                self.time.send(t);
+            }
+            if let Some(ss) = weak_ss.upgrade() {
+                let sodium_ctx = ss.stream_ref().sodium_ctx();
+                sodium_ctx.transaction(|| {
+                    // if the alarm's transaction is rolled back it hasn't
+                    // fired, so it is put back to fire next time
+                    let data = self.clone();
+                    sodium_ctx.on_rollback(move || {
+                        data.alarms.lock().queue.insert(key, weak_ss.clone());
+                    });
+                    ss.send(t);
+                });
+            }
+        }
+        let now = self.clock.now();
+        let advance_time = {
+            let mut alarms = self.alarms.lock();
+            let advance_time = now > alarms.last_time;
+            if advance_time {
+                alarms.last_time = now;
+            }
+            advance_time
+        };
+        if advance_time {
+            self.time.send(now);
+        }
+    }
+}
+
+pub struct VirtualClock {
+    now: Arc<Mutex<Time>>,
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Time {
+        *self.now.lock()
+    }
+}
+
+#[derive(Clone)]
+pub struct VirtualTimerSystem {
+    pub timer_system: TimerSystem,
+    now: Arc<Mutex<Time>>,
+}
+
+impl VirtualTimerSystem {
+    pub fn new(sodium_ctx: &SodiumCtx) -> VirtualTimerSystem {
+        let now = Arc::new(Mutex::new(Duration::ZERO));
+        let clock = VirtualClock { now: now.clone() };
+        VirtualTimerSystem {
+            timer_system: TimerSystem::new(sodium_ctx, clock),
+            now,
+        }
+    }
+
+    pub fn advance_to(&self, t: Time) {
+        // Stop at every alarm on the way, so each one sees the time cell at
+        // its own time, and alarms set while firing are not skipped.
+        while let Some(next) = self.timer_system.next_alarm() {
+            if next > t {
+                break;
+            }
+            self.set_now(next);
+            self.timer_system.sodium_ctx.transaction(|| {});
+        }
+        self.set_now(t);
+        self.timer_system.sodium_ctx.transaction(|| {});
+    }
+
+    fn set_now(&self, t: Time) {
+        let mut now = self.now.lock();
+        if t > *now {
+            *now = t;
+        }
+    }
+}
+
+pub struct MillisecondsClock {
+    wake_up: Arc<WakeUp>,
+}
+
+struct WakeUp {
+    state: Mutex<WakeUpState>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct WakeUpState {
+    alarms_changed: bool,
+    shutdown: bool,
+}
+
+pub fn milliseconds_since_epoch() -> Time {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    Duration::from_millis(since_epoch.as_millis() as u64)
+}
+
+impl Clock for MillisecondsClock {
+    fn now(&self) -> Time {
+        milliseconds_since_epoch()
+    }
+
+    fn alarms_changed(&self) {
+        let mut state = self.wake_up.state.lock();
+        state.alarms_changed = true;
+        self.wake_up.cond.notify_all();
+    }
+}
+
+impl Drop for MillisecondsClock {
+    fn drop(&mut self) {
+        let mut state = self.wake_up.state.lock();
+        state.shutdown = true;
+        self.wake_up.cond.notify_all();
+    }
+}
+
+pub fn milliseconds_timer_system(sodium_ctx: &SodiumCtx) -> TimerSystem {
+    let wake_up = Arc::new(WakeUp {
+        state: Mutex::new(WakeUpState::default()),
+        cond: Condvar::new(),
+    });
+    let timer_system = TimerSystem::new(
+        sodium_ctx,
+        MillisecondsClock {
+            wake_up: wake_up.clone(),
+        },
+    );
+    let data = Arc::downgrade(&timer_system.data);
+    let sodium_ctx = sodium_ctx.clone();
+    thread::Builder::new()
+        .name("sodium-timer".into())
+        .spawn(move || run_milliseconds_timer(sodium_ctx, data, wake_up))
+        .expect("failed to spawn sodium timer thread");
+    timer_system
+}
+
+// The thread only holds on to the timer system while looking at it, so it
+// exits once the timer system and every alarm stream using it are gone.
+fn run_milliseconds_timer(
+    sodium_ctx: SodiumCtx,
+    data: Weak<TimerSystemData>,
+    wake_up: Arc<WakeUp>,
+) {
+    loop {
+        let wait_op;
+        {
+            let data = match data.upgrade() {
+                Some(data) => data,
+                None => return,
+            };
+            let now = data.clock.now();
+            match data.next_alarm() {
+                Some(next) if next <= now => {
+                    // the alarm fires when the transaction starts
+                    sodium_ctx.transaction(|| {});
+                    continue;
+                }
+                Some(next) => wait_op = Some(next - now),
+                None => wait_op = None,
+            }
+        }
+        let mut state = wake_up.state.lock();
+        if state.shutdown {
+            return;
+        }
+        if state.alarms_changed {
+            state.alarms_changed = false;
+            continue;
+        }
+        match wait_op {
+            Some(wait) => {
+                wake_up.cond.wait_for(&mut state, wait);
+            }
+            None => wake_up.cond.wait(&mut state),
+        }
+        state.alarms_changed = false;
+    }
+}
@@ -0,0 +1,105 @@
+// Spans and events for the optional `tracing` feature. Without the
+// feature these are all empty, so the call sites don't need any cfgs.
+
+use crate::impl_::node::Node;
+
+#[cfg(feature = "tracing")]
+pub use self::enabled::*;
+
+#[cfg(not(feature = "tracing"))]
+pub use self::disabled::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use super::Node;
+
+    // The span of a top level transaction. A transaction can be opened
+    // and closed from different functions (see Transaction), so the span
+    // is entered and exited by hand rather than with a guard.
+    pub struct TransactionSpan {
+        span: tracing::Span,
+    }
+
+    impl TransactionSpan {
+        pub fn enter() -> TransactionSpan {
+            let span = tracing::debug_span!("transaction");
+            if let Some(id) = span.id() {
+                tracing::dispatcher::get_default(|dispatch| dispatch.enter(&id));
+            }
+            TransactionSpan { span }
+        }
+
+        pub fn exit(self) {
+            if let Some(id) = self.span.id() {
+                tracing::dispatcher::get_default(|dispatch| dispatch.exit(&id));
+            }
+        }
+    }
+
+    // The span nodes are updated in, which worker threads have to be told
+    // about explicitly.
+    pub type Parent = tracing::Span;
+
+    pub fn current() -> Parent {
+        tracing::Span::current()
+    }
+
+    pub type UpdateNodeSpan = tracing::span::EnteredSpan;
+
+    pub fn update_node(node: &Node, parent: &Parent) -> UpdateNodeSpan {
+        tracing::trace_span!(
+            parent: parent,
+            "update_node",
+            id = node.gc_node.id(),
+            name = %node.gc_node.name(),
+            label = node.gc_node.label().as_deref(),
+        )
+        .entered()
+    }
+
+    pub fn phase(phase: &'static str, callbacks: usize) {
+        tracing::trace!(callbacks, "{}", phase);
+    }
+
+    pub fn collect_cycles(node_count: usize) {
+        tracing::debug!(node_count, "collect_cycles");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use super::Node;
+
+    pub struct TransactionSpan;
+
+    impl TransactionSpan {
+        #[inline]
+        pub fn enter() -> TransactionSpan {
+            TransactionSpan
+        }
+
+        #[inline]
+        pub fn exit(self) {}
+    }
+
+    #[derive(Clone)]
+    pub struct Parent;
+
+    #[inline]
+    pub fn current() -> Parent {
+        Parent
+    }
+
+    pub struct UpdateNodeSpan;
+
+    #[inline]
+    pub fn update_node(_node: &Node, _parent: &Parent) -> UpdateNodeSpan {
+        UpdateNodeSpan
+    }
+
+    #[inline]
+    pub fn phase(_phase: &'static str, _callbacks: usize) {}
+
+    #[inline]
+    pub fn collect_cycles(_node_count: usize) {}
+}
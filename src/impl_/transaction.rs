@@ -25,6 +25,11 @@ impl Transaction {
 
 impl Drop for Transaction {
     fn drop(&mut self) {
-        self.close();
+        if std::thread::panicking() && !self.done.get() {
+            self.sodium_ctx.abandon_transaction();
+            self.done.set(true);
+        } else {
+            self.close();
+        }
     }
 }
@@ -0,0 +1,377 @@
+//! Queries on the structure of the graph of a [`SodiumCtx`].
+//!
+//! [`SodiumCtx::graph`] takes a [`Graph`], a snapshot of the nodes and
+//! listeners alive at the time, for tools and tests to look at. The
+//! context has to be built with
+//! [`SodiumCtxBuilder::track_nodes`][crate::SodiumCtxBuilder::track_nodes]
+//! for it to know which nodes are alive. Each
+//! node comes with its kind, its label and source location (see
+//! [`Stream::named`]), the nodes it depends on and that depend on it,
+//! and how many listeners are attached to it.
+//!
+//! Values are only included for nodes that opted in with
+//! [`Stream::debug_values`] or [`Cell::debug_values`], as formatting
+//! them needs `Debug`.
+//!
+//! [`SodiumCtx::check_leaks`] and the
+//! [`assert_no_leaks!`][crate::assert_no_leaks] macro check that
+//! nothing is left of a graph once it should have been dropped, and
+//! say what is holding on to whatever is.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::panic::Location;
+use std::sync::Arc;
+
+use crate::impl_::gc_node::GcNode;
+
+use crate::Cell;
+use crate::SodiumCtx;
+use crate::Stream;
+
+pub use crate::impl_::name::Cell as CellNodeName;
+pub use crate::impl_::name::NodeName;
+pub use crate::impl_::name::Stream as StreamNodeName;
+
+/// The id of a node or listener, unique within its [`SodiumCtx`].
+pub type NodeId = u32;
+
+/// A snapshot of the live nodes and listeners of a [`SodiumCtx`].
+#[derive(Clone, Debug)]
+pub struct Graph {
+    /// The live nodes, ordered by id.
+    pub nodes: Vec<NodeInfo>,
+    /// The live listeners, ordered by id.
+    pub listeners: Vec<ListenerInfo>,
+}
+
+/// A node of a [`Graph`].
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    /// The id of the node, as also shown by [`SodiumCtx::export_dot`].
+    pub id: NodeId,
+    /// What kind of node this is, for instance [`NodeName::STREAM_MAP`].
+    pub name: NodeName,
+    /// The label given with [`Stream::named`] or [`Cell::named`].
+    pub label: Option<String>,
+    /// Where the node was created, if it was created by a call from
+    /// outside this crate.
+    pub location: Option<&'static Location<'static>>,
+    /// The nodes this node is updated from.
+    pub dependencies: Vec<NodeId>,
+    /// The live nodes updated from this node.
+    pub dependents: Vec<NodeId>,
+    /// The number of live listeners attached directly to this node.
+    pub listener_count: usize,
+    /// The `Debug` form of the value of a cell, or of the value a
+    /// stream is firing with, if the node opted in with
+    /// [`Stream::debug_values`] or [`Cell::debug_values`]. A stream that
+    /// is not firing has no value.
+    pub value: Option<String>,
+}
+
+/// A listener of a [`Graph`].
+#[derive(Clone, Debug)]
+pub struct ListenerInfo {
+    /// The id of the listener.
+    pub id: NodeId,
+    /// Whether the listener was made with `listen_weak`.
+    pub is_weak: bool,
+    /// The node this listener is attached to, or `None` once it has
+    /// been unlistened.
+    pub node: Option<NodeId>,
+    /// Where the listener was created.
+    pub location: Option<&'static Location<'static>>,
+}
+
+impl Graph {
+    pub(crate) fn new(sodium_ctx: &SodiumCtx) -> Graph {
+        let live_nodes = sodium_ctx.impl_.live_nodes();
+        let live_listeners = sodium_ctx.impl_.live_listeners();
+        let listeners: Vec<ListenerInfo> = live_listeners
+            .iter()
+            .map(|(gc_node, data)| {
+                let data = data.lock();
+                // A listener holds on to the node running its callback,
+                // which depends on the node listened to.
+                let node = data.node_op.as_ref().and_then(|node| {
+                    node.data
+                        .dependencies
+                        .read()
+                        .first()
+                        .map(|dependency| dependency.gc_node().id())
+                });
+                ListenerInfo {
+                    id: gc_node.id(),
+                    is_weak: data.is_weak,
+                    node,
+                    location: gc_node.location(),
+                }
+            })
+            .collect();
+        let nodes = live_nodes
+            .iter()
+            .map(|(gc_node, data)| {
+                let id = gc_node.id();
+                let dependencies = data
+                    .dependencies
+                    .read()
+                    .iter()
+                    .map(|dependency| dependency.gc_node().id())
+                    .collect();
+                let dependents = data
+                    .dependents
+                    .read()
+                    .iter()
+                    .filter(|dependent| dependent.data().strong_count() > 0)
+                    .map(|dependent| dependent.gc_node().id())
+                    .collect();
+                let value = data.debug_value.read().as_ref().and_then(|f| f());
+                NodeInfo {
+                    id,
+                    name: gc_node.name(),
+                    label: gc_node.label().map(|label: Arc<str>| label.to_string()),
+                    location: gc_node.location(),
+                    dependencies,
+                    dependents,
+                    listener_count: listeners
+                        .iter()
+                        .filter(|listener| listener.node == Some(id))
+                        .count(),
+                    value,
+                }
+            })
+            .collect();
+        Graph { nodes, listeners }
+    }
+
+    /// Return the node with the given id.
+    pub fn node(&self, id: NodeId) -> Option<&NodeInfo> {
+        self.nodes
+            .binary_search_by_key(&id, |node| node.id)
+            .ok()
+            .map(|i| &self.nodes[i])
+    }
+
+    /// Return the node behind the given stream, if it was alive when
+    /// the snapshot was taken.
+    pub fn stream<A>(&self, s: &Stream<A>) -> Option<&NodeInfo> {
+        self.node(s.impl_.node().gc_node.id())
+    }
+
+    /// Return the node behind the given cell, if it was alive when the
+    /// snapshot was taken.
+    pub fn cell<A>(&self, c: &Cell<A>) -> Option<&NodeInfo> {
+        self.node(c.impl_.node().gc_node.id())
+    }
+
+    /// Return the nodes with the given label.
+    pub fn labelled<'a>(&'a self, label: &'a str) -> impl Iterator<Item = &'a NodeInfo> + 'a {
+        self.nodes
+            .iter()
+            .filter(move |node| node.label.as_deref() == Some(label))
+    }
+
+    /// Return the nodes of the given kind.
+    pub fn named(&self, name: NodeName) -> impl Iterator<Item = &NodeInfo> + '_ {
+        self.nodes.iter().filter(move |node| node.name == name)
+    }
+}
+
+/// The nodes and listeners still alive in a [`SodiumCtx`] that was
+/// expected to be empty, returned by [`SodiumCtx::check_leaks`].
+///
+/// Listeners that have been unlistened don't count, as they no longer
+/// hold on to anything.
+///
+/// Its `Display` form lists each of them, with the chain of references
+/// keeping it alive. For a context not built with
+/// [`SodiumCtxBuilder::track_nodes`][crate::SodiumCtxBuilder::track_nodes]
+/// it only gives the number of nodes left, and says that the retention
+/// paths are unavailable.
+#[derive(Clone, Debug)]
+pub struct LeakReport {
+    /// The surviving nodes and listeners, ordered by id. Empty if the
+    /// context doesn't track its nodes.
+    pub leaks: Vec<Leak>,
+    /// The number of surviving nodes, counted whether the context
+    /// tracks its nodes or not. Listeners aren't counted.
+    pub node_count: usize,
+    descriptions: HashMap<NodeId, String>,
+}
+
+/// A node or listener found by [`SodiumCtx::check_leaks`].
+#[derive(Clone, Debug)]
+pub struct Leak {
+    /// The id of the node or listener.
+    pub id: NodeId,
+    /// What kind of node this is.
+    pub name: NodeName,
+    /// The label given with [`Stream::named`] or [`Cell::named`].
+    pub label: Option<String>,
+    /// Where the node was created.
+    pub location: Option<&'static Location<'static>>,
+    /// The chain of references keeping the node alive, starting from
+    /// something held outside the graph (such as a listener, a sink or
+    /// a loop that has not been dropped) and ending with the node that
+    /// refers to this one. Empty if the node is itself held from
+    /// outside, or if no such chain was found.
+    pub retained_by: Vec<NodeId>,
+}
+
+impl LeakReport {
+    pub(crate) fn new(sodium_ctx: &SodiumCtx) -> LeakReport {
+        let node_count = sodium_ctx.impl_.node_count();
+        let mut gc_nodes: Vec<GcNode> = sodium_ctx
+            .impl_
+            .live_nodes()
+            .into_iter()
+            .map(|(gc_node, _)| gc_node)
+            .chain(
+                sodium_ctx
+                    .impl_
+                    .live_listeners()
+                    .into_iter()
+                    // an unlistened listener holds on to nothing
+                    .filter(|(_, data)| data.lock().node_op.is_some())
+                    .map(|(gc_node, _)| gc_node),
+            )
+            .collect();
+        gc_nodes.sort_by_key(|gc_node| gc_node.id());
+        // Anything referred to more often than the graph itself refers to
+        // it is held from outside the graph.
+        let mut references: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        let mut internal_ref_counts: HashMap<NodeId, u32> = HashMap::new();
+        for gc_node in &gc_nodes {
+            let mut children = Vec::new();
+            gc_node.trace(|child: &GcNode| {
+                children.push(child.id());
+                *internal_ref_counts.entry(child.id()).or_insert(0) += 1;
+            });
+            references.insert(gc_node.id(), children);
+        }
+        let mut queue: VecDeque<NodeId> = gc_nodes
+            .iter()
+            .filter(|gc_node| {
+                gc_node.ref_count() > internal_ref_counts.get(&gc_node.id()).copied().unwrap_or(0)
+            })
+            .map(|gc_node| gc_node.id())
+            .collect();
+        // Breadth first from those, so the chains found are the shortest.
+        let mut retained_by: HashMap<NodeId, Option<NodeId>> =
+            queue.iter().map(|id| (*id, None)).collect();
+        while let Some(id) = queue.pop_front() {
+            for child in references.get(&id).into_iter().flatten() {
+                if !retained_by.contains_key(child) {
+                    retained_by.insert(*child, Some(id));
+                    queue.push_back(*child);
+                }
+            }
+        }
+        let leaks = gc_nodes
+            .iter()
+            .map(|gc_node| {
+                let mut chain = Vec::new();
+                let mut next_op = retained_by.get(&gc_node.id()).copied().flatten();
+                while let Some(next) = next_op {
+                    chain.push(next);
+                    next_op = retained_by.get(&next).copied().flatten();
+                }
+                chain.reverse();
+                Leak {
+                    id: gc_node.id(),
+                    name: gc_node.name(),
+                    label: gc_node.label().map(|label: Arc<str>| label.to_string()),
+                    location: gc_node.location(),
+                    retained_by: chain,
+                }
+            })
+            .collect();
+        let descriptions = gc_nodes
+            .iter()
+            .map(|gc_node| (gc_node.id(), gc_node.description()))
+            .collect();
+        LeakReport {
+            leaks,
+            node_count,
+            descriptions,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.leaks.is_empty() && self.node_count == 0
+    }
+
+    /// Return what kind of node the node or listener with the given id
+    /// is, with its label and location if known.
+    pub fn describe(&self, id: NodeId) -> Option<&str> {
+        self.descriptions
+            .get(&id)
+            .map(|description| description.as_str())
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.leaks.is_empty() {
+            return writeln!(
+                f,
+                "{} Sodium nodes leaked; retention paths unavailable (track_nodes off)",
+                self.node_count
+            );
+        }
+        writeln!(f, "{} Sodium nodes leaked:", self.leaks.len())?;
+        for leak in &self.leaks {
+            writeln!(
+                f,
+                "  #{} {}",
+                leak.id,
+                self.describe(leak.id).unwrap_or_default()
+            )?;
+            for id in &leak.retained_by {
+                writeln!(
+                    f,
+                    "      held via #{} {}",
+                    id,
+                    self.describe(*id).unwrap_or_default()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for LeakReport {}
+
+/// Assert that nothing is left alive in a [`SodiumCtx`], panicking with
+/// the [`LeakReport`] otherwise.
+///
+/// The report only names the nodes left and the paths keeping them
+/// alive if the context was built with
+/// [`SodiumCtxBuilder::track_nodes`][crate::SodiumCtxBuilder::track_nodes].
+/// Otherwise it only checks how many nodes are left.
+///
+/// Meant for the end of tests, once every stream, cell, sink and
+/// listener made in the test has been dropped:
+///
+/// ```
+/// use sodium_rust::{assert_no_leaks, SodiumCtx};
+///
+/// let sodium_ctx = SodiumCtx::new();
+/// {
+///     let s = sodium_ctx.new_stream_sink::<i32>();
+///     let l = s.stream().map(|a: &i32| *a + 1).listen(|_: &i32| {});
+///     s.send(1);
+///     l.unlisten();
+/// }
+/// assert_no_leaks!(sodium_ctx);
+/// ```
+#[macro_export]
+macro_rules! assert_no_leaks {
+    ($sodium_ctx:expr) => {
+        if let ::std::result::Result::Err(report) = $crate::SodiumCtx::check_leaks(&$sodium_ctx) {
+            ::std::panic!("{}", report);
+        }
+    };
+}
@@ -1,22 +1,49 @@
 //! Sodium is a library for doing Functional Reactive Programming
 //! (FRP) in Rust.
+//!
+//! # Features
+//!
+//! - `async`: conversions between Sodium streams and cells and `std`
+//!   futures, see `Stream::to_async_stream`, `Cell::changed` and
+//!   `StreamSink::feed`.
+//! - `tracing`: a [tracing](https://docs.rs/tracing) span for every
+//!   transaction, with a child span for each node updated and events
+//!   for the `pre_eot`, `pre_post` and `post` phases and cycle
+//!   collection.
 
+#[cfg(feature = "async")]
+mod async_;
 mod cell;
 mod cell_loop;
+mod cell_map;
 mod cell_sink;
+mod cell_tuple;
+mod cell_vec;
 mod impl_;
+pub mod introspect;
 mod listener;
+pub mod local;
 mod operational;
 mod router;
 mod sodium_ctx;
 mod stream;
+mod stream_collection;
 mod stream_loop;
 mod stream_sink;
+pub mod timer;
 mod transaction;
 
+#[cfg(feature = "async")]
+pub use self::async_::{AsyncBuffer, AsyncStream, Changed};
 pub use self::cell::Cell;
+pub use self::cell::CellFn;
 pub use self::cell_loop::CellLoop;
+pub use self::cell_map::CellMap;
+pub use self::cell_map::MapDiff;
 pub use self::cell_sink::CellSink;
+pub use self::cell_tuple::CellTuple;
+pub use self::cell_vec::CellVec;
+pub use self::cell_vec::VecDiff;
 #[doc(hidden)]
 pub use self::impl_::dep::Dep;
 #[doc(hidden)]
@@ -42,11 +69,19 @@ pub use self::impl_::lambda::Lambda;
 pub use self::impl_::lazy::Lazy;
 #[doc(hidden)]
 pub use self::impl_::node::Node;
+pub use self::impl_::sodium_ctx::GcPolicy;
+pub use self::impl_::stats::SodiumStats;
+pub use self::impl_::stats::TransactionStats;
+pub use self::impl_::stream_loop::LoopError;
 pub use self::listener::Listener;
+pub use self::operational::IoExecutor;
 pub use self::operational::Operational;
+pub use self::operational::ThreadIoExecutor;
 pub use self::router::Router;
 pub use self::sodium_ctx::SodiumCtx;
+pub use self::sodium_ctx::SodiumCtxBuilder;
 pub use self::stream::Stream;
+pub use self::stream_collection::StreamCollection;
 pub use self::stream_loop::StreamLoop;
 pub use self::stream_sink::StreamSink;
 pub use self::transaction::Transaction;
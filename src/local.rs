@@ -0,0 +1,587 @@
+//! A Sodium context confined to one thread, for values that aren't
+//! `Send`.
+//!
+//! Every combinator of [`SodiumCtx`] needs its values and lambdas to be
+//! `Send`, as the graph may be updated from other threads. A
+//! [`LocalSodiumCtx`] instead always updates its graph on the thread
+//! that created it, and neither it nor its streams, cells and sinks
+//! can leave that thread. In return they take values and lambdas
+//! without `Send` bounds, such as `Rc`s or GUI widgets.
+//!
+//! ```
+//! use sodium_rust::local::LocalSodiumCtx;
+//! use std::rc::Rc;
+//!
+//! let sodium_ctx = LocalSodiumCtx::new();
+//! let ss = sodium_ctx.new_stream_sink::<Rc<str>>();
+//! let c = ss.stream().map(|s: &Rc<str>| s.len()).hold(0);
+//! ss.send(Rc::from("hello"));
+//! assert_eq!(c.sample(), 5);
+//! ```
+//!
+//! The local types are a thin layer over the same streams and cells as
+//! the rest of the crate, running on a single threaded [`SodiumCtx`].
+//! Values and lambdas are carried through them in a wrapper that
+//! checks it is only used on the thread it was created on.
+
+use crate::impl_::lambda::{lambda1, lambda1_deps, lambda2, lambda2_deps};
+use crate::impl_::lambda::{IsLambda1, IsLambda2};
+use crate::impl_::local::Local;
+use crate::Cell;
+use crate::CellLoop;
+use crate::CellSink;
+use crate::Dep;
+use crate::Listener;
+use crate::LoopError;
+use crate::SodiumCtx;
+use crate::Stream;
+use crate::StreamLoop;
+use crate::StreamSink;
+
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+// Keeps the local types on the thread they were created on.
+type NotSend = PhantomData<Rc<()>>;
+
+fn local_lambda1<A: 'static, B: 'static, FN: IsLambda1<A, B> + 'static>(
+    f: FN,
+) -> impl IsLambda1<Local<A>, Local<B>> + Send + Sync + 'static {
+    let deps = lambda1_deps(&f);
+    let mut f = Local::new(f);
+    lambda1(
+        move |a: &Local<A>| Local::new(f.get_mut().call(a.get())),
+        deps,
+    )
+}
+
+fn local_lambda2<A: 'static, B: 'static, C: 'static, FN: IsLambda2<A, B, C> + 'static>(
+    f: FN,
+) -> impl IsLambda2<Local<A>, Local<B>, Local<C>> + Send + Sync + 'static {
+    let deps = lambda2_deps(&f);
+    let mut f = Local::new(f);
+    lambda2(
+        move |a: &Local<A>, b: &Local<B>| Local::new(f.get_mut().call(a.get(), b.get())),
+        deps,
+    )
+}
+
+/// A context object for a Sodium system that stays on the thread that
+/// created it.
+///
+/// See the [module documentation][crate::local].
+///
+/// Neither the context nor anything made from it can be sent to
+/// another thread:
+///
+/// ```compile_fail
+/// let sodium_ctx = sodium_rust::local::LocalSodiumCtx::new();
+/// let ss = sodium_ctx.new_stream_sink::<i32>();
+/// std::thread::spawn(move || ss.send(1));
+/// ```
+#[derive(Clone)]
+pub struct LocalSodiumCtx {
+    pub(crate) impl_: SodiumCtx,
+    _not_send: NotSend,
+}
+
+impl Default for LocalSodiumCtx {
+    fn default() -> LocalSodiumCtx {
+        LocalSodiumCtx::new()
+    }
+}
+
+impl LocalSodiumCtx {
+    /// Create a new Sodium FRP context on the current thread.
+    pub fn new() -> LocalSodiumCtx {
+        LocalSodiumCtx {
+            impl_: SodiumCtx::builder().single_threaded().build(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Create a new constant value [`LocalCell`] in this context.
+    #[track_caller]
+    pub fn new_cell<A: Clone + 'static>(&self, a: A) -> LocalCell<A> {
+        LocalCell::new(self.impl_.new_cell(Local::new(a)))
+    }
+
+    /// Create a new [`LocalStream`] that will never fire in this
+    /// context.
+    #[track_caller]
+    pub fn new_stream<A: Clone + 'static>(&self) -> LocalStream<A> {
+        LocalStream::new(self.impl_.new_stream())
+    }
+
+    /// Create a new [`LocalCellSink`] for interfacing I/O and FRP.
+    #[track_caller]
+    pub fn new_cell_sink<A: Clone + 'static>(&self, a: A) -> LocalCellSink<A> {
+        LocalCellSink {
+            impl_: self.impl_.new_cell_sink(Local::new(a)),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Create a new [`LocalStreamSink`] for interfacing I/O and FRP.
+    #[track_caller]
+    pub fn new_stream_sink<A: Clone + 'static>(&self) -> LocalStreamSink<A> {
+        LocalStreamSink {
+            impl_: self.impl_.new_stream_sink(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Create a new [`LocalCellLoop`] to act as a forward reference
+    /// for a [`LocalCell`] that will be created later.
+    #[track_caller]
+    pub fn new_cell_loop<A: Clone + 'static>(&self) -> LocalCellLoop<A> {
+        LocalCellLoop {
+            impl_: self.impl_.new_cell_loop(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Create a new [`LocalStreamLoop`] to act as a forward reference
+    /// for a [`LocalStream`] that will be created later.
+    #[track_caller]
+    pub fn new_stream_loop<A: Clone + 'static>(&self) -> LocalStreamLoop<A> {
+        LocalStreamLoop {
+            impl_: self.impl_.new_stream_loop(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Run the given function inside a single Sodium transaction,
+    /// closing the transaction after the function returns.
+    ///
+    /// See [`SodiumCtx::transaction`].
+    pub fn transaction<R, K: FnOnce() -> R>(&self, k: K) -> R {
+        self.impl_.transaction(k)
+    }
+
+    /// Execute the given code after the current transaction is
+    /// closed, or immediately if there is no current transaction.
+    pub fn post<K: FnMut() + 'static>(&self, k: K) {
+        let mut k = Local::new(k);
+        self.impl_.post(move || k.get_mut()());
+    }
+
+    /// Collect any cycles of Sodium objects that are no longer
+    /// reachable.
+    ///
+    /// See [`SodiumCtx::collect_cycles`].
+    pub fn collect_cycles(&self) {
+        self.impl_.collect_cycles();
+    }
+}
+
+/// A [`Stream`] of a [`LocalSodiumCtx`], whose values don't need to be
+/// `Send`.
+pub struct LocalStream<A> {
+    impl_: Stream<Local<A>>,
+    _not_send: NotSend,
+}
+
+impl<A> Clone for LocalStream<A> {
+    fn clone(&self) -> Self {
+        LocalStream {
+            impl_: self.impl_.clone(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<A: Clone + 'static> LocalStream<A> {
+    fn new(impl_: Stream<Local<A>>) -> LocalStream<A> {
+        LocalStream {
+            impl_,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Return a dependency on this stream, for the lambdas that use it
+    /// to declare with [`lambda1`][crate::lambda1] and friends.
+    pub fn to_dep(&self) -> Dep {
+        self.impl_.to_dep()
+    }
+
+    /// Attach a label to this stream's node and return the stream.
+    ///
+    /// See [`Stream::named`].
+    pub fn named(&self, label: &str) -> LocalStream<A> {
+        LocalStream::new(self.impl_.named(label))
+    }
+
+    /// Transform this stream's event values with the supplied
+    /// function.
+    ///
+    /// See [`Stream::map`].
+    #[track_caller]
+    pub fn map<B: Clone + 'static, FN: IsLambda1<A, B> + 'static>(&self, f: FN) -> LocalStream<B> {
+        LocalStream::new(self.impl_.map(local_lambda1(f)))
+    }
+
+    /// Transform this stream's event values into the specified
+    /// constant value.
+    #[track_caller]
+    pub fn map_to<B: Clone + 'static>(&self, b: B) -> LocalStream<B> {
+        self.map(move |_: &A| b.clone())
+    }
+
+    /// Return a stream that only outputs events for which the
+    /// predicate returns `true`.
+    #[track_caller]
+    pub fn filter<PRED: IsLambda1<A, bool> + 'static>(&self, pred: PRED) -> LocalStream<A> {
+        let mut pred = local_lambda1(pred);
+        let deps = lambda1_deps(&pred);
+        LocalStream::new(
+            self.impl_
+                .filter(lambda1(move |a: &Local<A>| pred.call(a).into_inner(), deps)),
+        )
+    }
+
+    /// Variant of [`merge`][LocalStream::merge] that merges two
+    /// streams, keeping the event from `self` if both fire in the same
+    /// transaction.
+    #[track_caller]
+    pub fn or_else(&self, s2: &LocalStream<A>) -> LocalStream<A> {
+        self.merge(s2, |lhs: &A, _rhs: &A| lhs.clone())
+    }
+
+    /// Merge two streams of the same type into one, combining
+    /// simultaneous events with `f`.
+    ///
+    /// See [`Stream::merge`].
+    #[track_caller]
+    pub fn merge<FN: IsLambda2<A, A, A> + 'static>(
+        &self,
+        s2: &LocalStream<A>,
+        f: FN,
+    ) -> LocalStream<A> {
+        LocalStream::new(self.impl_.merge(&s2.impl_, local_lambda2(f)))
+    }
+
+    /// Return a stream whose events are the result of the combination
+    /// of the event value and the value of the cell at the time of
+    /// the event.
+    ///
+    /// See [`Stream::snapshot`].
+    #[track_caller]
+    pub fn snapshot<B: Clone + 'static, C: Clone + 'static, FN: IsLambda2<A, B, C> + 'static>(
+        &self,
+        cb: &LocalCell<B>,
+        f: FN,
+    ) -> LocalStream<C> {
+        LocalStream::new(self.impl_.snapshot(&cb.impl_, local_lambda2(f)))
+    }
+
+    /// A variant of [`snapshot`][LocalStream::snapshot] that captures
+    /// the cell's value at the time of the event firing, ignoring the
+    /// stream's value.
+    #[track_caller]
+    pub fn snapshot1<B: Clone + 'static>(&self, cb: &LocalCell<B>) -> LocalStream<B> {
+        self.snapshot(cb, |_a: &A, b: &B| b.clone())
+    }
+
+    /// Return a stream that only outputs events from the input stream
+    /// when the specified cell's value is true.
+    #[track_caller]
+    pub fn gate(&self, cpred: &LocalCell<bool>) -> LocalStream<A> {
+        self.snapshot(cpred, |a: &A, pred: &bool| (a.clone(), *pred))
+            .filter(|(_, pred): &(A, bool)| *pred)
+            .map(|(a, _): &(A, bool)| a.clone())
+    }
+
+    /// Return a stream that outputs only one value, which is the next
+    /// event of the input stream.
+    #[track_caller]
+    pub fn once(&self) -> LocalStream<A> {
+        LocalStream::new(self.impl_.once())
+    }
+
+    /// Returns a cell with the specified initial value, which is
+    /// updated by this stream's event values.
+    #[track_caller]
+    pub fn hold(&self, a: A) -> LocalCell<A> {
+        LocalCell::new(self.impl_.hold(Local::new(a)))
+    }
+
+    /// Transform an event with a generalized state loop (a Mealy
+    /// machine).
+    ///
+    /// See [`Stream::collect`].
+    #[track_caller]
+    pub fn collect<B, S, F>(&self, init_state: S, f: F) -> LocalStream<B>
+    where
+        B: Clone + 'static,
+        S: Clone + 'static,
+        F: IsLambda2<A, S, (B, S)> + 'static,
+    {
+        let mut f = local_lambda2(f);
+        let deps = lambda2_deps(&f);
+        LocalStream::new(self.impl_.collect(
+            Local::new(init_state),
+            lambda2(
+                move |a: &Local<A>, s: &Local<S>| {
+                    let (b, s) = f.call(a, s).into_inner();
+                    (Local::new(b), Local::new(s))
+                },
+                deps,
+            ),
+        ))
+    }
+
+    /// Accumulate on an input event, outputting the new state each
+    /// time.
+    ///
+    /// See [`Stream::accum`].
+    #[track_caller]
+    pub fn accum<S, F>(&self, init_state: S, f: F) -> LocalCell<S>
+    where
+        S: Clone + 'static,
+        F: IsLambda2<A, S, S> + 'static,
+    {
+        LocalCell::new(self.impl_.accum(Local::new(init_state), local_lambda2(f)))
+    }
+
+    /// Listen for events on this stream.
+    ///
+    /// See [`Stream::listen`].
+    #[track_caller]
+    pub fn listen<K: IsLambda1<A, ()> + 'static>(&self, k: K) -> Listener {
+        let mut k = local_lambda1(k);
+        let deps = lambda1_deps(&k);
+        self.impl_
+            .listen(lambda1(move |a: &Local<A>| k.call(a).into_inner(), deps))
+    }
+}
+
+/// A [`Cell`] of a [`LocalSodiumCtx`], whose values don't need to be
+/// `Send`.
+pub struct LocalCell<A> {
+    impl_: Cell<Local<A>>,
+    _not_send: NotSend,
+}
+
+impl<A> Clone for LocalCell<A> {
+    fn clone(&self) -> Self {
+        LocalCell {
+            impl_: self.impl_.clone(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<A: Clone + 'static> LocalCell<A> {
+    fn new(impl_: Cell<Local<A>>) -> LocalCell<A> {
+        LocalCell {
+            impl_,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Sample the cell's current value.
+    ///
+    /// See [`Cell::sample`].
+    pub fn sample(&self) -> A {
+        self.impl_.sample().into_inner()
+    }
+
+    /// Return a dependency on this cell, for the lambdas that sample
+    /// it to declare with [`lambda1`][crate::lambda1] and friends.
+    pub fn to_dep(&self) -> Dep {
+        self.impl_.to_dep()
+    }
+
+    /// Attach a label to this cell's node and return the cell.
+    ///
+    /// See [`Cell::named`].
+    pub fn named(&self, label: &str) -> LocalCell<A> {
+        LocalCell::new(self.impl_.named(label))
+    }
+
+    /// Return a stream that gives the updates to this cell.
+    ///
+    /// See [`Cell::updates`].
+    #[track_caller]
+    pub fn updates(&self) -> LocalStream<A> {
+        LocalStream::new(self.impl_.updates())
+    }
+
+    /// Return a stream that fires once with the current value of this
+    /// cell and thereafter gives its updates.
+    ///
+    /// See [`Cell::value`].
+    #[track_caller]
+    pub fn value(&self) -> LocalStream<A> {
+        LocalStream::new(self.impl_.value())
+    }
+
+    /// Transform the cell's value with the supplied function.
+    ///
+    /// See [`Cell::map`].
+    #[track_caller]
+    pub fn map<B: Clone + 'static, FN: IsLambda1<A, B> + 'static>(&self, f: FN) -> LocalCell<B> {
+        LocalCell::new(self.impl_.map(local_lambda1(f)))
+    }
+
+    /// Lift a binary function into cells so the returned cell always
+    /// reflects the specified function applied to the input cells'
+    /// values.
+    #[track_caller]
+    pub fn lift2<B: Clone + 'static, C: Clone + 'static, FN: IsLambda2<A, B, C> + 'static>(
+        &self,
+        cb: &LocalCell<B>,
+        f: FN,
+    ) -> LocalCell<C> {
+        LocalCell::new(self.impl_.lift2(&cb.impl_, local_lambda2(f)))
+    }
+
+    /// Unwrap a [`LocalStream`] in a cell to give a time-varying
+    /// stream implementation.
+    #[track_caller]
+    pub fn switch_s(csa: &LocalCell<LocalStream<A>>) -> LocalStream<A> {
+        LocalStream::new(Cell::switch_s(
+            &csa.impl_
+                .map(|sa: &Local<LocalStream<A>>| sa.get().impl_.clone()),
+        ))
+    }
+
+    /// Unwrap a cell in another cell to give a time-varying cell
+    /// implementation.
+    #[track_caller]
+    pub fn switch_c(cca: &LocalCell<LocalCell<A>>) -> LocalCell<A> {
+        LocalCell::new(Cell::switch_c(
+            &cca.impl_
+                .map(|ca: &Local<LocalCell<A>>| ca.get().impl_.clone()),
+        ))
+    }
+
+    /// Listen for updates to the value of this cell.
+    ///
+    /// See [`Cell::listen`].
+    #[track_caller]
+    pub fn listen<K: IsLambda1<A, ()> + 'static>(&self, k: K) -> Listener {
+        let mut k = local_lambda1(k);
+        let deps = lambda1_deps(&k);
+        self.impl_
+            .listen(lambda1(move |a: &Local<A>| k.call(a).into_inner(), deps))
+    }
+}
+
+/// A [`StreamSink`] of a [`LocalSodiumCtx`].
+pub struct LocalStreamSink<A> {
+    impl_: StreamSink<Local<A>>,
+    _not_send: NotSend,
+}
+
+impl<A> Clone for LocalStreamSink<A> {
+    fn clone(&self) -> Self {
+        LocalStreamSink {
+            impl_: self.impl_.clone(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<A: Clone + 'static> LocalStreamSink<A> {
+    /// Return a [`LocalStream`] that can be used in the creation of
+    /// Sodium logic that will consume events pushed into this sink.
+    pub fn stream(&self) -> LocalStream<A> {
+        LocalStream::new(self.impl_.stream())
+    }
+
+    /// Send a value, making the stream fire.
+    ///
+    /// See [`StreamSink::send`].
+    pub fn send(&self, a: A) {
+        self.impl_.send(Local::new(a));
+    }
+}
+
+/// A [`CellSink`] of a [`LocalSodiumCtx`].
+pub struct LocalCellSink<A> {
+    impl_: CellSink<Local<A>>,
+    _not_send: NotSend,
+}
+
+impl<A> Clone for LocalCellSink<A> {
+    fn clone(&self) -> Self {
+        LocalCellSink {
+            impl_: self.impl_.clone(),
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<A: Clone + 'static> LocalCellSink<A> {
+    /// Return a [`LocalCell`] that can be used to create Sodium logic
+    /// that will read the updated values of this sink.
+    pub fn cell(&self) -> LocalCell<A> {
+        LocalCell::new(self.impl_.cell())
+    }
+
+    /// Send a value, modifying the value of the cell.
+    ///
+    /// See [`CellSink::send`].
+    pub fn send(&self, a: A) {
+        self.impl_.send(Local::new(a));
+    }
+}
+
+/// A [`StreamLoop`] of a [`LocalSodiumCtx`].
+pub struct LocalStreamLoop<A> {
+    impl_: StreamLoop<Local<A>>,
+    _not_send: NotSend,
+}
+
+impl<A: Clone + 'static> LocalStreamLoop<A> {
+    /// Return a [`LocalStream`] that is equivalent to this loop once
+    /// it has been resolved by calling
+    /// [`loop_`][LocalStreamLoop::loop_].
+    pub fn stream(&self) -> LocalStream<A> {
+        LocalStream::new(self.impl_.stream())
+    }
+
+    /// Resolve the loop to specify what this `LocalStreamLoop` was a
+    /// forward reference to.
+    ///
+    /// See [`StreamLoop::loop_`].
+    pub fn loop_(&self, sa: &LocalStream<A>) {
+        self.impl_.loop_(&sa.impl_);
+    }
+
+    /// A variant of [`loop_`][LocalStreamLoop::loop_] that returns an
+    /// error rather than panicking.
+    pub fn try_loop_(&self, sa: &LocalStream<A>) -> Result<(), LoopError> {
+        self.impl_.try_loop_(&sa.impl_)
+    }
+}
+
+/// A [`CellLoop`] of a [`LocalSodiumCtx`].
+pub struct LocalCellLoop<A> {
+    impl_: CellLoop<Local<A>>,
+    _not_send: NotSend,
+}
+
+impl<A: Clone + 'static> LocalCellLoop<A> {
+    /// Return a [`LocalCell`] that is equivalent to this loop once it
+    /// has been resolved by calling [`loop_`][LocalCellLoop::loop_].
+    pub fn cell(&self) -> LocalCell<A> {
+        LocalCell::new(self.impl_.cell())
+    }
+
+    /// Resolve the loop to specify what this `LocalCellLoop` was a
+    /// forward reference to.
+    ///
+    /// See [`CellLoop::loop_`].
+    pub fn loop_(&self, ca: &LocalCell<A>) {
+        self.impl_.loop_(&ca.impl_);
+    }
+
+    /// A variant of [`loop_`][LocalCellLoop::loop_] that returns an
+    /// error rather than panicking.
+    pub fn try_loop_(&self, ca: &LocalCell<A>) -> Result<(), LoopError> {
+        self.impl_.try_loop_(&ca.impl_)
+    }
+}
@@ -1,26 +1,117 @@
+use crate::impl_::name::CallerLocation;
 use crate::Cell;
 use crate::Stream;
 
+/// Runs the jobs started by [`Operational::execute_async_io`].
+///
+/// Any `Fn(Box<dyn FnOnce() + Send>)` closure is an `IoExecutor`, so a
+/// thread pool or an async runtime can be plugged in with a closure
+/// that hands the job over to it.
+pub trait IoExecutor: Send + Sync + 'static {
+    /// Run `job`, either straight away or later on some other
+    /// thread.
+    fn execute(&self, job: Box<dyn FnOnce() + Send>);
+}
+
+impl<F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static> IoExecutor for F {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        self(job)
+    }
+}
+
+/// An [`IoExecutor`] that runs every job on a new thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadIoExecutor;
+
+impl IoExecutor for ThreadIoExecutor {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        std::thread::spawn(job);
+    }
+}
+
 /// Operational primitives that must be used with care because they
 /// break non-detectability of `Cell` steps/updates.
 pub struct Operational {}
 
 impl Operational {
+    #[track_caller]
     pub fn updates<A: Clone + Send + 'static>(ca: &Cell<A>) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: ca.impl_.updates(),
         }
     }
 
+    #[track_caller]
     pub fn value<A: Clone + Send + 'static>(ca: &Cell<A>) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: ca.impl_.value(),
         }
     }
 
+    #[track_caller]
     pub fn defer<A: Clone + Send + 'static>(sa: &Stream<A>) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: sa.impl_.defer(),
         }
     }
+
+    /// Run `f` on each event of `sa` once the transaction the event
+    /// came in has closed, and output its result in a new
+    /// transaction.
+    ///
+    /// `f` runs on the thread that closed the transaction, one event
+    /// at a time, so results come out in the order of the events. It
+    /// is meant for quick side effects; use
+    /// [`execute_async_io`][Operational::execute_async_io] for
+    /// anything that can block.
+    #[track_caller]
+    pub fn execute_sync_io<A, B, F>(sa: &Stream<A>, f: F) -> Stream<B>
+    where
+        A: Clone + Send + 'static,
+        B: Clone + Send + 'static,
+        F: FnMut(&A) -> B + Send + 'static,
+    {
+        let _caller = CallerLocation::enter();
+        Stream {
+            impl_: sa.impl_.execute_sync_io(f),
+        }
+    }
+
+    /// Run `f` on each event of `sa` on the given [`IoExecutor`], once
+    /// the transaction the event came in has closed, and output each
+    /// result in a new transaction as it arrives.
+    ///
+    /// Results may come out in a different order from the events if
+    /// the executor runs jobs concurrently. A result is dropped if the
+    /// returned stream has been garbage collected by the time it
+    /// arrives.
+    ///
+    /// ```
+    /// use sodium_rust::{Operational, SodiumCtx, ThreadIoExecutor};
+    ///
+    /// let sodium_ctx = SodiumCtx::new();
+    /// let requests = sodium_ctx.new_stream_sink::<u32>();
+    /// let responses =
+    ///     Operational::execute_async_io(&requests.stream(), ThreadIoExecutor, |id: &u32| {
+    ///         format!("response to {}", id)
+    ///     });
+    /// ```
+    #[track_caller]
+    pub fn execute_async_io<A, B, EX, F>(sa: &Stream<A>, executor: EX, f: F) -> Stream<B>
+    where
+        A: Clone + Send + 'static,
+        B: Clone + Send + 'static,
+        EX: IoExecutor,
+        F: Fn(&A) -> B + Send + Sync + 'static,
+    {
+        let _caller = CallerLocation::enter();
+        Stream {
+            impl_: sa
+                .impl_
+                .execute_async_io(move |job| executor.execute(job), f),
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use crate::impl_::name::CallerLocation;
 use crate::impl_::router::Router as RouterImpl;
 use crate::SodiumCtx;
 use crate::Stream;
@@ -13,6 +14,7 @@ pub struct Router<A, K> {
 impl<A, K> Router<A, K> {
     /// Create a new `Router` from the given input stream and selector
     /// function.
+    #[track_caller]
     pub fn new(
         sodium_ctx: &SodiumCtx,
         in_stream: &Stream<A>,
@@ -22,6 +24,7 @@ impl<A, K> Router<A, K> {
         A: Clone + Send + 'static,
         K: Send + Sync + Eq + Hash + 'static,
     {
+        let _caller = CallerLocation::enter();
         Router {
             impl_: RouterImpl::new(&sodium_ctx.impl_, &in_stream.impl_, selector),
         }
@@ -29,11 +32,13 @@ impl<A, K> Router<A, K> {
 
     /// Create a Stream that is subscribed to event values that the
     /// selector function routes to the given `K` value.
+    #[track_caller]
     pub fn filter_matches(&self, k: &K) -> Stream<A>
     where
         A: Clone + Send + 'static,
         K: Clone + Send + Sync + Eq + Hash + 'static,
     {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.filter_matches(k),
         }
@@ -1,4 +1,12 @@
+use crate::impl_::name::CallerLocation;
+use crate::impl_::sodium_ctx::single_threaded_mode;
+use crate::impl_::sodium_ctx::thread_pool_threaded_mode;
+use crate::impl_::sodium_ctx::GcPolicy;
 use crate::impl_::sodium_ctx::SodiumCtx as SodiumCtxImpl;
+use crate::impl_::sodium_ctx::SodiumCtxConfig;
+use crate::impl_::sodium_ctx::{ThreadJoiner, ThreadSpawner, ThreadedMode};
+use crate::impl_::stats::{SodiumStats, TransactionStats};
+use crate::introspect::{Graph, LeakReport};
 use crate::Cell;
 use crate::CellLoop;
 use crate::CellSink;
@@ -30,41 +38,97 @@ impl SodiumCtx {
         }
     }
 
+    /// Create a new Sodium FRP context that updates independent
+    /// branches of the graph in parallel on a pool of `num_threads`
+    /// worker threads.
+    ///
+    /// Listeners are still called one at a time and in the same order
+    /// as in a context created with [`SodiumCtx::new`]. Lambdas passed
+    /// to combinators such as [`Stream::map`] may however be called
+    /// from any of the worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_threads` is zero.
+    pub fn new_with_thread_pool(num_threads: usize) -> SodiumCtx {
+        SodiumCtx::builder().thread_pool(num_threads).build()
+    }
+
+    /// Create a [`SodiumCtxBuilder`] for configuring the runtime
+    /// behaviour of a new Sodium FRP context.
+    pub fn builder() -> SodiumCtxBuilder {
+        SodiumCtxBuilder::new()
+    }
+
+    /// Collect any cycles of Sodium objects that are no longer
+    /// reachable.
+    ///
+    /// By default this happens automatically at the end of every
+    /// transaction, so this only needs to be called on contexts built
+    /// with a different [`GcPolicy`]. This collects everything there
+    /// is to collect, whatever the policy.
+    pub fn collect_cycles(&self) {
+        self.impl_.collect_cycles();
+    }
+
+    /// Return the statistics of all the transactions closed so far.
+    ///
+    /// Statistics are only collected by contexts built with
+    /// [`SodiumCtxBuilder::collect_stats`]; for any other context this
+    /// is all zeros, apart from
+    /// [`gc_roots_buffered`][SodiumStats::gc_roots_buffered].
+    pub fn stats(&self) -> SodiumStats {
+        self.impl_.stats()
+    }
+
     /// Create a new constant value [`Cell`] in this context.
+    #[track_caller]
     pub fn new_cell<A: Clone + Send + 'static>(&self, a: A) -> Cell<A> {
+        let _caller = CallerLocation::enter();
         Cell::new(self, a)
     }
 
     /// Create a new stream that will never fire in this context.
+    #[track_caller]
     pub fn new_stream<A: Clone + Send + 'static>(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream::new(self)
     }
 
     /// Create a new [`CellSink`] for interfacing I/O and FRP.
+    #[track_caller]
     pub fn new_cell_sink<A: Clone + Send + 'static>(&self, a: A) -> CellSink<A> {
+        let _caller = CallerLocation::enter();
         CellSink::new(self, a)
     }
 
     /// Create a new [`StreamSink`] for interfacing I/O and FRP.
+    #[track_caller]
     pub fn new_stream_sink<A: Clone + Send + 'static>(&self) -> StreamSink<A> {
+        let _caller = CallerLocation::enter();
         StreamSink::new(self)
     }
 
     /// Create a new [`CellLoop`] to act as a forward reference for a
     /// [`Cell`] that will be created later.
+    #[track_caller]
     pub fn new_cell_loop<A: Clone + Send + 'static>(&self) -> CellLoop<A> {
+        let _caller = CallerLocation::enter();
         CellLoop::new(self)
     }
 
     /// Create a new [`StreamLoop`] to act as a forward reference for
     /// a [`Stream`] that will be created later.
+    #[track_caller]
     pub fn new_stream_loop<A: Clone + Send + 'static>(&self) -> StreamLoop<A> {
+        let _caller = CallerLocation::enter();
         StreamLoop::new(self)
     }
 
     /// Create a new [`StreamSink`] with a combining function that
     /// allows [`send`][CellSink::send]ing multiple event values per
     /// transaction.
+    #[track_caller]
     pub fn new_stream_sink_with_coalescer<
         A: Clone + Send + 'static,
         COALESCER: FnMut(&A, &A) -> A + Send + 'static,
@@ -72,15 +136,107 @@ impl SodiumCtx {
         &self,
         coalescer: COALESCER,
     ) -> StreamSink<A> {
+        let _caller = CallerLocation::enter();
         StreamSink::new_with_coalescer(self, coalescer)
     }
 
     /// Run the given function inside a single Sodium transaction,
     /// closing the transaction after the function returns.
+    ///
+    /// # Panics
+    ///
+    /// If the function, or any lambda or listener run while the
+    /// transaction is closed, panics, the transaction is rolled back and
+    /// the panic carries on to the caller. Streams stop firing, cells
+    /// keep their old values and callbacks registered with
+    /// [`SodiumCtx::post`] are dropped, so IO queued by
+    /// [`Operational::execute_sync_io`][crate::Operational::execute_sync_io]
+    /// and
+    /// [`Operational::execute_async_io`][crate::Operational::execute_async_io]
+    /// is never started. The context can be used as normal afterwards.
+    ///
+    /// Rolling back only undoes changes to the graph. Listeners that
+    /// ran before the panic are not undone, so whatever they did
+    /// outside the graph, such as queueing items on an async stream,
+    /// stays done.
+    ///
+    /// A panic in a `post` callback comes after the transaction has
+    /// taken effect. The other `post` callbacks still run before it
+    /// carries on.
     pub fn transaction<R, K: FnOnce() -> R>(&self, k: K) -> R {
         self.impl_.transaction(k)
     }
 
+    /// Return the graph of every live node in this context in
+    /// Graphviz DOT format, for visualising and reviewing Sodium
+    /// logic.
+    ///
+    /// Each node is labelled with the kind of node and its id, and
+    /// listeners are drawn as ellipses. Edges point from each node to
+    /// what it refers to: solid edges for the dependencies a node
+    /// holds on to and from listeners to what they listen to, dotted
+    /// edges for the weak references a node has to its dependents,
+    /// and dashed edges for keep alives and for any other Sodium
+    /// objects used by lambdas.
+    ///
+    /// ```no_run
+    /// # let sodium_ctx = sodium_rust::SodiumCtx::builder().track_nodes().build();
+    /// std::fs::write("sodium.dot", sodium_ctx.export_dot()).unwrap();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the context was not built with
+    /// [`track_nodes`][SodiumCtxBuilder::track_nodes], as only then
+    /// does it know its live nodes.
+    pub fn export_dot(&self) -> String {
+        self.assert_tracking_nodes("export_dot");
+        self.impl_.export_dot()
+    }
+
+    /// Take a snapshot of the nodes and listeners of this context
+    /// that are alive, for looking at the structure of the graph.
+    ///
+    /// See the [`introspect`][crate::introspect] module.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the context was not built with
+    /// [`track_nodes`][SodiumCtxBuilder::track_nodes], as only then
+    /// does it know its live nodes.
+    pub fn graph(&self) -> Graph {
+        self.assert_tracking_nodes("graph");
+        Graph::new(self)
+    }
+
+    fn assert_tracking_nodes(&self, method: &str) {
+        if !self.impl_.is_tracking_nodes() {
+            panic!(
+                "SodiumCtx::{} needs a context built with SodiumCtxBuilder::track_nodes",
+                method
+            );
+        }
+    }
+
+    /// Collect cycles, then check that no nodes or listeners are left
+    /// in this context.
+    ///
+    /// Anything left is reported along with what is keeping it alive.
+    /// That needs a context built with
+    /// [`track_nodes`][SodiumCtxBuilder::track_nodes]. Any other context
+    /// can only report how many nodes are left, and its report says
+    /// that the retention paths are unavailable. See also
+    /// [`assert_no_leaks!`][crate::assert_no_leaks].
+    pub fn check_leaks(&self) -> Result<(), LeakReport> {
+        self.impl_.collect_cycles();
+        let report = LeakReport::new(self);
+        if report.is_empty() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+
     /// Create a new scoped transaction object.
     ///
     /// The Sodium transaction on this context will be held open until
@@ -97,6 +253,7 @@ impl SodiumCtx {
     }
 
     /// Create a new [`Router`] in this context.
+    #[track_caller]
     pub fn new_router<A, K>(
         &self,
         in_stream: &Stream<A>,
@@ -106,6 +263,174 @@ impl SodiumCtx {
         A: Clone + Send + 'static,
         K: Send + Sync + Eq + Hash + 'static,
     {
+        let _caller = CallerLocation::enter();
         Router::new(self, in_stream, selector)
     }
 }
+
+/// A builder for a [`SodiumCtx`], created with [`SodiumCtx::builder`].
+///
+/// A builder with no options set produces the same context as
+/// [`SodiumCtx::new`].
+pub struct SodiumCtxBuilder {
+    config: SodiumCtxConfig,
+}
+
+impl Default for SodiumCtxBuilder {
+    fn default() -> SodiumCtxBuilder {
+        SodiumCtxBuilder::new()
+    }
+}
+
+impl SodiumCtxBuilder {
+    /// Create a new builder with the default options.
+    pub fn new() -> SodiumCtxBuilder {
+        SodiumCtxBuilder {
+            config: SodiumCtxConfig::default(),
+        }
+    }
+
+    /// Update the graph on the thread that closes each transaction.
+    /// This is the default.
+    pub fn single_threaded(mut self) -> SodiumCtxBuilder {
+        self.config.threaded_mode = single_threaded_mode();
+        self
+    }
+
+    /// Update independent branches of the graph in parallel on a pool
+    /// of `num_threads` worker threads owned by the context.
+    ///
+    /// See [`SodiumCtx::new_with_thread_pool`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_threads` is zero.
+    pub fn thread_pool(mut self, num_threads: usize) -> SodiumCtxBuilder {
+        self.config.threaded_mode = thread_pool_threaded_mode(num_threads);
+        self
+    }
+
+    /// Update independent branches of the graph in parallel using a
+    /// user supplied way of running jobs, such as an existing thread
+    /// pool.
+    ///
+    /// `spawn` is given a job to run and returns a join function. When
+    /// the join function returns the job must have finished running,
+    /// and if the job panicked the join function should panic too. A
+    /// spawned job may spawn and join further jobs, so `spawn` must
+    /// not block waiting for a free thread.
+    ///
+    /// ```
+    /// use sodium_rust::SodiumCtx;
+    ///
+    /// let sodium_ctx = SodiumCtx::builder()
+    ///     .thread_spawner(|job| {
+    ///         let handle = std::thread::spawn(job);
+    ///         move || handle.join().unwrap()
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn thread_spawner<SPAWN, JOIN>(mut self, spawn: SPAWN) -> SodiumCtxBuilder
+    where
+        SPAWN: Fn(Box<dyn FnOnce() + Send>) -> JOIN + Send + Sync + 'static,
+        JOIN: FnOnce() + Send + 'static,
+    {
+        self.config.threaded_mode = ThreadedMode {
+            spawner: ThreadSpawner {
+                spawn_fn: Box::new(move |callback| ThreadJoiner {
+                    join_fn: Box::new(spawn(callback)),
+                }),
+            },
+            parallel: true,
+        };
+        self
+    }
+
+    /// Collect cycles of unreachable Sodium objects at the end of
+    /// every `num_transactions`th transaction instead of at the end of
+    /// every transaction.
+    ///
+    /// Passing `0` disables automatic collection entirely, in which
+    /// case [`SodiumCtx::collect_cycles`] must be called periodically
+    /// to reclaim memory.
+    ///
+    /// This is the same as [`gc_policy`][SodiumCtxBuilder::gc_policy]
+    /// with [`GcPolicy::EveryNTransactions`].
+    pub fn collect_cycles_every(mut self, num_transactions: u32) -> SodiumCtxBuilder {
+        self.config.gc_policy = GcPolicy::EveryNTransactions(num_transactions);
+        self
+    }
+
+    /// Choose when cycles of unreachable Sodium objects are collected.
+    ///
+    /// ```
+    /// use sodium_rust::{GcPolicy, SodiumCtx};
+    /// use std::time::Duration;
+    ///
+    /// let sodium_ctx = SodiumCtx::builder()
+    ///     .gc_policy(GcPolicy::Incremental {
+    ///         max_roots: 100,
+    ///         max_time: Duration::from_micros(200),
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn gc_policy(mut self, gc_policy: GcPolicy) -> SodiumCtxBuilder {
+        self.config.gc_policy = gc_policy;
+        self
+    }
+
+    /// Collect statistics on the work done closing each transaction,
+    /// available from [`SodiumCtx::stats`].
+    ///
+    /// This costs a few reads of the clock per transaction.
+    pub fn collect_stats(mut self) -> SodiumCtxBuilder {
+        self.config.collect_stats = true;
+        self
+    }
+
+    /// Collect statistics as with
+    /// [`collect_stats`][SodiumCtxBuilder::collect_stats], and also pass
+    /// the statistics of each transaction to `f` once it is closed.
+    ///
+    /// `f` is called outside of any transaction, on the thread that
+    /// closed the transaction.
+    ///
+    /// ```
+    /// use sodium_rust::{SodiumCtx, TransactionStats};
+    ///
+    /// let sodium_ctx = SodiumCtx::builder()
+    ///     .on_transaction_stats(|stats: &TransactionStats| {
+    ///         if stats.update_time.as_millis() > 10 {
+    ///             println!("slow transaction: {:?}", stats);
+    ///         }
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on_transaction_stats<F>(mut self, f: F) -> SodiumCtxBuilder
+    where
+        F: Fn(&TransactionStats) + Send + Sync + 'static,
+    {
+        self.config.collect_stats = true;
+        self.config.on_transaction_stats = Some(Box::new(f));
+        self
+    }
+
+    /// Keep track of every live node and listener, so that
+    /// [`SodiumCtx::graph`] and [`SodiumCtx::export_dot`] can show
+    /// them and [`SodiumCtx::check_leaks`] can say what is keeping
+    /// them alive.
+    ///
+    /// This puts a lock shared by the whole context on creating and
+    /// dropping every node, so it is meant for tests and debugging.
+    pub fn track_nodes(mut self) -> SodiumCtxBuilder {
+        self.config.track_nodes = true;
+        self
+    }
+
+    /// Create the configured [`SodiumCtx`].
+    pub fn build(self) -> SodiumCtx {
+        SodiumCtx {
+            impl_: SodiumCtxImpl::new_with_config(self.config),
+        }
+    }
+}
@@ -1,12 +1,22 @@
+#[cfg(feature = "async")]
+use crate::async_::{AsyncBuffer, AsyncStream};
 use crate::cell::Cell;
+use crate::cell_tuple::CellTuple;
 use crate::impl_::dep::Dep;
-use crate::impl_::lambda::{lambda1, lambda2};
+use crate::impl_::lambda::{lambda1, lambda1_deps, lambda2};
 use crate::impl_::lambda::{IsLambda1, IsLambda2, IsLambda3, IsLambda4, IsLambda5, IsLambda6};
+use crate::impl_::name::CallerLocation;
 use crate::impl_::stream::Stream as StreamImpl;
 use crate::listener::Listener;
 use crate::sodium_ctx::SodiumCtx;
+use crate::stream_collection::StreamCollection;
+use crate::timer;
+use crate::timer::TimerSystem;
 use crate::Lazy;
 
+use std::fmt;
+use std::time::Duration;
+
 /// Represents a stream of discrete events/firings containing values
 /// of type `A`.
 ///
@@ -29,12 +39,79 @@ impl<A: Clone + Send + 'static> Stream<Option<A>> {
     /// Return a `Stream` that only outputs events that have present
     /// values, removing the `Option` wrapper and discarding empty
     /// values.
+    #[track_caller]
     pub fn filter_option(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         self.filter(|a: &Option<A>| a.is_some())
             .map(|a: &Option<A>| a.clone().unwrap())
     }
 }
 
+impl<A: Clone + Send + 'static, E: Clone + Send + 'static> Stream<Result<A, E>> {
+    /// Return a `Stream` of the values of the `Ok` events of this
+    /// `Stream`, discarding the errors.
+    #[track_caller]
+    pub fn filter_ok(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        self.map(|r: &Result<A, E>| r.as_ref().ok().cloned())
+            .filter_option()
+    }
+
+    /// Return a `Stream` of the errors of the `Err` events of this
+    /// `Stream`, discarding the values.
+    #[track_caller]
+    pub fn errors(&self) -> Stream<E> {
+        let _caller = CallerLocation::enter();
+        self.map(|r: &Result<A, E>| r.as_ref().err().cloned())
+            .filter_option()
+    }
+
+    /// Split this `Stream` into a `Stream` of the `Ok` values and a
+    /// `Stream` of the errors, as [`filter_ok`][Stream::filter_ok] and
+    /// [`errors`][Stream::errors] would.
+    #[track_caller]
+    pub fn split_result(&self) -> (Stream<A>, Stream<E>) {
+        let _caller = CallerLocation::enter();
+        (self.filter_ok(), self.errors())
+    }
+
+    /// Transform the `Ok` values of this `Stream` with the supplied
+    /// function, passing errors through unchanged.
+    #[track_caller]
+    pub fn map_ok<B: Clone + Send + 'static, FN: IsLambda1<A, B> + Send + Sync + 'static>(
+        &self,
+        mut f: FN,
+    ) -> Stream<Result<B, E>> {
+        let _caller = CallerLocation::enter();
+        let deps = lambda1_deps(&f);
+        self.map(lambda1(
+            move |r: &Result<A, E>| match r {
+                Ok(a) => Ok(f.call(a)),
+                Err(e) => Err(e.clone()),
+            },
+            deps,
+        ))
+    }
+
+    /// Transform the errors of this `Stream` with the supplied
+    /// function, passing `Ok` values through unchanged.
+    #[track_caller]
+    pub fn map_err<F: Clone + Send + 'static, FN: IsLambda1<E, F> + Send + Sync + 'static>(
+        &self,
+        mut f: FN,
+    ) -> Stream<Result<A, F>> {
+        let _caller = CallerLocation::enter();
+        let deps = lambda1_deps(&f);
+        self.map(lambda1(
+            move |r: &Result<A, E>| match r {
+                Ok(a) => Ok(a.clone()),
+                Err(e) => Err(f.call(e)),
+            },
+            deps,
+        ))
+    }
+}
+
 impl<
         A: Clone + Send + Sync + 'static,
         COLLECTION: IntoIterator<Item = A> + Clone + Send + 'static,
@@ -42,7 +119,9 @@ impl<
 {
     /// Flatten a `Stream` of a collection of `A` into a `Stream` of
     /// single `A`s.
+    #[track_caller]
     pub fn split(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.split(),
         }
@@ -51,7 +130,9 @@ impl<
 
 impl<A: Clone + Send + 'static> Stream<A> {
     /// Create a `Stream` that will never fire.
+    #[track_caller]
     pub fn new(sodium_ctx: &SodiumCtx) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: StreamImpl::new(&sodium_ctx.impl_),
         }
@@ -63,6 +144,31 @@ impl<A: Clone + Send + 'static> Stream<A> {
         self.impl_.to_dep()
     }
 
+    /// Attach a label to the node behind this stream, and return the
+    /// stream.
+    ///
+    /// The label appears along with the kind of node and the source
+    /// location it was created at in `Debug` output, in cycle
+    /// collector tracing and in [`SodiumCtx::export_dot`], so that
+    /// nodes of the same kind can be told apart.
+    pub fn named(&self, label: &str) -> Stream<A> {
+        self.impl_.node().gc_node.set_label(label);
+        self.clone()
+    }
+
+    /// Let [`SodiumCtx::graph`] show the values this stream fires
+    /// with, and return the stream.
+    ///
+    /// Only the `Debug` form of the value is kept, and only while the
+    /// stream is firing.
+    pub fn debug_values(&self) -> Stream<A>
+    where
+        A: fmt::Debug,
+    {
+        self.impl_.debug_values();
+        self.clone()
+    }
+
     /// Return a stream whose events are the result of the combination
     /// of the event value and the current value of the cell using the
     /// specified function.
@@ -73,6 +179,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// transaction. To put this another way, `snapshot` always sees
     /// the value of a cell as it wass before any state changes from
     /// the current transaction.
+    #[track_caller]
     pub fn snapshot<
         B: Clone + Send + 'static,
         C: Clone + Send + 'static,
@@ -82,6 +189,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
         cb: &Cell<B>,
         f: FN,
     ) -> Stream<C> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.snapshot(&cb.impl_, f),
         }
@@ -90,12 +198,15 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// A variant of [`snapshot`][Stream::snapshot] that captures the
     /// cell's value at the time of the event firing, ignoring the
     /// stream's value.
+    #[track_caller]
     pub fn snapshot1<B: Send + Clone + 'static>(&self, cb: &Cell<B>) -> Stream<B> {
+        let _caller = CallerLocation::enter();
         self.snapshot(cb, |_a: &A, b: &B| b.clone())
     }
 
     /// A variant of [`snapshot`][Stream::snapshot] that captures the
     /// value of two cells.
+    #[track_caller]
     pub fn snapshot3<
         B: Send + Clone + 'static,
         C: Send + Clone + 'static,
@@ -107,6 +218,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
         cc: &Cell<C>,
         mut f: FN,
     ) -> Stream<D> {
+        let _caller = CallerLocation::enter();
         let mut deps = if let Some(deps2) = f.deps_op() {
             deps2.clone()
         } else {
@@ -122,6 +234,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
 
     /// A variant of [`snapshot`][Stream::snapshot] that captures the
     /// value of three cells.
+    #[track_caller]
     pub fn snapshot4<
         B: Send + Clone + 'static,
         C: Send + Clone + 'static,
@@ -135,6 +248,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
         cd: &Cell<D>,
         mut f: FN,
     ) -> Stream<E> {
+        let _caller = CallerLocation::enter();
         let mut deps = if let Some(deps2) = f.deps_op() {
             deps2.clone()
         } else {
@@ -155,6 +269,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
 
     /// A variant of [`snapshot`][Stream::snapshot] that captures the
     /// value of four cells.
+    #[track_caller]
     pub fn snapshot5<
         B: Send + Clone + 'static,
         C: Send + Clone + 'static,
@@ -170,6 +285,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
         ce: &Cell<E>,
         mut f: FN,
     ) -> Stream<F> {
+        let _caller = CallerLocation::enter();
         let mut deps = if let Some(deps2) = f.deps_op() {
             deps2.clone()
         } else {
@@ -192,6 +308,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
 
     /// A variant of [`snapshot`][Stream::snapshot] that captures the
     /// value of five cells.
+    #[track_caller]
     pub fn snapshot6<
         B: Send + Clone + 'static,
         C: Send + Clone + 'static,
@@ -209,6 +326,7 @@ impl<A: Clone + Send + 'static> Stream<A> {
         cf: &Cell<F>,
         mut f: FN,
     ) -> Stream<G> {
+        let _caller = CallerLocation::enter();
         let mut deps = if let Some(deps2) = f.deps_op() {
             deps2.clone()
         } else {
@@ -233,6 +351,23 @@ impl<A: Clone + Send + 'static> Stream<A> {
         )
     }
 
+    /// A variant of [`snapshot`][Stream::snapshot] that captures the
+    /// values of a tuple of up to 12 cells, such as `(&cb, &cc, &cd)`,
+    /// passed to the function as a tuple.
+    #[track_caller]
+    pub fn snapshot_n<
+        CS: CellTuple,
+        B: Clone + Send + 'static,
+        FN: IsLambda2<A, CS::Values, B> + Send + Sync + 'static,
+    >(
+        &self,
+        cells: CS,
+        f: FN,
+    ) -> Stream<B> {
+        let _caller = CallerLocation::enter();
+        self.snapshot(&cells.combine(), f)
+    }
+
     /// Transform this `Stream`'s event values with the supplied
     /// function.
     ///
@@ -240,30 +375,128 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// [`Cell::sample`], in which case it's equivalent to
     /// [`snapshot`][Stream::snapshot]ing the cell. In addition, the
     /// function must be referentially transparent.
+    #[track_caller]
     pub fn map<B: Send + Clone + 'static, FN: IsLambda1<A, B> + Send + Sync + 'static>(
         &self,
         f: FN,
     ) -> Stream<B> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.map(f),
         }
     }
 
     /// Transform this `Stream`'s event values into the specified constant value.
+    #[track_caller]
     pub fn map_to<B: Send + Sync + Clone + 'static>(&self, b: B) -> Stream<B> {
+        let _caller = CallerLocation::enter();
         self.map(move |_: &A| b.clone())
     }
 
     /// Return a `Stream` that only outputs events for which the predicate returns `true`.
+    #[track_caller]
     pub fn filter<PRED: IsLambda1<A, bool> + Send + Sync + 'static>(
         &self,
         pred: PRED,
     ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.filter(pred),
         }
     }
 
+    /// Return a `Stream` that only outputs the events whose values
+    /// differ from the value `ca` has before the transaction.
+    ///
+    /// Dropped events don't reach the nodes that depend on the returned
+    /// stream, so they do no work for them.
+    #[track_caller]
+    pub fn calm(&self, ca: &Cell<A>) -> Stream<A>
+    where
+        A: PartialEq,
+    {
+        let _caller = CallerLocation::enter();
+        self.calm_by(ca, |a: &A, b: &A| a == b)
+    }
+
+    /// A variant of [`calm`][Stream::calm] that compares values with
+    /// `eq`, which is given the value of `ca` first.
+    #[track_caller]
+    pub fn calm_by<EQ: IsLambda2<A, A, bool> + Send + Sync + 'static>(
+        &self,
+        ca: &Cell<A>,
+        eq: EQ,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        Stream {
+            impl_: self.impl_.calm_by(&ca.impl_, eq),
+        }
+    }
+
+    /// Transform this `Stream`'s event values with a function that can
+    /// fail, giving a `Stream` of its results.
+    ///
+    /// See [`Stream::split_result`] for handling the errors separately.
+    #[track_caller]
+    pub fn try_map<
+        B: Clone + Send + 'static,
+        E: Clone + Send + 'static,
+        FN: IsLambda1<A, Result<B, E>> + Send + Sync + 'static,
+    >(
+        &self,
+        f: FN,
+    ) -> Stream<Result<B, E>> {
+        let _caller = CallerLocation::enter();
+        self.map(f)
+    }
+
+    /// A variant of [`filter`][Stream::filter] for a predicate that can
+    /// fail.
+    ///
+    /// Events for which the predicate returns `Ok(true)` are passed on
+    /// as `Ok`, those for which it returns `Ok(false)` are dropped, and
+    /// errors are passed on as `Err`.
+    #[track_caller]
+    pub fn try_filter<
+        E: Clone + Send + 'static,
+        PRED: IsLambda1<A, Result<bool, E>> + Send + Sync + 'static,
+    >(
+        &self,
+        mut pred: PRED,
+    ) -> Stream<Result<A, E>> {
+        let _caller = CallerLocation::enter();
+        let deps = lambda1_deps(&pred);
+        self.map(lambda1(
+            move |a: &A| match pred.call(a) {
+                Ok(true) => Some(Ok(a.clone())),
+                Ok(false) => None,
+                Err(e) => Some(Err(e)),
+            },
+            deps,
+        ))
+        .filter_option()
+    }
+
+    /// A variant of [`snapshot`][Stream::snapshot] for a function that
+    /// can fail, giving a `Stream` of its results.
+    ///
+    /// See [`Stream::filter_ok`] and [`Stream::errors`] for taking the
+    /// results apart again.
+    #[track_caller]
+    pub fn try_snapshot<
+        B: Clone + Send + 'static,
+        C: Clone + Send + 'static,
+        E: Clone + Send + 'static,
+        FN: IsLambda2<A, B, Result<C, E>> + Send + Sync + 'static,
+    >(
+        &self,
+        cb: &Cell<B>,
+        f: FN,
+    ) -> Stream<Result<C, E>> {
+        let _caller = CallerLocation::enter();
+        self.snapshot(cb, f)
+    }
+
     /// Variant of [`merge`][Stream::merge] that merges two streams.
     ///
     /// In the case where two events are simultaneous (both in the
@@ -275,7 +508,9 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// `s1.merge(s2, |l, _r| l)`. The name `or_else` is used instead
     /// of `merge` to make it clear that care should be taken because
     /// events can be dropped.
+    #[track_caller]
     pub fn or_else(&self, s2: &Stream<A>) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         self.merge(s2, |lhs: &A, _rhs: &A| lhs.clone())
     }
 
@@ -289,19 +524,72 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// transaction. The event from `self` will appear at the left
     /// input of the combining function, and the event from `s2` will
     /// appear at the right.
+    #[track_caller]
     pub fn merge<FN: IsLambda2<A, A, A> + Send + Sync + 'static>(
         &self,
         s2: &Stream<A>,
         f: FN,
     ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.merge(&s2.impl_, f),
         }
     }
 
+    /// Merge a runtime-sized collection of streams into one, as a
+    /// single node rather than a chain of [`merge`][Stream::merge]s.
+    ///
+    /// Simultaneous events are combined with `f` from left to right,
+    /// as chained merges would. If `streams` is empty the returned
+    /// stream never fires.
+    #[track_caller]
+    pub fn merge_all<FN: IsLambda2<A, A, A> + Send + Sync + 'static>(
+        sodium_ctx: &SodiumCtx,
+        streams: Vec<Stream<A>>,
+        f: FN,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        let streams: Vec<StreamImpl<A>> = streams.into_iter().map(|s| s.impl_).collect();
+        Stream {
+            impl_: StreamImpl::merge_all(&sodium_ctx.impl_, &streams, f),
+        }
+    }
+
+    /// Merge the streams currently held in `csa`, following the
+    /// collection as it changes.
+    ///
+    /// Simultaneous events are combined with `f` from left to right,
+    /// in the order given by [`StreamCollection::to_streams`]. For a
+    /// `HashMap` that order is unspecified, so `f` should not depend
+    /// on it.
+    ///
+    /// As with [`Cell::switch_s`], a change of collection takes effect
+    /// from the next transaction: events are merged from the streams
+    /// held before the change in the transaction where it happens.
+    /// Streams that are no longer in the collection stop being
+    /// depended on.
+    #[track_caller]
+    pub fn merge_dynamic<C: StreamCollection<A>, FN: IsLambda2<A, A, A> + Send + Sync + 'static>(
+        csa: &Cell<C>,
+        f: FN,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        let css = csa.map(|c: &C| {
+            c.to_streams()
+                .into_iter()
+                .map(|s| s.impl_)
+                .collect::<Vec<StreamImpl<A>>>()
+        });
+        Stream {
+            impl_: StreamImpl::merge_dynamic(&css.impl_, f),
+        }
+    }
+
     /// Returns a cell with the specified initial value, which is
     /// updated by this stream's event values.
+    #[track_caller]
     pub fn hold(&self, a: A) -> Cell<A> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.hold(a),
         }
@@ -309,15 +597,45 @@ impl<A: Clone + Send + 'static> Stream<A> {
 
     /// A variant of [`hold`][Stream::hold] that uses an initial value
     /// returned by [`Cell::sample_lazy`].
+    #[track_caller]
     pub fn hold_lazy(&self, a: Lazy<A>) -> Cell<A> {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.hold_lazy(a),
         }
     }
 
+    /// A variant of [`hold`][Stream::hold] that gives a calm cell:
+    /// events equal to the cell's value don't update it, and its
+    /// [`updates`][Cell::updates] don't fire for them.
+    #[track_caller]
+    pub fn hold_calm(&self, a: A) -> Cell<A>
+    where
+        A: PartialEq,
+    {
+        let _caller = CallerLocation::enter();
+        self.hold_calm_by(a, |a: &A, b: &A| a == b)
+    }
+
+    /// A variant of [`hold_calm`][Stream::hold_calm] that compares
+    /// values with `eq`, which is given the value of the cell first.
+    #[track_caller]
+    pub fn hold_calm_by<EQ: IsLambda2<A, A, bool> + Send + Sync + 'static>(
+        &self,
+        a: A,
+        eq: EQ,
+    ) -> Cell<A> {
+        let _caller = CallerLocation::enter();
+        Cell {
+            impl_: self.impl_.hold_calm_by(Lazy::of_value(a), eq),
+        }
+    }
+
     /// Return a stream that only outputs events from the input stream
     /// when the specified cell's value is true.
+    #[track_caller]
     pub fn gate(&self, cpred: &Cell<bool>) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         let cpred = cpred.clone();
         let cpred_dep = cpred.to_dep();
         self.filter(lambda1(move |_: &A| cpred.sample(), vec![cpred_dep]))
@@ -326,7 +644,9 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// Return a stream that outputs only one value, which is the next
     /// event of the input stream, starting from the transaction in
     /// `once` was invoked.
+    #[track_caller]
     pub fn once(&self) -> Stream<A> {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.once(),
         }
@@ -335,23 +655,27 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// Transform an event with a generalized state loop (a Mealy
     /// machine). The function is passed the input and the old state
     /// and returns the new state and output value.
+    #[track_caller]
     pub fn collect<B, S, F>(&self, init_state: S, f: F) -> Stream<B>
     where
         B: Send + Clone + 'static,
         S: Send + Clone + 'static,
         F: IsLambda2<A, S, (B, S)> + Send + Sync + 'static,
     {
+        let _caller = CallerLocation::enter();
         self.collect_lazy(Lazy::new(move || init_state.clone()), f)
     }
 
     /// A variant of [`collect`][Stream::collect] that takes an
     /// initial state that is returned by [`Cell::sample_lazy`].
+    #[track_caller]
     pub fn collect_lazy<B, S, F>(&self, init_state: Lazy<S>, f: F) -> Stream<B>
     where
         B: Send + Clone + 'static,
         S: Send + Clone + 'static,
         F: IsLambda2<A, S, (B, S)> + Send + Sync + 'static,
     {
+        let _caller = CallerLocation::enter();
         Stream {
             impl_: self.impl_.collect_lazy(init_state, f),
         }
@@ -365,33 +689,99 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// [`Cell::sample`], in which case it's equivalent to
     /// [`snapshot`][Stream::snapshot]ing the cell. In additon, the
     /// function must be referentially transparent.
+    #[track_caller]
     pub fn accum<S, F>(&self, init_state: S, f: F) -> Cell<S>
     where
         S: Send + Clone + 'static,
         F: IsLambda2<A, S, S> + Send + Sync + 'static,
     {
+        let _caller = CallerLocation::enter();
         self.accum_lazy(Lazy::new(move || init_state.clone()), f)
     }
 
     /// A variant of [`accum`][Stream::accum] that takes an initial
     /// state returned by [`Cell::sample_lazy`].
+    #[track_caller]
     pub fn accum_lazy<S, F>(&self, init_state: Lazy<S>, f: F) -> Cell<S>
     where
         S: Send + Clone + 'static,
         F: IsLambda2<A, S, S> + Send + Sync + 'static,
     {
+        let _caller = CallerLocation::enter();
         Cell {
             impl_: self.impl_.accum_lazy(init_state, f),
         }
     }
 
+    /// Return a stream that outputs each event of this stream
+    /// `duration` later, according to the given timer system.
+    ///
+    /// Every event is kept, in order, and each is output in a
+    /// transaction of its own.
+    #[track_caller]
+    pub fn delay<TS: TimerSystem + ?Sized>(
+        &self,
+        timer_system: &TS,
+        duration: Duration,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        timer::delay(self, timer_system, duration)
+    }
+
+    /// Return a stream that outputs an event of this stream only once
+    /// `duration` has passed without another event following it.
+    ///
+    /// Each new event restarts the wait, so a burst of events comes
+    /// out as its last event, `duration` after the end of the burst.
+    #[track_caller]
+    pub fn debounce<TS: TimerSystem + ?Sized>(
+        &self,
+        timer_system: &TS,
+        duration: Duration,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        timer::debounce(self, timer_system, duration)
+    }
+
+    /// Return a stream that outputs at most one event of this stream
+    /// per `duration`.
+    ///
+    /// An event outputs straight away when none has been output in the
+    /// last `duration`. Otherwise the latest such event is held back
+    /// and output once `duration` has passed since the previous
+    /// output, so the last event of a burst is never lost.
+    #[track_caller]
+    pub fn throttle<TS: TimerSystem + ?Sized>(
+        &self,
+        timer_system: &TS,
+        duration: Duration,
+    ) -> Stream<A> {
+        let _caller = CallerLocation::enter();
+        timer::throttle(self, timer_system, duration)
+    }
+
+    /// Return a [`futures_core::Stream`] of this stream's events, for
+    /// use from async code.
+    ///
+    /// Events are buffered until they are taken according to
+    /// `buffer`. Dropping the returned stream deregisters its
+    /// listener.
+    #[cfg(feature = "async")]
+    #[track_caller]
+    pub fn to_async_stream(&self, buffer: AsyncBuffer) -> AsyncStream<A> {
+        let _caller = CallerLocation::enter();
+        AsyncStream::new(self, buffer)
+    }
+
     /// A variant of [`listen`][Stream::listen] that will deregister
     /// the listener automatically if the listener is
     /// garbage-collected.
     ///
     /// With [`listen`][Stream::listen] the listener is only
     /// deregistered if [`Listener::unlisten`] is called explicitly.
+    #[track_caller]
     pub fn listen_weak<K: IsLambda1<A, ()> + Send + Sync + 'static>(&self, k: K) -> Listener {
+        let _caller = CallerLocation::enter();
         Listener {
             impl_: self.impl_.listen_weak(k),
         }
@@ -409,7 +799,9 @@ impl<A: Clone + Send + 'static> Stream<A> {
     /// handler should not block. It also is not allowed to use
     /// [`CellSink::send`][crate::CellSink::send] or
     /// [`StreamSink::send`][crate::StreamSink::send] in the handler.
+    #[track_caller]
     pub fn listen<K: IsLambda1<A, ()> + Send + Sync + 'static>(&self, k: K) -> Listener {
+        let _caller = CallerLocation::enter();
         Listener {
             impl_: self.impl_.listen(k),
         }
@@ -0,0 +1,42 @@
+use crate::Stream;
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+/// A collection of streams, such as a `Vec` or a map with streams as
+/// its values, that can be merged while it changes with
+/// [`Stream::merge_dynamic`].
+pub trait StreamCollection<A>: Clone + Send + 'static {
+    /// Return the streams in the collection, in the order their
+    /// simultaneous events should be combined.
+    fn to_streams(&self) -> Vec<Stream<A>>;
+}
+
+impl<A: Send + 'static> StreamCollection<A> for Vec<Stream<A>> {
+    fn to_streams(&self) -> Vec<Stream<A>> {
+        self.clone()
+    }
+}
+
+/// The streams are in the map's iteration order, which is unspecified.
+impl<A, K, S> StreamCollection<A> for HashMap<K, Stream<A>, S>
+where
+    A: Send + 'static,
+    K: Eq + Hash + Clone + Send + 'static,
+    S: BuildHasher + Clone + Send + 'static,
+{
+    fn to_streams(&self) -> Vec<Stream<A>> {
+        self.values().cloned().collect()
+    }
+}
+
+/// The streams are in the order of their keys.
+impl<A, K> StreamCollection<A> for BTreeMap<K, Stream<A>>
+where
+    A: Send + 'static,
+    K: Ord + Clone + Send + 'static,
+{
+    fn to_streams(&self) -> Vec<Stream<A>> {
+        self.values().cloned().collect()
+    }
+}
@@ -1,20 +1,35 @@
+use crate::impl_::name::CallerLocation;
 use crate::impl_::stream_loop::StreamLoop as StreamLoopImpl;
+use crate::LoopError;
 use crate::SodiumCtx;
 use crate::Stream;
 
 /// A forward reference of a [`Stream`] for creating dependency loops.
+///
+/// A `StreamLoop` created inside a transaction must be looped before
+/// that transaction closes; otherwise closing it panics with a
+/// [`LoopError::NotLooped`] naming the loop.
 pub struct StreamLoop<A> {
     pub impl_: StreamLoopImpl<A>,
 }
 
 impl<A: Send + Clone + 'static> StreamLoop<A> {
     /// Create a new `StreamLoop` in the given context.
+    #[track_caller]
     pub fn new(sodium_ctx: &SodiumCtx) -> StreamLoop<A> {
+        let _caller = CallerLocation::enter();
         StreamLoop {
             impl_: StreamLoopImpl::new(&sodium_ctx.impl_),
         }
     }
 
+    /// Attach a label to this loop, to be shown with its source location
+    /// in [`LoopError`]s, and return the loop.
+    pub fn named(self, label: &str) -> StreamLoop<A> {
+        self.impl_.gc_node.set_label(label);
+        self
+    }
+
     /// Return a [`Stream`] that is equivalent to this `StreamLoop`
     /// once it has been resolved by calling
     /// [`loop_`][StreamLoop::loop_].
@@ -32,7 +47,20 @@ impl<A: Send + Clone + 'static> StreamLoop<A> {
     /// create an explicit transaction, either with
     /// [`SodiumCtx::transaction`] or
     /// [`Transaction::new`][crate::Transaction::new].
+    ///
+    /// # Panics
+    ///
+    /// Panics if this `StreamLoop` has already been looped, or if `sa`
+    /// belongs to another [`SodiumCtx`]. See
+    /// [`try_loop_`][StreamLoop::try_loop_].
     pub fn loop_(&self, sa: &Stream<A>) {
         self.impl_.loop_(&sa.impl_);
     }
+
+    /// A variant of [`loop_`][StreamLoop::loop_] that returns an error
+    /// rather than panicking if this `StreamLoop` has already been
+    /// looped or `sa` belongs to another [`SodiumCtx`].
+    pub fn try_loop_(&self, sa: &Stream<A>) -> Result<(), LoopError> {
+        self.impl_.try_loop_(&sa.impl_)
+    }
 }
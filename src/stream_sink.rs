@@ -1,3 +1,4 @@
+use crate::impl_::name::CallerLocation;
 use crate::impl_::stream_sink::StreamSink as StreamSinkImpl;
 use crate::sodium_ctx::SodiumCtx;
 use crate::stream::Stream;
@@ -27,7 +28,9 @@ impl<A: Clone + Send + 'static> StreamSink<A> {
     /// `StreamSink` constructed with `StreamSink::new` it will
     /// panic. If you need to do this then use
     /// [`StreamSink::new_with_coalescer`].
+    #[track_caller]
     pub fn new(sodium_ctx: &SodiumCtx) -> StreamSink<A> {
+        let _caller = CallerLocation::enter();
         StreamSink {
             impl_: StreamSinkImpl::new(&sodium_ctx.impl_),
         }
@@ -40,10 +43,12 @@ impl<A: Clone + Send + 'static> StreamSink<A> {
     /// in a single transaction the events will be combined into a
     /// single event using the specified combining function. The
     /// combining function should be _associative_.
+    #[track_caller]
     pub fn new_with_coalescer<COALESCER: FnMut(&A, &A) -> A + Send + 'static>(
         sodium_ctx: &SodiumCtx,
         coalescer: COALESCER,
     ) -> StreamSink<A> {
+        let _caller = CallerLocation::enter();
         StreamSink {
             impl_: StreamSinkImpl::new_with_coalescer(&sodium_ctx.impl_, coalescer),
         }
@@ -62,8 +67,47 @@ impl<A: Clone + Send + 'static> StreamSink<A> {
     /// associated with this `StreamSink`.
     ///
     /// This method may not be called in handlers registered with
-    /// [`Stream::listen`] or [`Cell::listen`][crate::Cell::listen].
+    /// [`Stream::listen`] or [`Cell::listen`][crate::Cell::listen], or
+    /// in the lambdas passed to combinators. Use
+    /// [`send_later`][StreamSink::send_later] there instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from a listener or lambda while the context is
+    /// updating the graph.
+    ///
+    /// Panics if a lambda or listener run for the value panics, after
+    /// rolling the transaction back as described under
+    /// [`SodiumCtx::transaction`][crate::SodiumCtx::transaction].
     pub fn send(&self, a: A) {
         self.impl_.send(a);
     }
+
+    /// Send a value in a new transaction, once the current transaction
+    /// is over, or at once if there is no current transaction.
+    ///
+    /// Unlike [`send`][StreamSink::send], this may be called from
+    /// listeners and lambdas. All the values sent this way during one
+    /// transaction are sent together in the next one, in the order they
+    /// were given. On a sink made with
+    /// [`new_with_coalescer`][StreamSink::new_with_coalescer] they are
+    /// combined by the coalescer. On any other sink only the last of
+    /// them is fired.
+    pub fn send_later(&self, a: A) {
+        self.impl_.send_later(a);
+    }
+
+    /// Return a future that sends each item of `stream` into this
+    /// `StreamSink`, each in a transaction of its own, and completes
+    /// when `stream` ends.
+    ///
+    /// The future does nothing until it is spawned on an executor or
+    /// awaited.
+    #[cfg(feature = "async")]
+    pub fn feed<S: futures_core::Stream<Item = A>>(
+        &self,
+        stream: S,
+    ) -> impl std::future::Future<Output = ()> {
+        crate::async_::feed(self.clone(), stream)
+    }
 }
@@ -1,22 +1,33 @@
-use crate::{lambda1, Cell, CellLoop, Operational, SodiumCtx, Stream, StreamLoop, StreamSink};
+use crate::{
+    assert_no_leaks, lambda1, Cell, CellFn, CellLoop, CellSink, LoopError, Operational, SodiumCtx,
+    Stream, StreamLoop, StreamSink,
+};
 
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
+#[cfg(feature = "async")]
+mod async_test;
+mod cell_collection_test;
+mod deep_graph_test;
+mod introspect_test;
+mod local_sodium_ctx_test;
 mod mem_test;
 mod node_test;
+mod panic_test;
+mod sodium_ctx_test;
+mod threaded_mode_test;
+mod timer_test;
+#[cfg(feature = "tracing")]
+mod tracing_test;
 
 fn init() {
     let _ = env_logger::builder().is_test(true).try_init();
 }
 
 pub fn assert_memory_freed(sodium_ctx: &SodiumCtx) {
-    sodium_ctx.impl_.collect_cycles();
-    let node_count = sodium_ctx.impl_.node_count();
-    let node_ref_count = sodium_ctx.impl_.node_ref_count();
-    println!();
-    println!("node_count {}", node_count);
-    println!("node_ref_count {}", node_ref_count);
-    assert_eq!(node_count, 0);
+    assert_no_leaks!(sodium_ctx);
+    assert_eq!(sodium_ctx.impl_.node_count(), 0);
 }
 
 #[test]
@@ -167,6 +178,121 @@ fn filter_option() {
     assert_memory_freed(sodium_ctx);
 }
 
+#[test]
+fn try_map() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<&'static str> = sodium_ctx.new_stream_sink();
+        let parsed = s.stream().try_map(|a: &&'static str| a.parse::<i32>());
+        let (oks, errors) = parsed.split_result();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l1;
+        let l2;
+        {
+            let out = out.clone();
+            l1 = oks.listen(move |a: &i32| out.lock().unwrap().push(format!("ok {}", a)));
+        }
+        {
+            let out = out.clone();
+            l2 = errors
+                .map(|e: &std::num::ParseIntError| e.to_string())
+                .listen(move |e: &String| out.lock().unwrap().push(format!("err {}", e)));
+        }
+        s.send("1");
+        s.send("x");
+        s.send("3");
+        {
+            let lock = out.lock();
+            let out: &Vec<String> = lock.as_ref().unwrap();
+            assert_eq!(
+                vec!["ok 1", "err invalid digit found in string", "ok 3"],
+                *out
+            );
+        }
+        l1.unlisten();
+        l2.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn try_filter() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s
+                .stream()
+                .try_filter(|a: &i32| {
+                    if *a < 0 {
+                        Err(format!("{} is negative", a))
+                    } else {
+                        Ok(*a % 2 == 0)
+                    }
+                })
+                .map_err(|e: &String| e.to_uppercase())
+                .listen(move |r: &Result<i32, String>| out.lock().unwrap().push(r.clone()));
+        }
+        s.send(1);
+        s.send(2);
+        s.send(-3);
+        {
+            let lock = out.lock();
+            let out: &Vec<Result<i32, String>> = lock.as_ref().unwrap();
+            assert_eq!(vec![Ok(2), Err(String::from("-3 IS NEGATIVE"))], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn try_snapshot() {
+    let mut sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &mut sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let divisor = sodium_ctx.new_cell_sink(2);
+        let offset = sodium_ctx.new_cell_sink(100);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let offset = offset.cell();
+            let offset_dep = offset.to_dep();
+            l = s
+                .stream()
+                .try_snapshot(&divisor.cell(), |a: &i32, b: &i32| {
+                    a.checked_div(*b).ok_or("divide by zero")
+                })
+                .map_ok(lambda1(
+                    move |q: &i32| *q + offset.sample(),
+                    vec![offset_dep],
+                ))
+                .filter_ok()
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        s.send(10);
+        divisor.send(0);
+        s.send(10);
+        offset.send(200);
+        divisor.send(5);
+        s.send(10);
+        {
+            let lock = out.lock();
+            let out: &Vec<i32> = lock.as_ref().unwrap();
+            assert_eq!(vec![105, 202], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
 #[test]
 fn loop_stream1() {
     let mut sodium_ctx = SodiumCtx::new();
@@ -232,6 +358,103 @@ fn loop_stream2() {
     assert_memory_freed(sodium_ctx);
 }
 
+#[test]
+fn loop_not_looped() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            sodium_ctx.transaction(|| {
+                let sl: StreamLoop<i32> = sodium_ctx.new_stream_loop().named("forgotten");
+                let _c = sl.stream().hold(0);
+            })
+        }));
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert!(
+            message.starts_with("StreamLoop::new \"forgotten\" at src/tests.rs:"),
+            "{}",
+            message
+        );
+        assert!(
+            message.ends_with("was not looped before the transaction it was created in closed"),
+            "{}",
+            message
+        );
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _t = sodium_ctx.new_transaction();
+            let _cl: CellLoop<i32> = sodium_ctx.new_cell_loop();
+        }));
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.starts_with("CellLoop::new at "), "{}", message);
+        // loops made outside of a transaction are not checked
+        let _sl: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+        sodium_ctx.transaction(|| {});
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn loop_not_looped_in_lambda() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<bool> = sodium_ctx.new_stream_sink();
+        let l;
+        {
+            let sodium_ctx = sodium_ctx.clone();
+            l = s.stream().listen(move |forget: &bool| {
+                let sl: StreamLoop<bool> = sodium_ctx.new_stream_loop().named("in listener");
+                if !*forget {
+                    sl.loop_(&sodium_ctx.new_stream());
+                }
+            });
+        }
+        s.send(false);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| s.send(true)));
+        let message = result.unwrap_err().downcast::<String>().unwrap();
+        assert!(
+            message.starts_with("StreamLoop::new \"in listener\" at src/tests.rs:"),
+            "{}",
+            message
+        );
+        s.send(false);
+        l.unlisten();
+        // the check isn't left over for the next transaction
+        sodium_ctx.transaction(|| {});
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn try_loop() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    let other_ctx = SodiumCtx::new();
+    {
+        let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let ca = sodium_ctx.new_cell(1);
+        sodium_ctx.transaction(|| {
+            let sl: StreamLoop<i32> = sodium_ctx.new_stream_loop();
+            assert!(matches!(
+                sl.try_loop_(&other_ctx.new_stream()),
+                Err(LoopError::WrongContext { .. })
+            ));
+            assert_eq!(sl.try_loop_(&sa.stream()), Ok(()));
+            let err = sl.try_loop_(&sa.stream()).unwrap_err();
+            assert!(matches!(err, LoopError::AlreadyLooped { .. }));
+            assert!(err.to_string().ends_with("was already looped"));
+            let cl: CellLoop<i32> = sodium_ctx.new_cell_loop();
+            assert_eq!(cl.try_loop_(&ca), Ok(()));
+            assert!(matches!(
+                cl.try_loop_(&ca),
+                Err(LoopError::AlreadyLooped { .. })
+            ));
+        });
+    }
+    assert_memory_freed(sodium_ctx);
+    assert_memory_freed(&other_ctx);
+}
+
 #[test]
 fn gate() {
     init();
@@ -632,193 +855,761 @@ fn send_before_listen() {
             );
         }
     }
-    l.unlisten();
+    l.unlisten();
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn send_in_listener() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s1: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let s2: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c2 = s2.stream().hold(0);
+        let l;
+        {
+            let s2 = s2.clone();
+            l = s1
+                .stream()
+                .map(|a: &i32| *a * 10)
+                .listen(move |a: &i32| s2.send(*a));
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| s1.send(1)));
+        let message = result.err().unwrap().downcast::<&str>().unwrap();
+        assert!(message.contains("use send_later"), "{}", message);
+        assert_eq!(c2.sample(), 0);
+        s2.send(2);
+        assert_eq!(c2.sample(), 2);
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn send_later() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s1: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let s2: StreamSink<i32> =
+            sodium_ctx.new_stream_sink_with_coalescer(|a: &i32, b: &i32| *a + *b);
+        let c1 = s1.stream().hold(0);
+        let out = Arc::new(Mutex::new(Vec::<String>::new()));
+        let mut listeners = Vec::new();
+        {
+            let out = out.clone();
+            let s2 = s2.clone();
+            listeners.push(s1.stream().listen(move |a: &i32| {
+                out.lock().unwrap().push(format!("s1 {}", a));
+                s2.send_later(*a * 10);
+                s2.send_later(*a * 100);
+            }));
+        }
+        {
+            let out = out.clone();
+            let c1 = c1.clone();
+            listeners.push(s2.stream().listen(move |a: &i32| {
+                out.lock()
+                    .unwrap()
+                    .push(format!("s2 {} after {}", a, c1.sample()))
+            }));
+        }
+        s1.send(1);
+        s2.send_later(5);
+        {
+            let lock = out.lock();
+            let out: &Vec<String> = lock.as_ref().unwrap();
+            assert_eq!(vec!["s1 1", "s2 110 after 1", "s2 5 after 1"], *out);
+        }
+        for l in listeners {
+            l.unlisten();
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn send_later_without_coalescer() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s1: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let s2: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::<i32>::new()));
+        let mut listeners = Vec::new();
+        {
+            let s2 = s2.clone();
+            listeners.push(s1.stream().listen(move |a: &i32| {
+                s2.send_later(*a * 10);
+                s2.send_later(*a * 100);
+            }));
+        }
+        {
+            let out = out.clone();
+            listeners.push(
+                s2.stream()
+                    .listen(move |a: &i32| out.lock().unwrap().push(*a)),
+            );
+        }
+        s1.send(1);
+        assert_eq!(vec![100], *out.lock().unwrap());
+        for l in listeners {
+            l.unlisten();
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    let l;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let a = sodium_ctx.new_cell_sink(1);
+        let b = sodium_ctx.new_cell_sink(5);
+        {
+            let out = out.clone();
+            l = a
+                .cell()
+                .lift2(&b.cell(), |aa: &i32, bb: &i32| format!("{} {}", aa, bb))
+                .listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+        }
+        a.send(12);
+        b.send(6);
+        {
+            let l = out.lock();
+            let out: &Vec<String> = l.as_ref().unwrap();
+            assert_eq!(
+                vec!["1 5", "12 5", "12 6"],
+                out.iter().map(|s| s.as_str()).collect::<Vec<&str>>()
+            );
+        }
+    }
+    l.unlisten();
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift_glitch() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    let l;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let a = sodium_ctx.new_cell_sink(1);
+        let ac = a.cell();
+        let a3 = ac.map(|x: &i32| x * 3);
+        let a5 = ac.map(|x: &i32| x * 5);
+        let b = a3.lift2(&a5, |x: &i32, y: &i32| format!("{} {}", x, y));
+        {
+            let out = out.clone();
+            l = b.listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+        }
+        a.send(2);
+        {
+            let l = out.lock();
+            let out: &Vec<String> = l.as_ref().unwrap();
+            assert_eq!(vec!["3 5", "6 10"], *out);
+        }
+    }
+    l.unlisten();
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift_from_simultaneous() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    let l;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let (b1, b2) = sodium_ctx.transaction(|| {
+            let b1 = sodium_ctx.new_cell_sink(3);
+            let b2 = sodium_ctx.new_cell_sink(5);
+            b2.send(7);
+            (b1, b2)
+        });
+        {
+            let out = out.clone();
+            l = b1
+                .cell()
+                .lift2(&b2.cell(), |x: &i32, y: &i32| x + y)
+                .listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
+        }
+        {
+            let l = out.lock();
+            let out: &Vec<i32> = l.as_ref().unwrap();
+            assert_eq!(vec![10], *out);
+        }
+    }
+    l.unlisten();
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn router1() {
+    #[derive(Clone)]
+    pub struct Packet {
+        pub address: i32,
+        pub payload: &'static str,
+    }
+    impl Packet {
+        fn new(address: i32, payload: &'static str) -> Packet {
+            Packet { address, payload }
+        }
+    }
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink::<Packet>();
+        let r = sodium_ctx.new_router(&s.stream(), |pkt: &Packet| vec![pkt.address]);
+        let one = r.filter_matches(&1);
+        let out_one = Arc::new(Mutex::new(Vec::<&'static str>::new()));
+        let kill_one;
+        {
+            let out_one = out_one.clone();
+            kill_one =
+                one.listen(move |p: &Packet| out_one.lock().as_mut().unwrap().push(p.payload));
+        }
+        let two = r.filter_matches(&2);
+        let out_two = Arc::new(Mutex::new(Vec::<&'static str>::new()));
+        let kill_two;
+        {
+            let out_two = out_two.clone();
+            kill_two =
+                two.listen(move |p: &Packet| out_two.lock().as_mut().unwrap().push(p.payload));
+        }
+        let three = r.filter_matches(&3);
+        let out_three = Arc::new(Mutex::new(Vec::<&'static str>::new()));
+        let kill_three;
+        {
+            let out_three = out_three.clone();
+            kill_three =
+                three.listen(move |p: &Packet| out_three.lock().as_mut().unwrap().push(p.payload));
+        }
+        s.send(Packet::new(1, "dog"));
+        s.send(Packet::new(3, "manuka"));
+        s.send(Packet::new(2, "square"));
+        s.send(Packet::new(3, "tawa"));
+        s.send(Packet::new(2, "circle"));
+        s.send(Packet::new(1, "otter"));
+        s.send(Packet::new(1, "lion"));
+        s.send(Packet::new(2, "rectangle"));
+        s.send(Packet::new(3, "rata"));
+        s.send(Packet::new(4, "kauri"));
+        kill_one.unlisten();
+        kill_two.unlisten();
+        kill_three.unlisten();
+        {
+            let l1 = out_one.lock();
+            let out_one: &Vec<&'static str> = l1.as_ref().unwrap();
+            let l2 = out_two.lock();
+            let out_two: &Vec<&'static str> = l2.as_ref().unwrap();
+            let l3 = out_three.lock();
+            let out_three: &Vec<&'static str> = l3.as_ref().unwrap();
+            assert_eq!(vec!["dog", "otter", "lion"], *out_one);
+            assert_eq!(vec!["square", "circle", "rectangle"], *out_two);
+            assert_eq!(vec!["manuka", "tawa", "rata"], *out_three);
+        }
+    }
+}
+
+#[test]
+fn apply() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let cf =
+            sodium_ctx.new_cell_sink(Arc::new(|a: &u8| format!("1 {}", a)) as CellFn<u8, String>);
+        let ca = sodium_ctx.new_cell_sink(5);
+        let l;
+        {
+            let out = out.clone();
+            l = Cell::apply(&cf.cell(), &ca.cell())
+                .listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+        }
+        cf.send(Arc::new(|a: &u8| format!("12 {}", a)));
+        ca.send(6);
+        {
+            let l = out.lock();
+            let out: &Vec<String> = l.as_ref().unwrap();
+            assert_eq!(vec!["1 5", "12 5", "12 6"], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift_n() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let cells: Vec<CellSink<u32>> = (0..10).map(|i| sodium_ctx.new_cell_sink(i)).collect();
+        let c: Vec<Cell<u32>> = cells.iter().map(|cell| cell.cell()).collect();
+        let offset = sodium_ctx.new_cell_sink(1000);
+        let offset_cell = offset.cell();
+        let offset_dep = offset_cell.to_dep();
+        let sum = Cell::lift_n(
+            (
+                &c[0], &c[1], &c[2], &c[3], &c[4], &c[5], &c[6], &c[7], &c[8], &c[9],
+            ),
+            lambda1(
+                move |(a, b, c, d, e, f, g, h, i, j): &(
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                    u32,
+                )| { a + b + c + d + e + f + g + h + i + j + offset_cell.sample() },
+                vec![offset_dep],
+            ),
+        );
+        let l;
+        {
+            let out = out.clone();
+            l = sum.listen(move |a: &u32| out.lock().as_mut().unwrap().push(*a));
+        }
+        cells[3].send(13);
+        sodium_ctx.transaction(|| {
+            cells[0].send(10);
+            cells[9].send(19);
+        });
+        offset.send(2000);
+        cells[1].send(1);
+        {
+            let l = out.lock();
+            let out: &Vec<u32> = l.as_ref().unwrap();
+            assert_eq!(vec![1045, 1055, 1075, 2075], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn snapshot_n() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let cb = sodium_ctx.new_cell_sink(2);
+        let cc = sodium_ctx.new_cell(String::from("c"));
+        let cd = sodium_ctx.new_cell(4u8);
+        let l;
+        {
+            let out = out.clone();
+            l = s
+                .stream()
+                .snapshot_n(
+                    (&cb.cell(), &cc, &cd),
+                    |a: &i32, (b, c, d): &(i32, String, u8)| format!("{} {} {} {}", a, b, c, d),
+                )
+                .listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+        }
+        s.send(1);
+        sodium_ctx.transaction(|| {
+            cb.send(3);
+            s.send(5);
+        });
+        s.send(6);
+        {
+            let l = out.lock();
+            let out: &Vec<String> = l.as_ref().unwrap();
+            assert_eq!(vec!["1 2 c 4", "5 2 c 4", "6 3 c 4"], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn sequence() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sinks: Vec<CellSink<u32>> = (0..100).map(|i| sodium_ctx.new_cell_sink(i)).collect();
+        let node_count = sodium_ctx.impl_.node_count();
+        let c = Cell::sequence(sodium_ctx, sinks.iter().map(|sink| sink.cell()).collect());
+        // one node and its cell, however many inputs there are
+        assert!(sodium_ctx.impl_.node_count() - node_count <= 3);
+        let l;
+        {
+            let out = out.clone();
+            l = c.listen(move |a: &Vec<u32>| {
+                out.lock()
+                    .as_mut()
+                    .unwrap()
+                    .push((a.len(), a[0], a[50], a[99]))
+            });
+        }
+        sinks[50].send(500);
+        sodium_ctx.transaction(|| {
+            sinks[0].send(1000);
+            sinks[99].send(990);
+        });
+        {
+            let l = out.lock();
+            let out: &Vec<(usize, u32, u32, u32)> = l.as_ref().unwrap();
+            assert_eq!(
+                vec![(100, 0, 50, 99), (100, 0, 500, 99), (100, 1000, 500, 990)],
+                *out
+            );
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn lift_all() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sinks: Vec<CellSink<u32>> = (1..=4).map(|i| sodium_ctx.new_cell_sink(i)).collect();
+        let cells: Vec<Cell<u32>> = sinks.iter().map(|sink| sink.cell()).collect();
+        let scale = sodium_ctx.new_cell_sink(1);
+        let scale_cell = scale.cell();
+        let scale_dep = scale_cell.to_dep();
+        let product = Cell::lift_all(
+            sodium_ctx,
+            &cells,
+            lambda1(
+                move |values: &Vec<u32>| values.iter().product::<u32>() * scale_cell.sample(),
+                vec![scale_dep],
+            ),
+        );
+        let l;
+        {
+            let out = out.clone();
+            l = product.listen(move |a: &u32| out.lock().as_mut().unwrap().push(*a));
+        }
+        sinks[1].send(5);
+        scale.send(10);
+        sinks[3].send(1);
+        {
+            let l = out.lock();
+            let out: &Vec<u32> = l.as_ref().unwrap();
+            assert_eq!(vec![24, 60, 150], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn merge_all() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sinks: Vec<StreamSink<String>> = (0..5).map(|_| sodium_ctx.new_stream_sink()).collect();
+        let s = Stream::merge_all(
+            sodium_ctx,
+            sinks.iter().map(|sink| sink.stream()).collect(),
+            |a: &String, b: &String| format!("{}+{}", a, b),
+        );
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+        }
+        sinks[2].send(String::from("c"));
+        sodium_ctx.transaction(|| {
+            sinks[4].send(String::from("e"));
+            sinks[0].send(String::from("a"));
+            sinks[3].send(String::from("d"));
+        });
+        {
+            let l = out.lock();
+            let out: &Vec<String> = l.as_ref().unwrap();
+            assert_eq!(vec!["c", "a+d+e"], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn runtime_sized_combinators_of_nothing() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let c = Cell::sequence(sodium_ctx, Vec::<Cell<u32>>::new());
+        let sum = Cell::lift_all(sodium_ctx, &[], |values: &Vec<u32>| {
+            values.iter().sum::<u32>() + 1
+        });
+        let s = Stream::merge_all(sodium_ctx, Vec::new(), |a: &u32, b: &u32| a + b);
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |a: &u32| out.lock().as_mut().unwrap().push(*a));
+        }
+        assert_eq!(c.sample(), Vec::<u32>::new());
+        assert_eq!(sum.sample(), 1);
+        l.unlisten();
+        assert!(out.lock().as_ref().unwrap().is_empty());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn merge_dynamic() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sinks: Vec<StreamSink<String>> = (0..3).map(|_| sodium_ctx.new_stream_sink()).collect();
+        let css: CellSink<Vec<Stream<String>>> = sodium_ctx.new_cell_sink(vec![sinks[0].stream()]);
+        let s = Stream::merge_dynamic(&css.cell(), |a: &String, b: &String| format!("{}+{}", a, b));
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+        }
+        sinks[0].send(String::from("a"));
+        sinks[1].send(String::from("b"));
+        sodium_ctx.transaction(|| {
+            css.send(vec![sinks[1].stream(), sinks[2].stream()]);
+            sinks[0].send(String::from("c"));
+            sinks[1].send(String::from("d"));
+        });
+        sinks[0].send(String::from("e"));
+        sodium_ctx.transaction(|| {
+            sinks[2].send(String::from("f"));
+            sinks[1].send(String::from("g"));
+        });
+        css.send(Vec::new());
+        sinks[1].send(String::from("h"));
+        {
+            let l = out.lock();
+            let out: &Vec<String> = l.as_ref().unwrap();
+            assert_eq!(vec!["a", "c", "g+f"], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn merge_dynamic_map() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let sb: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let mut streams = BTreeMap::new();
+        streams.insert("a", sa.stream());
+        let css: CellSink<BTreeMap<&'static str, Stream<i32>>> =
+            sodium_ctx.new_cell_sink(streams.clone());
+        let s = Stream::merge_dynamic(&css.cell(), |a: &i32, b: &i32| a + b);
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
+        }
+        sodium_ctx.transaction(|| {
+            sa.send(1);
+            sb.send(10);
+        });
+        streams.insert("b", sb.stream());
+        css.send(streams);
+        sodium_ctx.transaction(|| {
+            sa.send(2);
+            sb.send(20);
+        });
+        {
+            let l = out.lock();
+            let out: &Vec<i32> = l.as_ref().unwrap();
+            assert_eq!(vec![1, 22], *out);
+        }
+        l.unlisten();
+    }
     assert_memory_freed(sodium_ctx);
 }
 
 #[test]
-fn lift() {
+fn calm() {
     let sodium_ctx = SodiumCtx::new();
     let sodium_ctx = &sodium_ctx;
-    let l;
     {
         let out = Arc::new(Mutex::new(Vec::new()));
-        let a = sodium_ctx.new_cell_sink(1);
-        let b = sodium_ctx.new_cell_sink(5);
+        let calls = Arc::new(Mutex::new(0));
+        let cs = sodium_ctx.new_cell_sink(1);
+        let c = cs.cell().map(|a: &i32| a / 10).calm();
+        let doubled;
+        {
+            let calls = calls.clone();
+            doubled = c.map(move |a: &i32| {
+                *calls.lock().unwrap() += 1;
+                a * 2
+            });
+        }
+        let l;
         {
             let out = out.clone();
-            l = a
-                .cell()
-                .lift2(&b.cell(), |aa: &i32, bb: &i32| format!("{} {}", aa, bb))
-                .listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+            l = doubled
+                .updates()
+                .listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
         }
-        a.send(12);
-        b.send(6);
+        cs.send(2);
+        cs.send(13);
+        cs.send(17);
+        cs.send(3);
+        assert_eq!(c.sample(), 0);
         {
             let l = out.lock();
-            let out: &Vec<String> = l.as_ref().unwrap();
-            assert_eq!(
-                vec!["1 5", "12 5", "12 6"],
-                out.iter().map(|s| s.as_str()).collect::<Vec<&str>>()
-            );
+            let out: &Vec<i32> = l.as_ref().unwrap();
+            assert_eq!(vec![2, 0], *out);
         }
+        // only for the two changes, as the initial value is never sampled
+        assert_eq!(*calls.lock().unwrap(), 2);
+        l.unlisten();
     }
-    l.unlisten();
     assert_memory_freed(sodium_ctx);
 }
 
 #[test]
-fn lift_glitch() {
+fn calm_by_compares_with_own_value() {
     let sodium_ctx = SodiumCtx::new();
     let sodium_ctx = &sodium_ctx;
-    let l;
     {
         let out = Arc::new(Mutex::new(Vec::new()));
-        let a = sodium_ctx.new_cell_sink(1);
-        let ac = a.cell();
-        let a3 = ac.map(|x: &i32| x * 3);
-        let a5 = ac.map(|x: &i32| x * 5);
-        let b = a3.lift2(&a5, |x: &i32, y: &i32| format!("{} {}", x, y));
+        let cs = sodium_ctx.new_cell_sink(0);
+        // not transitive: 0 ~ 3 and 3 ~ 6, but 0 !~ 6
+        let c = cs.cell().calm_by(|a: &i32, b: &i32| (a - b).abs() < 5);
+        let l;
         {
             let out = out.clone();
-            l = b.listen(move |a: &String| out.lock().as_mut().unwrap().push(a.clone()));
+            l = c
+                .updates()
+                .listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
         }
-        a.send(2);
+        cs.send(3);
+        assert_eq!(c.sample(), 0);
+        cs.send(6);
+        assert_eq!(c.sample(), 6);
+        cs.send(9);
+        assert_eq!(c.sample(), 6);
         {
             let l = out.lock();
-            let out: &Vec<String> = l.as_ref().unwrap();
-            assert_eq!(vec!["3 5", "6 10"], *out);
+            let out: &Vec<i32> = l.as_ref().unwrap();
+            assert_eq!(vec![6], *out);
         }
+        l.unlisten();
     }
-    l.unlisten();
     assert_memory_freed(sodium_ctx);
 }
 
 #[test]
-fn lift_from_simultaneous() {
+fn calm_updates_kept_without_cell() {
     let sodium_ctx = SodiumCtx::new();
     let sodium_ctx = &sodium_ctx;
-    let l;
     {
         let out = Arc::new(Mutex::new(Vec::new()));
-        let (b1, b2) = sodium_ctx.transaction(|| {
-            let b1 = sodium_ctx.new_cell_sink(3);
-            let b2 = sodium_ctx.new_cell_sink(5);
-            b2.send(7);
-            (b1, b2)
-        });
+        let cs = sodium_ctx.new_cell_sink(1);
+        let l;
         {
             let out = out.clone();
-            l = b1
+            l = cs
                 .cell()
-                .lift2(&b2.cell(), |x: &i32, y: &i32| x + y)
+                .calm()
+                .updates()
                 .listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
         }
+        cs.send(1);
+        cs.send(2);
+        cs.send(2);
         {
             let l = out.lock();
             let out: &Vec<i32> = l.as_ref().unwrap();
-            assert_eq!(vec![10], *out);
+            assert_eq!(vec![2], *out);
         }
+        l.unlisten();
     }
-    l.unlisten();
     assert_memory_freed(sodium_ctx);
 }
 
 #[test]
-fn router1() {
-    #[derive(Clone)]
-    pub struct Packet {
-        pub address: i32,
-        pub payload: &'static str,
-    }
-    impl Packet {
-        fn new(address: i32, payload: &'static str) -> Packet {
-            Packet { address, payload }
-        }
-    }
+fn calm_stream() {
     let sodium_ctx = SodiumCtx::new();
     let sodium_ctx = &sodium_ctx;
     {
-        let s = sodium_ctx.new_stream_sink::<Packet>();
-        let r = sodium_ctx.new_router(&s.stream(), |pkt: &Packet| vec![pkt.address]);
-        let one = r.filter_matches(&1);
-        let out_one = Arc::new(Mutex::new(Vec::<&'static str>::new()));
-        let kill_one;
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let s = sodium_ctx.new_stream_sink();
+        let ca = sodium_ctx.new_cell_sink("a");
+        let l;
         {
-            let out_one = out_one.clone();
-            kill_one =
-                one.listen(move |p: &Packet| out_one.lock().as_mut().unwrap().push(p.payload));
+            let out = out.clone();
+            l = s
+                .stream()
+                .calm_by(&ca.cell(), |a: &&'static str, b: &&'static str| {
+                    a.eq_ignore_ascii_case(b)
+                })
+                .listen(move |a: &&'static str| out.lock().as_mut().unwrap().push(*a));
         }
-        let two = r.filter_matches(&2);
-        let out_two = Arc::new(Mutex::new(Vec::<&'static str>::new()));
-        let kill_two;
+        s.send("A");
+        s.send("b");
+        sodium_ctx.transaction(|| {
+            ca.send("b");
+            s.send("b");
+        });
+        s.send("b");
         {
-            let out_two = out_two.clone();
-            kill_two =
-                two.listen(move |p: &Packet| out_two.lock().as_mut().unwrap().push(p.payload));
+            let l = out.lock();
+            let out: &Vec<&str> = l.as_ref().unwrap();
+            assert_eq!(vec!["b", "b"], *out);
         }
-        let three = r.filter_matches(&3);
-        let out_three = Arc::new(Mutex::new(Vec::<&'static str>::new()));
-        let kill_three;
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn hold_calm() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let s = sodium_ctx.new_stream_sink();
+        let c = s.stream().hold_calm(0);
+        let l;
         {
-            let out_three = out_three.clone();
-            kill_three =
-                three.listen(move |p: &Packet| out_three.lock().as_mut().unwrap().push(p.payload));
+            let out = out.clone();
+            l = c
+                .updates()
+                .listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
         }
-        s.send(Packet::new(1, "dog"));
-        s.send(Packet::new(3, "manuka"));
-        s.send(Packet::new(2, "square"));
-        s.send(Packet::new(3, "tawa"));
-        s.send(Packet::new(2, "circle"));
-        s.send(Packet::new(1, "otter"));
-        s.send(Packet::new(1, "lion"));
-        s.send(Packet::new(2, "rectangle"));
-        s.send(Packet::new(3, "rata"));
-        s.send(Packet::new(4, "kauri"));
-        kill_one.unlisten();
-        kill_two.unlisten();
-        kill_three.unlisten();
+        s.send(0);
+        s.send(1);
+        s.send(1);
+        s.send(2);
+        s.send(0);
         {
-            let l1 = out_one.lock();
-            let out_one: &Vec<&'static str> = l1.as_ref().unwrap();
-            let l2 = out_two.lock();
-            let out_two: &Vec<&'static str> = l2.as_ref().unwrap();
-            let l3 = out_three.lock();
-            let out_three: &Vec<&'static str> = l3.as_ref().unwrap();
-            assert_eq!(vec!["dog", "otter", "lion"], *out_one);
-            assert_eq!(vec!["square", "circle", "rectangle"], *out_two);
-            assert_eq!(vec!["manuka", "tawa", "rata"], *out_three);
+            let l = out.lock();
+            let out: &Vec<i32> = l.as_ref().unwrap();
+            assert_eq!(vec![1, 2, 0], *out);
         }
+        l.unlisten();
     }
+    assert_memory_freed(sodium_ctx);
 }
 
-// TODO(RadicalZephyr 2020-07-17): port apply and then uncomment this test
-// #[test]
-// fn apply() {
-//     let sodium_ctx = SodiumCtx::new();
-//     let sodium_ctx = &sodium_ctx;
-//     let l;
-//     {
-//         let out = Arc::new(Mutex::new(Vec::new()));
-//         let cf = sodium_ctx.new_cell_sink(Box::new(|a: &u8| format!("1 {}", a)) as Box<dyn Fn(&u8) -> String>);
-//         let ca = sodium_ctx.new_cell_sink(5);
-
-//         {
-//             let out = out.clone();
-//             l = Cell::apply(cf, ca).listen(move |a: &i32| out.lock().as_mut().unwrap().push(a.clone()));
-//         }
-//         cf.send(Box::new(|a: &u8| format!("12 {}", a)) as Box<dyn Fn(&u8) -> String>);
-//         ca.send(6);
-//         {
-//             let l = out.lock();
-//             let out: &Vec<i32> = l.as_ref().unwrap();
-//             assert_eq!(vec!["1 5", "12 5", "12 6"], *out);
-//         }
-//     }
-//     l.unlisten();
-//     assert_memory_freed(sodium_ctx);
-// }
-
 #[test]
 fn loop_value_snapshot() {
     let mut sodium_ctx = SodiumCtx::new();
@@ -1084,6 +1875,46 @@ fn merge() {
     assert_memory_freed(sodium_ctx);
 }
 
+#[test]
+fn merge_from_simultaneous_branches() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sa = sodium_ctx.new_stream_sink();
+        let sb = sodium_ctx.new_stream_sink();
+        let mut merged: Option<Stream<i32>> = None;
+        for i in 0..4 {
+            let source = if i % 2 == 0 { sa.stream() } else { sb.stream() };
+            let branch = source.map(move |x: &i32| *x * (i + 1));
+            merged = Some(match merged {
+                Some(merged) => merged.merge(&branch, |x: &i32, y: &i32| *x + *y),
+                None => branch,
+            });
+        }
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = merged
+                .unwrap()
+                .listen(move |a: &i32| out.lock().as_mut().unwrap().push(*a));
+        }
+        sodium_ctx.transaction(|| {
+            sa.send(1);
+            sb.send(10);
+        });
+        sb.send(1);
+        {
+            let lock = out.lock();
+            let out: &Vec<i32> = lock.as_ref().unwrap();
+            assert_eq!(vec![64, 6], *out);
+        }
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
 #[test]
 fn collect() {
     let mut sodium_ctx = SodiumCtx::new();
@@ -1202,6 +2033,136 @@ fn defer() {
     assert_memory_freed(sodium_ctx);
 }
 
+#[test]
+fn defer_in_loop() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let _t = sodium_ctx.new_transaction();
+            let c: CellLoop<i32> = sodium_ctx.new_cell_loop();
+            let s_next = Operational::defer(
+                &Operational::updates(&c.cell())
+                    .map(|a: &i32| *a + 1)
+                    .filter(|a: &i32| *a < 4),
+            )
+            .or_else(&s.stream());
+            c.loop_(&s_next.hold(0));
+            let out = out.clone();
+            l = s_next.listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        s.send(1);
+        l.unlisten();
+        assert_eq!(vec![1, 2, 3], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn execute_sync_io() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let io_calls = Arc::new(Mutex::new(0));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let io_calls = io_calls.clone();
+            let s_io = Operational::execute_sync_io(&s.stream(), move |a: &i32| {
+                *io_calls.lock().unwrap() += 1;
+                *a * 10
+            });
+            let out = out.clone();
+            // results come in a later transaction, so or_else drops nothing
+            l = s
+                .stream()
+                .or_else(&s_io)
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        s.send(1);
+        s.send(2);
+        l.unlisten();
+        assert_eq!(2, *io_calls.lock().unwrap());
+        assert_eq!(vec![1, 10, 2, 20], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn execute_async_io() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        type Job = Box<dyn FnOnce() + Send>;
+        let jobs: Arc<Mutex<Vec<Job>>> = Arc::new(Mutex::new(Vec::new()));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let jobs = jobs.clone();
+            let executor = move |job: Job| jobs.lock().unwrap().push(job);
+            let out = out.clone();
+            l = Operational::execute_async_io(&s.stream(), executor, |a: &i32| *a * 10)
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        {
+            let _t = sodium_ctx.new_transaction();
+            s.send(1);
+            // nothing starts until the transaction is over
+            assert!(jobs.lock().unwrap().is_empty());
+        }
+        s.send(2);
+        let started: Vec<Job> = jobs.lock().unwrap().drain(..).collect();
+        assert_eq!(2, started.len());
+        for job in started.into_iter().rev() {
+            job();
+        }
+        l.unlisten();
+        assert_eq!(vec![20, 10], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn execute_async_io_on_threads() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let handles = Arc::new(Mutex::new(Vec::new()));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let handles = handles.clone();
+            let executor = move |job: Box<dyn FnOnce() + Send>| {
+                handles.lock().unwrap().push(std::thread::spawn(job))
+            };
+            let out = out.clone();
+            l = Operational::execute_async_io(&s.stream(), executor, |a: &i32| *a + 1)
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        s.send(1);
+        s.send(2);
+        let handles: Vec<_> = handles.lock().unwrap().drain(..).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        l.unlisten();
+        let mut out = out.lock().unwrap().clone();
+        out.sort();
+        assert_eq!(vec![2, 3], out);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
 #[test]
 fn hold_is_delayed() {
     init();
@@ -1528,3 +2489,28 @@ fn primes2() {
     }
     assert_memory_freed(sodium_ctx);
 }
+#[test]
+fn zz_scratch() {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<bool> = sodium_ctx.new_stream_sink();
+        let l;
+        {
+            let sodium_ctx = sodium_ctx.clone();
+            l = s.stream().listen(move |_: &bool| {
+                eprintln!("in listener");
+                let sl: StreamLoop<bool> = sodium_ctx.new_stream_loop();
+                eprintln!("made loop");
+                sl.loop_(&sodium_ctx.new_stream());
+                eprintln!("looped");
+            });
+        }
+        s.send(false);
+        eprintln!("sent");
+        l.unlisten();
+        eprintln!("unlistened");
+    }
+    sodium_ctx.transaction(|| {});
+    eprintln!("transaction");
+}
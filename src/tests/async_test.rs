@@ -0,0 +1,107 @@
+use crate::{AsyncBuffer, SodiumCtx};
+
+use futures_executor::block_on;
+use futures_util::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::tests::{assert_memory_freed, init};
+
+#[test]
+fn to_async_stream() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let mut events = s
+            .stream()
+            .map(|a: &i32| *a * 10)
+            .to_async_stream(AsyncBuffer::Unbounded);
+        s.send(1);
+        s.send(2);
+        let sender;
+        {
+            let s = s.clone();
+            sender = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                s.send(3);
+            });
+        }
+        let out: Vec<i32> = block_on((&mut events).take(3).collect());
+        sender.join().unwrap();
+        assert_eq!(vec![10, 20, 30], out);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn to_async_stream_bounded() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let mut oldest_dropped = s.stream().to_async_stream(AsyncBuffer::DropOldest(2));
+        let mut newest_dropped = s.stream().to_async_stream(AsyncBuffer::DropNewest(2));
+        s.send(1);
+        s.send(2);
+        s.send(3);
+        let out1: Vec<i32> = block_on((&mut oldest_dropped).take(2).collect());
+        let out2: Vec<i32> = block_on((&mut newest_dropped).take(2).collect());
+        assert_eq!(vec![2, 3], out1);
+        assert_eq!(vec![1, 2], out2);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn changed() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let c = sodium_ctx.new_cell_sink(1);
+        let changed = c.cell().changed();
+        // registered before the first poll, so this change is not missed
+        c.send(2);
+        assert_eq!(2, block_on(changed));
+        let changed = c.cell().map(|a: &i32| *a + 1).changed();
+        let sender;
+        {
+            let c = c.clone();
+            sender = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                c.send(5);
+            });
+        }
+        assert_eq!(6, block_on(changed));
+        sender.join().unwrap();
+        // dropping the future unlistens
+        drop(c.cell().changed());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn feed() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s
+                .stream()
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        block_on(s.feed(futures_util::stream::iter(vec![1, 2, 3])));
+        l.unlisten();
+        assert_eq!(vec![1, 2, 3], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
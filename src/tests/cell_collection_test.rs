@@ -0,0 +1,498 @@
+use crate::{lambda1, Cell, CellMap, CellVec, MapDiff, SodiumCtx, StreamSink, VecDiff};
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::tests::{assert_memory_freed, init};
+
+// A small deterministic generator of diffs, so the operators can be
+// checked against the same computation done on the whole vector.
+struct Diffs {
+    seed: u64,
+}
+
+impl Diffs {
+    fn next(&mut self, n: usize) -> usize {
+        self.seed = self
+            .seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.seed >> 33) as usize) % n
+    }
+
+    fn diff(&mut self, len: usize) -> VecDiff<i32> {
+        let value = self.next(20) as i32;
+        match (len, self.next(10)) {
+            (_, 0) => VecDiff::Replace {
+                values: (0..self.next(6)).map(|_| self.next(20) as i32).collect(),
+            },
+            (0, _) | (_, 1..=3) => VecDiff::Insert {
+                index: self.next(len + 1),
+                value,
+            },
+            (_, 4 | 5) => VecDiff::Remove {
+                index: self.next(len),
+            },
+            (_, 6 | 7) => VecDiff::Update {
+                index: self.next(len),
+                value,
+            },
+            _ => VecDiff::Move {
+                from: self.next(len),
+                to: self.next(len),
+            },
+        }
+    }
+}
+
+#[test]
+fn cell_vec_hold() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<Vec<VecDiff<&'static str>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec!["a", "b"]);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = cv
+                .updates()
+                .listen(move |diffs: &Vec<VecDiff<&'static str>>| {
+                    out.lock().unwrap().push(diffs.clone())
+                });
+        }
+        sodium_ctx.transaction(|| {
+            s.send(vec![
+                VecDiff::Insert {
+                    index: 0,
+                    value: "z",
+                },
+                VecDiff::Move { from: 2, to: 0 },
+            ]);
+            assert_eq!(cv.sample(), vec!["a", "b"]);
+        });
+        assert_eq!(cv.sample(), vec!["b", "z", "a"]);
+        s.send(vec![VecDiff::Remove { index: 1 }]);
+        assert_eq!(cv.sample(), vec!["b", "a"]);
+        l.unlisten();
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+fn check_cell_vec_operators(sodium_ctx: &SodiumCtx) {
+    {
+        let s: StreamSink<Vec<VecDiff<i32>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec![3, 1, 4, 1, 5]);
+        let mapped = cv.map(|a: &i32| a * 10);
+        let filtered = cv.filter(|a: &i32| a % 2 == 0);
+        let sorted = cv.sort_by(|a: &i32, b: &i32| a.cmp(b));
+        let grouped = cv.group_by(|a: &i32| a % 3);
+        let len = cv.len();
+        let cell = cv.to_cell();
+        let mut diffs = Diffs { seed: 1 };
+        for _ in 0..200 {
+            sodium_ctx.transaction(|| {
+                let mut len = cv.sample().len();
+                let mut batch = Vec::new();
+                for _ in 0..diffs.next(3) + 1 {
+                    let diff = diffs.diff(len);
+                    len = match &diff {
+                        VecDiff::Insert { .. } => len + 1,
+                        VecDiff::Remove { .. } => len - 1,
+                        VecDiff::Replace { values } => values.len(),
+                        _ => len,
+                    };
+                    batch.push(diff);
+                }
+                s.send(batch);
+            });
+            let values = cv.sample();
+            assert_eq!(cell.sample(), values);
+            assert_eq!(len.sample(), values.len());
+            assert_eq!(
+                mapped.sample(),
+                values.iter().map(|a| a * 10).collect::<Vec<_>>()
+            );
+            assert_eq!(
+                filtered.sample(),
+                values
+                    .iter()
+                    .copied()
+                    .filter(|a| a % 2 == 0)
+                    .collect::<Vec<_>>()
+            );
+            let mut sorted_values = values.clone();
+            sorted_values.sort();
+            assert_eq!(sorted.sample(), sorted_values);
+            let mut groups: BTreeMap<i32, Vec<i32>> = BTreeMap::new();
+            for a in &values {
+                groups.entry(a % 3).or_default().push(*a);
+            }
+            assert_eq!(grouped.sample(), groups);
+        }
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_operators() {
+    init();
+    check_cell_vec_operators(&SodiumCtx::new());
+}
+
+#[test]
+fn cell_vec_operators_on_thread_pool() {
+    init();
+    check_cell_vec_operators(&SodiumCtx::new_with_thread_pool(4));
+}
+
+#[test]
+fn cell_vec_sort_by_is_stable() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<Vec<VecDiff<(i32, &'static str)>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec![(2, "a"), (1, "b"), (2, "c")]);
+        let sorted = cv.sort_by(|a: &(i32, &str), b: &(i32, &str)| a.0.cmp(&b.0));
+        assert_eq!(sorted.sample(), vec![(1, "b"), (2, "a"), (2, "c")]);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = sorted
+                .updates()
+                .listen(move |diffs: &Vec<VecDiff<(i32, &'static str)>>| {
+                    out.lock().unwrap().push(diffs.clone())
+                });
+        }
+        s.send(vec![VecDiff::Insert {
+            index: 0,
+            value: (2, "d"),
+        }]);
+        s.send(vec![VecDiff::Update {
+            index: 2,
+            value: (0, "b"),
+        }]);
+        assert_eq!(
+            sorted.sample(),
+            vec![(0, "b"), (2, "a"), (2, "c"), (2, "d")]
+        );
+        l.unlisten();
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![
+                vec![VecDiff::Insert {
+                    index: 3,
+                    value: (2, "d")
+                }],
+                vec![VecDiff::Update {
+                    index: 0,
+                    value: (0, "b")
+                }],
+            ]
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_from_cell() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let cs = sodium_ctx.new_cell_sink(vec![1, 2, 3, 4]);
+        let cv = CellVec::from_cell(&cs.cell());
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = cv
+                .updates()
+                .listen(move |diffs: &Vec<VecDiff<i32>>| out.lock().unwrap().push(diffs.clone()));
+        }
+        cs.send(vec![1, 5, 4]);
+        cs.send(vec![1, 5, 4]);
+        cs.send(vec![1, 5, 4, 6]);
+        assert_eq!(cv.sample(), vec![1, 5, 4, 6]);
+        l.unlisten();
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![
+                vec![
+                    VecDiff::Update { index: 1, value: 5 },
+                    VecDiff::Remove { index: 2 },
+                ],
+                vec![VecDiff::Insert { index: 3, value: 6 }],
+            ]
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_sampled_in_lambda() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        // appends each value, so the CellVec is sampled by the lambda
+        // making its own diffs
+        let cv = sodium_ctx.transaction(|| {
+            let diffs = sodium_ctx.new_stream_loop();
+            let cv = CellVec::hold(&diffs.stream(), Vec::new());
+            let cv2 = cv.clone();
+            diffs.loop_(&s.stream().map(lambda1(
+                move |a: &i32| {
+                    vec![VecDiff::Insert {
+                        index: cv2.sample_with(|values: &[i32]| values.len()),
+                        value: *a,
+                    }]
+                },
+                vec![cv.to_dep()],
+            )));
+            cv
+        });
+        s.send(1);
+        s.send(2);
+        assert_eq!(cv.sample(), vec![1, 2]);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_operator_in_same_transaction() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<Vec<VecDiff<i32>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec![1, 2]);
+        let filtered = sodium_ctx.transaction(|| {
+            s.send(vec![VecDiff::Insert { index: 0, value: 4 }]);
+            cv.filter(|a: &i32| a % 2 == 0)
+        });
+        assert_eq!(filtered.sample(), vec![4, 2]);
+        s.send(vec![VecDiff::Remove { index: 0 }]);
+        assert_eq!(filtered.sample(), vec![2]);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_operator_made_in_listener() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<Vec<VecDiff<i32>>> = sodium_ctx.new_stream_sink();
+        let make: StreamSink<()> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec![3, 1, 2]);
+        let sorted = Arc::new(Mutex::new(None));
+        let l;
+        {
+            let cv = cv.clone();
+            let sorted = sorted.clone();
+            l = make.stream().listen(move |_: &()| {
+                *sorted.lock().unwrap() = Some(cv.sort_by(|a: &i32, b: &i32| a.cmp(b)));
+            });
+        }
+        make.send(());
+        // the sort node was made in an earlier transaction, so these
+        // updates must not rely on a cached result
+        s.send(vec![VecDiff::Insert { index: 0, value: 0 }]);
+        s.send(vec![VecDiff::Remove { index: 1 }]);
+        let sorted = sorted.lock().unwrap().take().unwrap();
+        assert_eq!(sorted.sample(), vec![0, 1, 2]);
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_updates_kept_without_result() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<Vec<VecDiff<i32>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec![3, 1]);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l1;
+        let l2;
+        {
+            let out = out.clone();
+            l1 = cv.sort_by(|a: &i32, b: &i32| a.cmp(b)).updates().listen(
+                move |diffs: &Vec<VecDiff<i32>>| out.lock().unwrap().push(format!("{:?}", diffs)),
+            );
+        }
+        {
+            let out = out.clone();
+            l2 = cv.group_by(|a: &i32| a % 2).updates().listen(
+                move |diffs: &Vec<MapDiff<i32, Vec<i32>>>| {
+                    out.lock().unwrap().push(format!("{:?}", diffs))
+                },
+            );
+        }
+        s.send(vec![
+            VecDiff::Insert { index: 0, value: 2 },
+            VecDiff::Update { index: 2, value: 0 },
+        ]);
+        l1.unlisten();
+        l2.unlisten();
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![
+                "[Insert { index: 1, value: 2 }, Update { index: 0, value: 0 }]",
+                "[Insert { key: 0, value: [2, 0] }, Insert { key: 1, value: [3] }]",
+            ]
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_vec_panic_rolls_back() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<Vec<VecDiff<i32>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&s.stream(), vec![1, 2, 3]);
+        let sorted = cv.sort_by(|a: &i32, b: &i32| b.cmp(a));
+        let checked = sorted.map(|a: &i32| {
+            if *a < 0 {
+                panic!("negative");
+            }
+            *a
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            s.send(vec![
+                VecDiff::Insert { index: 0, value: 7 },
+                VecDiff::Insert {
+                    index: 0,
+                    value: -1,
+                },
+            ])
+        }));
+        assert!(result.is_err());
+        assert_eq!(sorted.sample(), vec![3, 2, 1]);
+        s.send(vec![VecDiff::Update { index: 1, value: 5 }]);
+        assert_eq!(cv.sample(), vec![1, 5, 3]);
+        assert_eq!(sorted.sample(), vec![5, 3, 1]);
+        assert_eq!(checked.sample(), vec![5, 3, 1]);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn cell_map_operators() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let cs: crate::CellSink<BTreeMap<&'static str, i32>> =
+            sodium_ctx.new_cell_sink(BTreeMap::from([("a", 1), ("b", 2)]));
+        let cm = CellMap::from_cell(&cs.cell());
+        let doubled = cm.map(|a: &i32| a * 2);
+        let even = cm.filter(|a: &i32| a % 2 == 0);
+        let values = cm.values();
+        let len = cm.len();
+        let cell: Cell<BTreeMap<&'static str, i32>> = cm.to_cell();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = even
+                .updates()
+                .listen(move |diffs: &Vec<MapDiff<&'static str, i32>>| {
+                    out.lock().unwrap().push(diffs.clone())
+                });
+        }
+        cs.send(BTreeMap::from([("a", 4), ("b", 2), ("c", 3)]));
+        cs.send(BTreeMap::from([("b", 3), ("c", 3)]));
+        assert_eq!(cm.sample(), BTreeMap::from([("b", 3), ("c", 3)]));
+        assert_eq!(cell.sample(), cm.sample());
+        assert_eq!(doubled.sample(), BTreeMap::from([("b", 6), ("c", 6)]));
+        assert!(even.sample().is_empty());
+        assert_eq!(values.sample(), vec![3, 3]);
+        assert_eq!(len.sample(), 2);
+        l.unlisten();
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![
+                vec![MapDiff::Insert { key: "a", value: 4 }],
+                vec![MapDiff::Remove { key: "a" }, MapDiff::Remove { key: "b" }],
+            ]
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn len_fires_only_when_it_changes() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sv: StreamSink<Vec<VecDiff<i32>>> = sodium_ctx.new_stream_sink();
+        let sm: StreamSink<Vec<MapDiff<&'static str, i32>>> = sodium_ctx.new_stream_sink();
+        let cv = CellVec::hold(&sv.stream(), vec![1, 2]);
+        let cm = CellMap::hold(&sm.stream(), BTreeMap::from([("a", 1)]));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let lv;
+        let lm;
+        {
+            let out = out.clone();
+            lv = cv
+                .len()
+                .updates()
+                .listen(move |len: &usize| out.lock().unwrap().push(("vec", *len)));
+        }
+        {
+            let out = out.clone();
+            lm = cm
+                .len()
+                .updates()
+                .listen(move |len: &usize| out.lock().unwrap().push(("map", *len)));
+        }
+        sv.send(vec![
+            VecDiff::Update { index: 0, value: 3 },
+            VecDiff::Move { from: 0, to: 1 },
+        ]);
+        sv.send(vec![VecDiff::Insert { index: 0, value: 4 }]);
+        sv.send(vec![VecDiff::Replace {
+            values: vec![5, 6, 7],
+        }]);
+        sm.send(vec![
+            MapDiff::Insert { key: "a", value: 2 },
+            MapDiff::Remove { key: "b" },
+        ]);
+        sm.send(vec![
+            MapDiff::Insert { key: "b", value: 1 },
+            MapDiff::Remove { key: "b" },
+            MapDiff::Insert { key: "b", value: 2 },
+        ]);
+        sm.send(vec![
+            MapDiff::Replace {
+                values: BTreeMap::from([("c", 1)]),
+            },
+            MapDiff::Insert { key: "a", value: 1 },
+            MapDiff::Remove { key: "c" },
+        ]);
+        sm.send(vec![MapDiff::Remove { key: "a" }]);
+        lv.unlisten();
+        lm.unlisten();
+        assert_eq!(
+            *out.lock().unwrap(),
+            vec![("vec", 3), ("map", 2), ("map", 1), ("map", 0)]
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,58 @@
+use crate::SodiumCtx;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::tests::{assert_memory_freed, init};
+
+// Much less than the default stack size for spawned threads, so a
+// recursive walk over the graph would overflow it.
+const STACK_SIZE: usize = 256 * 1024;
+
+fn map_chain(depth: usize) {
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sa = sodium_ctx.new_stream_sink();
+        let s = sodium_ctx.transaction(|| {
+            let mut s = sa.stream();
+            for _ in 0..depth {
+                s = s.map(|x: &usize| *x + 1);
+            }
+            s
+        });
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s.listen(move |x: &usize| out.lock().unwrap().push(*x));
+        }
+        sa.send(0);
+        sa.send(1);
+        l.unlisten();
+        assert_eq!(vec![depth, depth + 1], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+fn on_small_stack<K: FnOnce() + Send + 'static>(k: K) {
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(k)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn deep_map_chain() {
+    init();
+    on_small_stack(|| map_chain(100_000));
+}
+
+#[test]
+#[ignore = "slow in debug builds, run with --release -- --ignored"]
+fn very_deep_map_chain() {
+    init();
+    on_small_stack(|| map_chain(1_000_000));
+}
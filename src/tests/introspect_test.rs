@@ -0,0 +1,250 @@
+use crate::introspect::NodeName;
+use crate::{assert_no_leaks, Cell, CellSink, CellVec, Operational, SodiumCtx, StreamSink};
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::tests::{assert_memory_freed, init};
+
+#[test]
+fn graph_structure() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().track_nodes().build();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let m = s.stream().map(|a: &i32| *a + 1).named("plus one");
+        let line = line!() - 1;
+        let c = m.hold(0);
+        let l1 = m.listen(|_: &i32| {});
+        let l2 = m.listen_weak(|_: &i32| {});
+        let graph = sodium_ctx.graph();
+        let s_info = graph.stream(&s.stream()).unwrap();
+        let m_info = graph.stream(&m).unwrap();
+        let c_info = graph.cell(&c).unwrap();
+        assert_eq!(NodeName::STREAM_NEW, s_info.name);
+        assert_eq!(NodeName::STREAM_MAP, m_info.name);
+        assert_eq!(NodeName::CELL_HOLD, c_info.name);
+        assert_eq!(Some("plus one"), m_info.label.as_deref());
+        assert_eq!(line, m_info.location.unwrap().line());
+        assert_eq!(vec![s_info.id], m_info.dependencies);
+        assert!(s_info.dependents.contains(&m_info.id));
+        assert_eq!(2, m_info.listener_count);
+        assert_eq!(0, s_info.listener_count);
+        assert_eq!(
+            vec![m_info.id],
+            graph.labelled("plus one").map(|n| n.id).collect::<Vec<_>>()
+        );
+        assert_eq!(1, graph.named(NodeName::STREAM_MAP).count());
+        assert_eq!(2, graph.listeners.len());
+        assert!(!graph.listeners[0].is_weak);
+        assert!(graph.listeners[1].is_weak);
+        assert_eq!(Some(m_info.id), graph.listeners[0].node);
+        assert_eq!(None, m_info.value);
+        l1.unlisten();
+        l2.unlisten();
+        let graph = sodium_ctx.graph();
+        assert_eq!(0, graph.stream(&m).unwrap().listener_count);
+        assert!(graph.listeners.iter().all(|l| l.node.is_none()));
+    }
+    assert_memory_freed(sodium_ctx);
+    assert!(sodium_ctx.graph().nodes.is_empty());
+}
+
+#[test]
+fn location_of_node_made_in_lambda() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().track_nodes().build();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let cs: CellSink<i32> = sodium_ctx.new_cell_sink(1);
+        let t: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let made = Arc::new(Mutex::new(Vec::new()));
+        let csa;
+        {
+            let made = made.clone();
+            let t = t.stream();
+            csa = cs.cell().map(move |_: &i32| {
+                let line = line!() + 1;
+                let n = t.map(|a: &i32| *a);
+                made.lock().unwrap().push((n.clone(), line));
+                n
+            });
+        }
+        // switch_s samples csa, and listen gives the listener the current
+        // value, so both lambdas run inside the outer calls
+        let sw = Cell::switch_s(&csa);
+        let l;
+        {
+            let made = made.clone();
+            let t = t.stream();
+            l = cs.cell().listen(move |_: &i32| {
+                let line = line!() + 1;
+                let n = t.map(|a: &i32| *a);
+                made.lock().unwrap().push((n, line));
+            });
+        }
+        cs.send(2);
+        l.unlisten();
+        let made = mem::take(&mut *made.lock().unwrap());
+        assert!(made.len() >= 4);
+        let graph = sodium_ctx.graph();
+        for (n, line) in &made {
+            assert_eq!(*line, graph.stream(n).unwrap().location.unwrap().line());
+        }
+        drop((made, sw));
+        // the dropped nodes are collected at the end of the next transaction
+        sodium_ctx.transaction(|| {});
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn location_of_node_made_while_building() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let cs: CellSink<i32> = sodium_ctx.new_cell_sink(1);
+        let t: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let made = Arc::new(Mutex::new(Vec::new()));
+        let c;
+        let mapped;
+        {
+            let made = made.clone();
+            let t = t.stream();
+            c = cs.cell().map(move |a: &i32| {
+                let line = line!() + 1;
+                let n = t.map(|a: &i32| *a);
+                made.lock().unwrap().push((n, line));
+                vec![*a]
+            });
+        }
+        // from_cell samples c, and map runs its lambda on the values
+        // there are, while each is being built
+        let cv = CellVec::from_cell(&c);
+        {
+            let made = made.clone();
+            let t = t.stream();
+            mapped = cv.map(move |a: &i32| {
+                let line = line!() + 1;
+                let n = t.map(|a: &i32| *a);
+                made.lock().unwrap().push((n, line));
+                *a
+            });
+        }
+        let made = mem::take(&mut *made.lock().unwrap());
+        assert_eq!(2, made.len());
+        for (n, line) in &made {
+            let location = n.impl_.node().gc_node.location().unwrap();
+            assert_eq!((file!(), *line), (location.file(), location.line()));
+        }
+        drop((made, mapped, cv, c));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn debug_values() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().track_nodes().build();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c = s.stream().hold(3).debug_values();
+        let m = s.stream().map(|a: &i32| *a * 2).debug_values();
+        assert_eq!(
+            Some("3"),
+            sodium_ctx.graph().cell(&c).unwrap().value.as_deref()
+        );
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let sodium_ctx = sodium_ctx.clone();
+            let m2 = m.clone();
+            l = Operational::updates(&c).listen(move |_: &i32| {
+                out.lock()
+                    .unwrap()
+                    .push(sodium_ctx.graph().stream(&m2).unwrap().value.clone());
+            });
+        }
+        s.send(5);
+        l.unlisten();
+        assert_eq!(vec![Some("10".to_string())], *out.lock().unwrap());
+        let graph = sodium_ctx.graph();
+        assert_eq!(Some("5"), graph.cell(&c).unwrap().value.as_deref());
+        assert_eq!(None, graph.stream(&m).unwrap().value);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn check_leaks() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().track_nodes().build();
+    let sodium_ctx = &sodium_ctx;
+    let l;
+    let ids;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let m = s.stream().map(|a: &i32| *a + 1).named("plus one");
+        l = m.listen(|_: &i32| {});
+        ids = (
+            s.stream().impl_.node().gc_node.id(),
+            m.impl_.node().gc_node.id(),
+            l.impl_.gc_node.id(),
+        );
+    }
+    let (s_id, m_id, l_id) = ids;
+    let report = sodium_ctx.check_leaks().unwrap_err();
+    let leak = |id| report.leaks.iter().find(|leak| leak.id == id).unwrap();
+    assert_eq!(4, report.leaks.len());
+    assert!(leak(l_id).retained_by.is_empty());
+    let listen_id = leak(m_id).retained_by[1];
+    assert_eq!(vec![l_id], leak(listen_id).retained_by);
+    assert_eq!(NodeName::STREAM_LISTEN, leak(listen_id).name);
+    assert_eq!(vec![l_id, listen_id], leak(m_id).retained_by);
+    assert_eq!(Some("plus one"), leak(m_id).label.as_deref());
+    assert_eq!(vec![l_id, listen_id, m_id], leak(s_id).retained_by);
+    let text = report.to_string();
+    assert!(text.starts_with("4 Sodium nodes leaked:\n"));
+    assert!(text.contains(&format!("  #{} Stream::map \"plus one\" at ", m_id)));
+    assert!(text.contains(&format!("      held via #{} Listener::new at ", l_id)));
+    l.unlisten();
+    drop(l);
+    assert!(sodium_ctx.check_leaks().is_ok());
+    assert_no_leaks!(sodium_ctx);
+}
+
+#[test]
+#[should_panic(expected = "Sodium nodes leaked")]
+fn assert_no_leaks_panics() {
+    let sodium_ctx = SodiumCtx::new();
+    let _s = sodium_ctx.new_stream_sink::<i32>();
+    assert_no_leaks!(sodium_ctx);
+}
+
+#[test]
+fn untracked_nodes() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let s = sodium_ctx.new_stream_sink::<i32>();
+    let l = s.stream().map(|a: &i32| *a + 1).listen(|_: &i32| {});
+    let result = panic::catch_unwind(AssertUnwindSafe(|| sodium_ctx.graph()));
+    let message = result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("track_nodes"), "{}", message);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| sodium_ctx.export_dot()));
+    assert!(result.is_err());
+    let report = sodium_ctx.check_leaks().unwrap_err();
+    assert!(report.leaks.is_empty());
+    assert_eq!(3, report.node_count);
+    assert_eq!(
+        "3 Sodium nodes leaked; retention paths unavailable (track_nodes off)\n",
+        report.to_string()
+    );
+    l.unlisten();
+    drop((l, s));
+    assert!(sodium_ctx.check_leaks().is_ok());
+}
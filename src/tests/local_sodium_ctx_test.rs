@@ -0,0 +1,103 @@
+use crate::impl_::local::Local;
+use crate::local::{LocalCell, LocalSodiumCtx};
+
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::thread;
+
+use crate::tests::{assert_memory_freed, init};
+
+#[test]
+fn local_rc_values() {
+    init();
+    let sodium_ctx = LocalSodiumCtx::new();
+    {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let sa = sodium_ctx.new_stream_sink::<Rc<str>>();
+        let sb = sodium_ctx.new_stream_sink::<Rc<str>>();
+        let total = sodium_ctx.new_cell_sink(Rc::new(0_usize));
+        let merged = sa.stream().merge(&sb.stream(), |a: &Rc<str>, b: &Rc<str>| {
+            Rc::from(format!("{}{}", a, b))
+        });
+        let lengths = merged.snapshot(&total.cell(), |s: &Rc<str>, total: &Rc<usize>| {
+            Rc::new(s.len() + **total)
+        });
+        let last = lengths.hold(Rc::new(0));
+        let l;
+        {
+            let out = out.clone();
+            l = lengths
+                .filter(|n: &Rc<usize>| **n > 1)
+                .listen(move |n: &Rc<usize>| out.borrow_mut().push(**n));
+        }
+        sa.send(Rc::from("a"));
+        total.send(Rc::new(10));
+        sodium_ctx.transaction(|| {
+            sa.send(Rc::from("bc"));
+            sb.send(Rc::from("d"));
+        });
+        assert_eq!(*last.sample(), 13);
+        l.unlisten();
+        assert_eq!(*out.borrow(), vec![13]);
+    }
+    assert_memory_freed(&sodium_ctx.impl_);
+}
+
+#[test]
+fn local_loop_and_switch() {
+    init();
+    let sodium_ctx = LocalSodiumCtx::new();
+    {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let s = sodium_ctx.new_stream_sink::<i32>();
+        let total = sodium_ctx.transaction(|| {
+            let total_loop = sodium_ctx.new_cell_loop::<Rc<i32>>();
+            let total = s
+                .stream()
+                .snapshot(&total_loop.cell(), |x: &i32, total: &Rc<i32>| {
+                    Rc::new(*x + **total)
+                })
+                .hold(Rc::new(0));
+            total_loop.loop_(&total);
+            total
+        });
+        let streams = sodium_ctx.new_cell_sink(s.stream().map(|x: &i32| *x * 100));
+        let switched = LocalCell::switch_s(&streams.cell());
+        let cells = sodium_ctx.new_cell_sink(total.map(|total: &Rc<i32>| **total));
+        let current = LocalCell::switch_c(&cells.cell());
+        let l;
+        {
+            let out = out.clone();
+            l = switched.listen(move |x: &i32| out.borrow_mut().push(*x));
+        }
+        s.send(1);
+        s.send(2);
+        streams.send(s.stream());
+        cells.send(sodium_ctx.new_cell(-1));
+        s.send(3);
+        assert_eq!(*total.sample(), 6);
+        assert_eq!(current.sample(), -1);
+        l.unlisten();
+        assert_eq!(*out.borrow(), vec![100, 200, 3]);
+    }
+    assert_memory_freed(&sodium_ctx.impl_);
+}
+
+#[test]
+fn local_value_used_from_another_thread() {
+    init();
+    let rc = Rc::new(1);
+    let local = Local::new(rc.clone());
+    let result = thread::scope(|scope| {
+        scope
+            .spawn(|| panic::catch_unwind(AssertUnwindSafe(|| **local.get())).is_err())
+            .join()
+            .unwrap()
+    });
+    assert!(result);
+    // dropped on another thread, the value is leaked rather than
+    // dropped there
+    thread::spawn(move || drop(local)).join().unwrap();
+    assert_eq!(Rc::strong_count(&rc), 2);
+}
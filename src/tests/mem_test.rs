@@ -1,3 +1,4 @@
+use crate::assert_no_leaks;
 use crate::CellSink;
 use crate::SodiumCtx;
 use crate::StreamSink;
@@ -25,13 +26,7 @@ fn mem() {
         let l = s3.listen_weak(|_: &i32| {});
         l.unlisten();
     }
-    sodium_ctx.impl_.collect_cycles();
-    let node_count = sodium_ctx.impl_.node_count();
-    let node_ref_count = sodium_ctx.impl_.node_ref_count();
-    println!();
-    println!("node_count {}", node_count);
-    println!("node_ref_count {}", node_ref_count);
-    assert_eq!(node_count, 0);
+    assert_no_leaks!(sodium_ctx);
 }
 
 #[test]
@@ -47,13 +42,7 @@ fn map_s_mem() {
         println!("l: {:?}", l.impl_);
         l.unlisten();
     }
-    sodium_ctx.impl_.collect_cycles();
-    let node_count = sodium_ctx.impl_.node_count();
-    let node_ref_count = sodium_ctx.impl_.node_ref_count();
-    println!();
-    println!("node_count {}", node_count);
-    println!("node_ref_count {}", node_ref_count);
-    assert_eq!(node_count, 0);
+    assert_no_leaks!(sodium_ctx);
 }
 
 #[test]
@@ -68,11 +57,5 @@ fn map_c_mem() {
         //let l = _c2.listen_weak(|_:&i32| {});
         //l.unlisten();
     }
-    sodium_ctx.impl_.collect_cycles();
-    let node_count = sodium_ctx.impl_.node_count();
-    let node_ref_count = sodium_ctx.impl_.node_ref_count();
-    println!();
-    println!("node_count {}", node_count);
-    println!("node_ref_count {}", node_ref_count);
-    assert_eq!(node_count, 0);
+    assert_no_leaks!(sodium_ctx);
 }
@@ -0,0 +1,245 @@
+use crate::{Cell, CellSink, Operational, SodiumCtx, StreamSink};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::tests::{assert_memory_freed, init};
+
+fn panicking_map(sodium_ctx: &SodiumCtx) {
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c = s
+            .stream()
+            .map(|a: &i32| {
+                if *a < 0 {
+                    panic!("negative");
+                }
+                *a
+            })
+            .hold(0);
+        let out = Arc::new(Mutex::new(Vec::<i32>::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = c.listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        s.send(1);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| s.send(-1)));
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<&str>().copied(),
+            Some("negative")
+        );
+        assert_eq!(c.sample(), 1);
+        s.send(2);
+        assert_eq!(c.sample(), 2);
+        l.unlisten();
+        assert_eq!(*out.lock().unwrap(), vec![0, 1, 2]);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_in_map_rolls_back() {
+    init();
+    panicking_map(&SodiumCtx::new());
+}
+
+#[test]
+fn panic_in_map_rolls_back_on_thread_pool() {
+    init();
+    panicking_map(&SodiumCtx::new_with_thread_pool(4));
+}
+
+#[test]
+fn panic_in_listener_rolls_back() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let cs: CellSink<i32> = sodium_ctx.new_cell_sink(0);
+        let doubled = cs.cell().map(|a: &i32| *a * 2);
+        let l = doubled.updates().listen(|a: &i32| {
+            if *a == 6 {
+                panic!("listener");
+            }
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            sodium_ctx.transaction(|| {
+                cs.send(1);
+                cs.send(3);
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(cs.cell().sample(), 0);
+        assert_eq!(doubled.sample(), 0);
+        cs.send(4);
+        assert_eq!(doubled.sample(), 8);
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_in_transaction_rolls_back() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c = s.stream().hold(0);
+        let out = Arc::new(Mutex::new(Vec::<i32>::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s
+                .stream()
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            sodium_ctx.transaction(|| {
+                s.send(1);
+                panic!("in transaction");
+            })
+        }));
+        assert!(result.is_err());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _t = sodium_ctx.new_transaction();
+            s.send(2);
+            panic!("in scoped transaction");
+        }));
+        assert!(result.is_err());
+        assert_eq!(c.sample(), 0);
+        s.send(3);
+        assert_eq!(c.sample(), 3);
+        l.unlisten();
+        assert_eq!(*out.lock().unwrap(), vec![3]);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_keeps_effects_of_listeners_that_ran() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::<i32>::new()));
+        let io_calls = Arc::new(Mutex::new(0));
+        let l1;
+        {
+            let out = out.clone();
+            l1 = s
+                .stream()
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        let io;
+        {
+            let io_calls = io_calls.clone();
+            io = Operational::execute_sync_io(&s.stream(), move |a: &i32| {
+                *io_calls.lock().unwrap() += 1;
+                *a
+            });
+        }
+        let l2 = io.listen(|_: &i32| {});
+        // two steps away from the sink, so it runs after the listener
+        let l3 = s
+            .stream()
+            .map(|a: &i32| *a)
+            .map(|a: &i32| {
+                if *a < 0 {
+                    panic!("negative");
+                }
+                *a
+            })
+            .listen(|_: &i32| {});
+        let result = panic::catch_unwind(AssertUnwindSafe(|| s.send(-1)));
+        assert!(result.is_err());
+        // the listener isn't undone, but the io is never started
+        assert_eq!(*out.lock().unwrap(), vec![-1]);
+        assert_eq!(*io_calls.lock().unwrap(), 0);
+        s.send(2);
+        assert_eq!(*out.lock().unwrap(), vec![-1, 2]);
+        assert_eq!(*io_calls.lock().unwrap(), 1);
+        l1.unlisten();
+        l2.unlisten();
+        l3.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_in_post_runs_other_posts() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let c = s.stream().hold(0);
+        let ran = Arc::new(Mutex::new(false));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            sodium_ctx.transaction(|| {
+                sodium_ctx.post(|| panic!("in post"));
+                s.send(1);
+                let ran = ran.clone();
+                sodium_ctx.post(move || *ran.lock().unwrap() = true);
+            })
+        }));
+        assert!(result.is_err());
+        assert!(*ran.lock().unwrap());
+        assert_eq!(c.sample(), 1);
+        s.send(2);
+        assert_eq!(c.sample(), 2);
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_rolls_back_lift_n() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let ca: CellSink<i32> = sodium_ctx.new_cell_sink(1);
+        let cb: CellSink<i32> = sodium_ctx.new_cell_sink(10);
+        let sum = Cell::lift_n((&ca.cell(), &cb.cell()), |(a, b): &(i32, i32)| a + b);
+        let l = sum.updates().listen(|a: &i32| {
+            if *a == 105 {
+                panic!("listener");
+            }
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| ca.send(95)));
+        assert!(result.is_err());
+        assert_eq!(sum.sample(), 11);
+        cb.send(20);
+        assert_eq!(sum.sample(), 21);
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn panic_rolls_back_lift_all() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let ca: CellSink<i32> = sodium_ctx.new_cell_sink(1);
+        let cb: CellSink<i32> = sodium_ctx.new_cell_sink(10);
+        let sum = Cell::lift_all(sodium_ctx, &[ca.cell(), cb.cell()], |values: &Vec<i32>| {
+            values.iter().sum::<i32>()
+        });
+        let l = sum.updates().listen(|a: &i32| {
+            if *a == 105 {
+                panic!("listener");
+            }
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| ca.send(95)));
+        assert!(result.is_err());
+        assert_eq!(sum.sample(), 11);
+        cb.send(20);
+        assert_eq!(sum.sample(), 21);
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,301 @@
+use crate::{GcPolicy, SodiumCtx, SodiumStats, StreamSink, TransactionStats};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::tests::{assert_memory_freed, init};
+
+fn make_garbage(sodium_ctx: &SodiumCtx) {
+    let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+    let sum = sodium_ctx.transaction(|| {
+        let sum = sodium_ctx.new_cell_loop();
+        let sum_out = sa
+            .stream()
+            .snapshot(&sum.cell(), |x: &i32, y: &i32| *x + *y)
+            .hold(0);
+        sum.loop_(&sum_out);
+        sum_out
+    });
+    sa.send(1);
+    assert_eq!(sum.sample(), 1);
+}
+
+#[test]
+fn builder_default() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().build();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = s
+                .stream()
+                .map(|a: &i32| *a + 1)
+                .listen(move |a: &i32| out.lock().unwrap().push(*a));
+        }
+        s.send(7);
+        s.send(9);
+        l.unlisten();
+        assert_eq!(vec![8, 10], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn builder_thread_spawner() {
+    init();
+    let spawn_count = Arc::new(AtomicUsize::new(0));
+    let sodium_ctx;
+    {
+        let spawn_count = spawn_count.clone();
+        sodium_ctx = SodiumCtx::builder()
+            .thread_spawner(move |job| {
+                spawn_count.fetch_add(1, Ordering::SeqCst);
+                let handle = std::thread::spawn(job);
+                move || handle.join().unwrap()
+            })
+            .build();
+    }
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let mut listeners = Vec::new();
+        for i in 0..4 {
+            let out = out.clone();
+            listeners.push(
+                s.stream()
+                    .map(move |a: &i32| *a * 10 + i)
+                    .map(|a: &i32| *a + 1)
+                    .listen(move |a: &i32| out.lock().unwrap().push(*a)),
+            );
+        }
+        s.send(1);
+        s.send(2);
+        for l in listeners {
+            l.unlisten();
+        }
+        assert_eq!(vec![11, 12, 13, 14, 21, 22, 23, 24], *out.lock().unwrap());
+    }
+    assert!(spawn_count.load(Ordering::SeqCst) > 0);
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn builder_collect_cycles_manually() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().collect_cycles_every(0).build();
+    make_garbage(&sodium_ctx);
+    sodium_ctx.transaction(|| {});
+    assert!(sodium_ctx.impl_.node_count() > 0);
+    sodium_ctx.collect_cycles();
+    assert_eq!(sodium_ctx.impl_.node_count(), 0);
+}
+
+#[test]
+fn builder_collect_cycles_every() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().collect_cycles_every(3).build();
+    make_garbage(&sodium_ctx);
+    assert!(sodium_ctx.impl_.node_count() > 0);
+    let mut transactions = 0;
+    while sodium_ctx.impl_.node_count() > 0 {
+        assert!(transactions < 3);
+        sodium_ctx.transaction(|| {});
+        transactions += 1;
+    }
+}
+
+#[test]
+fn gc_policy_roots_over() {
+    init();
+    let sodium_ctx = SodiumCtx::builder()
+        .gc_policy(GcPolicy::RootsOver(20))
+        .build();
+    make_garbage(&sodium_ctx);
+    sodium_ctx.transaction(|| {});
+    assert!(sodium_ctx.stats().gc_roots_buffered > 0);
+    assert!(sodium_ctx.impl_.node_count() > 0);
+    for _ in 0..20 {
+        make_garbage(&sodium_ctx);
+        sodium_ctx.transaction(|| {});
+        assert!(sodium_ctx.stats().gc_roots_buffered <= 20);
+    }
+    assert!(sodium_ctx.impl_.node_count() < 20);
+    sodium_ctx.collect_cycles();
+    assert_eq!(0, sodium_ctx.impl_.node_count());
+    assert_eq!(0, sodium_ctx.stats().gc_roots_buffered);
+}
+
+#[test]
+fn gc_policy_incremental() {
+    init();
+    let sodium_ctx = SodiumCtx::builder()
+        .gc_policy(GcPolicy::Incremental {
+            max_roots: 1,
+            max_time: Duration::from_secs(60),
+        })
+        .collect_stats()
+        .build();
+    make_garbage(&sodium_ctx);
+    make_garbage(&sodium_ctx);
+    let mut transactions = 0;
+    while sodium_ctx.impl_.node_count() > 0 {
+        let before = sodium_ctx.stats();
+        sodium_ctx.transaction(|| {});
+        let after = sodium_ctx.stats();
+        assert!(after.total.gc_roots_scanned - before.total.gc_roots_scanned <= 1);
+        transactions += 1;
+    }
+    assert!(transactions > 1);
+    // a time budget that has already run out still gets through a batch
+    let sodium_ctx = SodiumCtx::builder()
+        .gc_policy(GcPolicy::Incremental {
+            max_roots: usize::MAX,
+            max_time: Duration::ZERO,
+        })
+        .build();
+    make_garbage(&sodium_ctx);
+    while sodium_ctx.impl_.node_count() > 0 {
+        sodium_ctx.transaction(|| {});
+    }
+}
+
+#[test]
+fn export_dot() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().track_nodes().build();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let m = s.stream().map(|a: &i32| *a + 1);
+        let m_line = line!() - 1;
+        let l = m.listen(|_: &i32| {});
+        let l_line = line!() - 1;
+        let s_id = s.stream().impl_.node().gc_node.id();
+        let m_id = m.impl_.node().gc_node.id();
+        let l_id = l.impl_.gc_node.id();
+        let dot = sodium_ctx.export_dot();
+        assert!(dot.starts_with("digraph sodium {\n"));
+        assert!(dot.contains(&format!(
+            "n{} [label=\"Stream::map\\n{}:{}:",
+            m_id,
+            file!(),
+            m_line
+        )));
+        assert!(dot.contains(&format!("n{} -> n{};", m_id, s_id)));
+        assert!(dot.contains(&format!("n{} -> n{} [style=dotted];", s_id, m_id)));
+        assert!(dot.contains(&format!(
+            "n{} [label=\"listener\\n{}:{}:",
+            l_id,
+            file!(),
+            l_line
+        )));
+        assert!(dot.contains(&format!("n{} -> n", l_id)));
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+    assert_eq!(
+        "digraph sodium {\n    node [shape=box];\n}\n",
+        sodium_ctx.export_dot()
+    );
+}
+
+#[test]
+fn named() {
+    init();
+    let sodium_ctx = SodiumCtx::builder().track_nodes().build();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let m = s.stream().map(|a: &i32| *a + 1).named("plus \"one\"");
+        let line = line!() - 1;
+        let c = m.hold(0).named("total");
+        let gc_node = &m.impl_.node().gc_node;
+        assert_eq!(Some("plus \"one\""), gc_node.label().as_deref());
+        let location = gc_node.location().unwrap();
+        assert_eq!(file!(), location.file());
+        assert_eq!(line, location.line());
+        assert_eq!(
+            format!(
+                "Stream::map \"plus \\\"one\\\"\" at {}:{}:{}",
+                file!(),
+                line,
+                location.column()
+            ),
+            gc_node.description()
+        );
+        assert!(format!("{}", c.impl_.node().gc_node).contains("Cell::hold \"total\" at "));
+        let dot = sodium_ctx.export_dot();
+        assert!(dot.contains(&format!(
+            "[label=\"Stream::map\\n\\\"plus \\\\\\\"one\\\\\\\"\\\"\\n{}:{}:",
+            file!(),
+            line
+        )));
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn stats() {
+    init();
+    let per_transaction = Arc::new(Mutex::new(Vec::new()));
+    let sodium_ctx;
+    {
+        let per_transaction = per_transaction.clone();
+        sodium_ctx = SodiumCtx::builder()
+            .on_transaction_stats(move |stats: &TransactionStats| {
+                per_transaction.lock().unwrap().push(*stats)
+            })
+            .build();
+    }
+    let sodium_ctx = &sodium_ctx;
+    {
+        let s: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let l;
+        {
+            let sodium_ctx = sodium_ctx.clone();
+            l = s
+                .stream()
+                .map(|a: &i32| *a + 1)
+                .filter(|a: &i32| *a > 2)
+                .listen(move |_: &i32| sodium_ctx.post(|| {}));
+        }
+        per_transaction.lock().unwrap().clear();
+        let before = sodium_ctx.stats();
+        s.send(1);
+        s.send(2);
+        l.unlisten();
+        let per_transaction = per_transaction.lock().unwrap();
+        // map and filter run both times, the listener only the second
+        assert_eq!(2, per_transaction[0].node_updates);
+        assert_eq!(0, per_transaction[0].listener_callbacks);
+        assert_eq!(0, per_transaction[0].post_callbacks);
+        assert_eq!(3, per_transaction[0].nodes_visited);
+        assert_eq!(3, per_transaction[1].node_updates);
+        assert_eq!(1, per_transaction[1].listener_callbacks);
+        assert_eq!(1, per_transaction[1].post_callbacks);
+        let after = sodium_ctx.stats();
+        assert_eq!(
+            per_transaction.len() as u64,
+            after.transactions - before.transactions
+        );
+        assert_eq!(
+            per_transaction.iter().map(|t| t.node_updates).sum::<u64>(),
+            after.total.node_updates - before.total.node_updates
+        );
+    }
+    // cycles are left to the cycle collector
+    make_garbage(sodium_ctx);
+    let before = sodium_ctx.stats();
+    sodium_ctx.transaction(|| {});
+    let after = sodium_ctx.stats();
+    assert!(after.total.gc_roots_scanned > before.total.gc_roots_scanned);
+    assert!(after.total.nodes_freed > before.total.nodes_freed);
+    assert_memory_freed(sodium_ctx);
+    assert_eq!(SodiumStats::default(), SodiumCtx::new().stats());
+}
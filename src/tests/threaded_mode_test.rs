@@ -0,0 +1,234 @@
+use crate::{Cell, Operational, SodiumCtx, Stream, StreamLoop, StreamSink};
+
+use std::sync::{Arc, Mutex};
+
+use crate::tests::{assert_memory_freed, init};
+
+fn wide_graph(sodium_ctx: &SodiumCtx) -> Vec<String> {
+    let out = Arc::new(Mutex::new(Vec::<String>::new()));
+    {
+        let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let sb: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let mut listeners = Vec::new();
+        let mut branches: Vec<Stream<i32>> = Vec::new();
+        for i in 0..16 {
+            let branch = sa
+                .stream()
+                .map(move |x: &i32| *x * (i + 1))
+                .filter(move |x: &i32| *x % 3 != i % 3)
+                .map(move |x: &i32| *x + i);
+            {
+                let out = out.clone();
+                listeners.push(
+                    branch.listen(move |x: &i32| {
+                        out.lock().unwrap().push(format!("branch{} {}", i, x))
+                    }),
+                );
+            }
+            branches.push(branch);
+        }
+        let mut merged = branches[0].clone();
+        for branch in &branches[1..] {
+            merged = merged.merge(branch, |a: &i32, b: &i32| *a + *b);
+        }
+        let total = merged.accum(0, |x: &i32, total: &i32| *x + *total);
+        let lifted = total.lift2(&sb.stream().hold(0), |t: &i32, b: &i32| *t - *b);
+        {
+            let out = out.clone();
+            listeners.push(
+                merged.listen(move |x: &i32| out.lock().unwrap().push(format!("merged {}", x))),
+            );
+        }
+        {
+            let out = out.clone();
+            listeners.push(
+                lifted.listen(move |x: &i32| out.lock().unwrap().push(format!("lifted {}", x))),
+            );
+        }
+        for x in 1..10 {
+            sodium_ctx.transaction(|| {
+                sa.send(x);
+                if x % 2 == 1 {
+                    sb.send(x * 10);
+                }
+            });
+        }
+        for l in listeners {
+            l.unlisten();
+        }
+    }
+    let out = out.lock().unwrap();
+    out.clone()
+}
+
+// wide_graph with the branches fed alternately from two sinks, so a
+// merge can see its inputs updated by separate sends
+fn mixed_source_graph(sodium_ctx: &SodiumCtx) -> Vec<String> {
+    let out = Arc::new(Mutex::new(Vec::<String>::new()));
+    {
+        let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let sb: StreamSink<i32> = sodium_ctx.new_stream_sink();
+        let mut listeners = Vec::new();
+        let mut branches: Vec<Stream<i32>> = Vec::new();
+        for i in 0..16 {
+            let source = if i % 2 == 0 { sa.stream() } else { sb.stream() };
+            let branch = source
+                .map(move |x: &i32| *x * (i + 1))
+                .filter(move |x: &i32| *x % 3 != i % 3)
+                .map(move |x: &i32| *x + i);
+            {
+                let out = out.clone();
+                listeners.push(
+                    branch.listen(move |x: &i32| {
+                        out.lock().unwrap().push(format!("branch{} {}", i, x))
+                    }),
+                );
+            }
+            branches.push(branch);
+        }
+        let mut merged = branches[0].clone();
+        for branch in &branches[1..] {
+            merged = merged.merge(branch, |a: &i32, b: &i32| *a + *b);
+        }
+        let total = merged.accum(0, |x: &i32, total: &i32| *x + *total);
+        let lifted = total.lift2(&sb.stream().hold(0), |t: &i32, b: &i32| *t - *b);
+        {
+            let out = out.clone();
+            listeners.push(
+                merged.listen(move |x: &i32| out.lock().unwrap().push(format!("merged {}", x))),
+            );
+        }
+        {
+            let out = out.clone();
+            listeners.push(
+                lifted.listen(move |x: &i32| out.lock().unwrap().push(format!("lifted {}", x))),
+            );
+        }
+        for x in 1..10 {
+            sodium_ctx.transaction(|| {
+                sa.send(x);
+                if x % 2 == 1 {
+                    sb.send(x * 10);
+                }
+            });
+        }
+        for l in listeners {
+            l.unlisten();
+        }
+    }
+    let out = out.lock().unwrap();
+    out.clone()
+}
+
+fn switching_graph(sodium_ctx: &SodiumCtx) -> Vec<String> {
+    let out = Arc::new(Mutex::new(Vec::<String>::new()));
+    {
+        let ss_input: StreamSink<i64>;
+        let s_output;
+        {
+            let _t = sodium_ctx.new_transaction();
+            ss_input = sodium_ctx.new_stream_sink();
+            let sl_s_output: StreamLoop<Stream<i64>> = sodium_ctx.new_stream_loop();
+            let c_s_output = sl_s_output.stream().hold(ss_input.stream());
+            s_output = Cell::switch_s(&c_s_output);
+            let s_output_next =
+                s_output.snapshot(&c_s_output, |prime: &i64, old_s_output: &Stream<i64>| {
+                    let prime = *prime;
+                    old_s_output.filter(move |x: &i64| (*x % prime) != 0)
+                });
+            sl_s_output.loop_(&Operational::defer(&s_output_next));
+        }
+        let cca = sodium_ctx.new_cell_sink(sodium_ctx.new_cell(0_i64));
+        let c = Cell::switch_c(&cca.cell());
+        let l1;
+        let l2;
+        {
+            let out = out.clone();
+            l1 = s_output.listen(move |x: &i64| out.lock().unwrap().push(format!("prime {}", x)));
+        }
+        {
+            let out = out.clone();
+            l2 = c.listen(move |x: &i64| out.lock().unwrap().push(format!("switched {}", x)));
+        }
+        for x in 2..50 {
+            ss_input.send(x);
+            if x % 7 == 0 {
+                cca.send(s_output.hold(x).map(|p: &i64| *p * 2));
+            }
+        }
+        l1.unlisten();
+        l2.unlisten();
+    }
+    let out = out.lock().unwrap();
+    out.clone()
+}
+
+#[test]
+fn thread_pool_wide_graph() {
+    init();
+    let expected = wide_graph(&SodiumCtx::new());
+    for num_threads in [1, 2, 4, 8] {
+        let sodium_ctx = SodiumCtx::new_with_thread_pool(num_threads);
+        let sodium_ctx = &sodium_ctx;
+        for _ in 0..10 {
+            assert_eq!(expected, wide_graph(sodium_ctx), "threads {}", num_threads);
+        }
+        assert_memory_freed(sodium_ctx);
+    }
+}
+
+#[test]
+fn thread_pool_mixed_source_graph() {
+    init();
+    let expected = mixed_source_graph(&SodiumCtx::new());
+    for num_threads in [1, 2, 4, 8] {
+        let sodium_ctx = SodiumCtx::new_with_thread_pool(num_threads);
+        let sodium_ctx = &sodium_ctx;
+        for _ in 0..10 {
+            assert_eq!(
+                expected,
+                mixed_source_graph(sodium_ctx),
+                "threads {}",
+                num_threads
+            );
+        }
+        assert_memory_freed(sodium_ctx);
+    }
+}
+
+#[test]
+fn thread_pool_switching_graph() {
+    init();
+    let expected = switching_graph(&SodiumCtx::new());
+    assert!(expected.contains(&String::from("prime 47")));
+    for num_threads in [1, 2, 4] {
+        let sodium_ctx = SodiumCtx::new_with_thread_pool(num_threads);
+        let sodium_ctx = &sodium_ctx;
+        for _ in 0..5 {
+            assert_eq!(expected, switching_graph(sodium_ctx));
+        }
+        assert_memory_freed(sodium_ctx);
+    }
+}
+
+#[test]
+#[should_panic(expected = "boom")]
+fn thread_pool_propagates_panics() {
+    let sodium_ctx = SodiumCtx::new_with_thread_pool(2);
+    let sa: StreamSink<i32> = sodium_ctx.new_stream_sink();
+    let sb: StreamSink<i32> = sodium_ctx.new_stream_sink();
+    let s1 = sa.stream().map(|x: &i32| -> i32 {
+        if *x == 2 {
+            panic!("boom");
+        }
+        *x
+    });
+    let s2 = sb.stream().map(|x: &i32| *x);
+    let _l = s1
+        .merge(&s2, |a: &i32, b: &i32| *a + *b)
+        .listen(|_: &i32| {});
+    sodium_ctx.transaction(|| {
+        sa.send(2);
+        sb.send(2);
+    });
+}
@@ -0,0 +1,316 @@
+use crate::timer::{MillisecondsTimerSystem, Time, TimerSystem, VirtualTimerSystem};
+use crate::{CellLoop, SodiumCtx, Stream};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::tests::{assert_memory_freed, init};
+
+fn ms(millis: u64) -> Time {
+    Duration::from_millis(millis)
+}
+
+fn with_time<A: Clone + Send + 'static>(
+    sys: &VirtualTimerSystem,
+    s: &Stream<A>,
+) -> Stream<(A, Time)> {
+    s.snapshot(&sys.time(), |a: &A, t: &Time| (a.clone(), *t))
+}
+
+#[test]
+fn virtual_time() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = sys
+                .time()
+                .listen(move |t: &Time| out.lock().unwrap().push(*t));
+        }
+        sys.advance(ms(5));
+        sys.advance_to(ms(12));
+        sys.advance_to(ms(3));
+        assert_eq!(ms(12), sys.now());
+        assert_eq!(ms(12), sys.time().sample());
+        l.unlisten();
+        assert_eq!(vec![ms(0), ms(5), ms(12)], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn virtual_at() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let alarm = sodium_ctx.new_cell_sink(Some(ms(10)));
+        let time = sys.time();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = sys
+                .at(&alarm.cell())
+                .snapshot(&time, |a: &Time, t: &Time| (*a, *t))
+                .listen(move |x: &(Time, Time)| out.lock().unwrap().push(*x));
+        }
+        sys.advance(ms(5));
+        assert!(out.lock().unwrap().is_empty());
+        sys.advance(ms(10));
+        assert_eq!(vec![(ms(10), ms(10))], *out.lock().unwrap());
+        // replacing an alarm cancels the old one
+        alarm.send(Some(ms(20)));
+        alarm.send(Some(ms(30)));
+        sys.advance_to(ms(25));
+        alarm.send(None);
+        sys.advance_to(ms(40));
+        // an alarm in the past fires straight away
+        alarm.send(Some(ms(1)));
+        l.unlisten();
+        assert_eq!(
+            vec![(ms(10), ms(10)), (ms(1), ms(40))],
+            *out.lock().unwrap()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn virtual_repeating_alarm() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let _t = sodium_ctx.new_transaction();
+            let alarm: CellLoop<Option<Time>> = sodium_ctx.new_cell_loop();
+            let s_tick = sys.at(&alarm.cell());
+            alarm.loop_(&s_tick.map(|t: &Time| Some(*t + ms(10))).hold(Some(ms(10))));
+            let out = out.clone();
+            l = s_tick.listen(move |t: &Time| out.lock().unwrap().push(*t));
+        }
+        sys.advance_to(ms(35));
+        l.unlisten();
+        assert_eq!(vec![ms(10), ms(20), ms(30)], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn milliseconds_at() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = MillisecondsTimerSystem::new(sodium_ctx);
+        let start = sys.now();
+        let alarm = sodium_ctx.new_cell_sink(Some(start + ms(20)));
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let l = sys
+            .at(&alarm.cell())
+            .snapshot(&sys.time(), |a: &Time, t: &Time| (*a, *t))
+            .listen(move |x: &(Time, Time)| tx.lock().unwrap().send(*x).unwrap());
+        let (a, t) = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(start + ms(20), a);
+        assert_eq!(a, t);
+        assert!(sys.now() >= a);
+        l.unlisten();
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn periodic() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        sys.advance(ms(3));
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = sys
+                .periodic(ms(10))
+                .listen(move |t: &Time| out.lock().unwrap().push(*t));
+        }
+        sys.advance_to(ms(35));
+        assert_eq!(vec![ms(13), ms(23), ms(33)], *out.lock().unwrap());
+        // missed ticks all fire in turn
+        sys.advance_to(ms(60));
+        l.unlisten();
+        assert_eq!(
+            vec![ms(13), ms(23), ms(33), ms(43), ms(53)],
+            *out.lock().unwrap()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn sample_every() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let c = sodium_ctx.new_cell_sink(1);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = with_time(&sys, &c.cell().sample_every(&sys, ms(10)))
+                .listen(move |x: &(i32, Time)| out.lock().unwrap().push(*x));
+        }
+        sys.advance_to(ms(15));
+        c.send(2);
+        c.send(3);
+        sys.advance_to(ms(30));
+        l.unlisten();
+        assert_eq!(
+            vec![(1, ms(10)), (3, ms(20)), (3, ms(30))],
+            *out.lock().unwrap()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn delay() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let s = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = with_time(&sys, &s.stream().delay(&sys, ms(10)))
+                .listen(move |x: &(char, Time)| out.lock().unwrap().push(*x));
+        }
+        s.send('a');
+        sys.advance(ms(5));
+        s.send('b');
+        sys.advance(ms(2));
+        s.send('c');
+        sys.advance_to(ms(12));
+        assert_eq!(vec![('a', ms(10))], *out.lock().unwrap());
+        sys.advance_to(ms(40));
+        l.unlisten();
+        assert_eq!(
+            vec![('a', ms(10)), ('b', ms(15)), ('c', ms(17))],
+            *out.lock().unwrap()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn debounce() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let s = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = with_time(&sys, &s.stream().debounce(&sys, ms(10)))
+                .listen(move |x: &(i32, Time)| out.lock().unwrap().push(*x));
+        }
+        s.send(1);
+        sys.advance(ms(3));
+        s.send(2);
+        sys.advance_to(ms(8));
+        assert!(out.lock().unwrap().is_empty());
+        sys.advance_to(ms(20));
+        s.send(3);
+        sys.advance_to(ms(40));
+        l.unlisten();
+        assert_eq!(vec![(2, ms(13)), (3, ms(30))], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn throttle() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let s = sodium_ctx.new_stream_sink();
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            l = with_time(&sys, &s.stream().throttle(&sys, ms(10)))
+                .listen(move |x: &(i32, Time)| out.lock().unwrap().push(*x));
+        }
+        s.send(1);
+        sys.advance(ms(2));
+        s.send(2);
+        sys.advance(ms(2));
+        s.send(3);
+        assert_eq!(vec![(1, ms(0))], *out.lock().unwrap());
+        sys.advance_to(ms(25));
+        s.send(4);
+        sys.advance_to(ms(40));
+        l.unlisten();
+        assert_eq!(
+            vec![(1, ms(0)), (3, ms(10)), (4, ms(25))],
+            *out.lock().unwrap()
+        );
+    }
+    assert_memory_freed(sodium_ctx);
+}
+
+#[test]
+fn periodic_survives_a_panic() {
+    init();
+    let sodium_ctx = SodiumCtx::new();
+    let sodium_ctx = &sodium_ctx;
+    {
+        let sys = VirtualTimerSystem::new(sodium_ctx);
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let l;
+        {
+            let out = out.clone();
+            let mut panicked = false;
+            l = sys.periodic(ms(10)).listen(move |t: &Time| {
+                if *t == ms(20) && !panicked {
+                    panicked = true;
+                    panic!("tick");
+                }
+                out.lock().unwrap().push(*t);
+            });
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| sys.advance(ms(25))));
+        assert!(result.is_err());
+        assert_eq!(vec![ms(10)], *out.lock().unwrap());
+        // the tick that was rolled back fires again, and the timer goes on
+        sys.advance_to(ms(45));
+        assert_eq!(ms(45), sys.time().sample());
+        l.unlisten();
+        assert_eq!(vec![ms(10), ms(20), ms(30), ms(40)], *out.lock().unwrap());
+    }
+    assert_memory_freed(sodium_ctx);
+}
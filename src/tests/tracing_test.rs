@@ -0,0 +1,126 @@
+use crate::SodiumCtx;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+use crate::tests::{assert_memory_freed, init};
+
+// Logs spans being entered and exited and events, on the thread it is
+// the default for.
+#[derive(Clone, Default)]
+struct Recorder {
+    next_id: Arc<AtomicU64>,
+    spans: Arc<Mutex<Vec<(String, &'static Metadata<'static>)>>>,
+    stack: Arc<Mutex<Vec<u64>>>,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" || field.name() == "name" {
+            self.0.push_str(&format!(" {:?}", value));
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields(attrs.metadata().name().to_string());
+        attrs.record(&mut fields);
+        let parent = match attrs.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if attrs.is_contextual() => self.stack.lock().unwrap().last().copied(),
+            None => None,
+        };
+        if let Some(parent) = parent {
+            let parent = self.spans.lock().unwrap()[parent as usize - 1].0.clone();
+            fields.0.push_str(&format!(" in {}", parent));
+        }
+        self.spans
+            .lock()
+            .unwrap()
+            .push((fields.0, attrs.metadata()));
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields("event".to_string());
+        event.record(&mut fields);
+        self.log.lock().unwrap().push(fields.0);
+    }
+
+    fn enter(&self, span: &Id) {
+        self.stack.lock().unwrap().push(span.into_u64());
+        let span = self.spans.lock().unwrap()[span.into_u64() as usize - 1]
+            .0
+            .clone();
+        self.log.lock().unwrap().push(format!("enter {}", span));
+    }
+
+    fn exit(&self, span: &Id) {
+        self.stack.lock().unwrap().pop();
+        let span = self.spans.lock().unwrap()[span.into_u64() as usize - 1]
+            .0
+            .clone();
+        self.log.lock().unwrap().push(format!("exit {}", span));
+    }
+
+    fn current_span(&self) -> Current {
+        match self.stack.lock().unwrap().last() {
+            Some(id) => {
+                let metadata = self.spans.lock().unwrap()[*id as usize - 1].1;
+                Current::new(Id::from_u64(*id), metadata)
+            }
+            None => Current::none(),
+        }
+    }
+}
+
+#[test]
+fn transaction_spans() {
+    init();
+    let sodium_ctx = SodiumCtx::builder()
+        .single_threaded()
+        .collect_cycles_every(1)
+        .build();
+    let sodium_ctx = &sodium_ctx;
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let s = sodium_ctx.new_stream_sink();
+        let l = s.stream().map(|a: &i32| *a + 1).listen(|_: &i32| {});
+        recorder.log.lock().unwrap().clear();
+        s.send(1);
+        l.unlisten();
+    });
+    assert_eq!(
+        vec![
+            "enter transaction",
+            "event pre_eot",
+            "enter update_node Stream::map in transaction",
+            "exit update_node Stream::map in transaction",
+            "enter update_node Stream::listen in transaction",
+            "exit update_node Stream::listen in transaction",
+            "event pre_post",
+            "event post",
+            "event collect_cycles",
+            "exit transaction",
+        ],
+        *recorder.log.lock().unwrap()
+    );
+    assert_memory_freed(sodium_ctx);
+}
@@ -0,0 +1,312 @@
+//! Timers that fire as ordinary Sodium transactions.
+//!
+//! A [`TimerSystem`] provides a [`Cell`] holding the current time and
+//! alarms in the form of [`Stream`]s. Two timer systems are provided: a
+//! [`VirtualTimerSystem`] whose time only moves when it is advanced,
+//! which makes code depending on time deterministic in tests, and a
+//! [`MillisecondsTimerSystem`] that follows the wall clock.
+//!
+//! On top of a timer system, [`periodic`][TimerSystem::periodic] gives
+//! a stream that fires at a fixed rate, [`Stream::delay`],
+//! [`Stream::debounce`] and [`Stream::throttle`] shift or thin out
+//! events over time, and [`Cell::sample_every`] samples a cell at a
+//! fixed rate.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::impl_::timer::milliseconds_timer_system;
+use crate::impl_::timer::TimerSystem as TimerSystemImpl;
+use crate::impl_::timer::VirtualTimerSystem as VirtualTimerSystemImpl;
+use crate::Cell;
+use crate::CellLoop;
+use crate::SodiumCtx;
+use crate::Stream;
+
+/// A point in time, measured from the epoch of the [`TimerSystem`] it
+/// came from.
+pub type Time = Duration;
+
+/// A source of time and alarms for Sodium logic.
+///
+/// Alarms that are due fire at the start of the next transaction, each
+/// one in a transaction of its own, with [`time`][TimerSystem::time]
+/// moved forward to the time of the alarm.
+pub trait TimerSystem {
+    /// A cell holding the current time.
+    ///
+    /// The time is brought up to date at the start of every
+    /// transaction, and never goes backwards.
+    fn time(&self) -> Cell<Time>;
+
+    /// Return a stream that fires once the time reaches the alarm
+    /// held in the given cell, with the time of the alarm.
+    ///
+    /// Changing the alarm cancels the previous alarm if it has not
+    /// fired yet, and `None` sets no alarm at all. An alarm that is
+    /// already in the past fires straight after the transaction that
+    /// set it.
+    fn at(&self, alarm: &Cell<Option<Time>>) -> Stream<Time>;
+
+    /// Return a stream that fires every `period`, starting one period
+    /// after the transaction it was created in, with the time of each
+    /// tick.
+    ///
+    /// Ticks are spaced from each other rather than from when they
+    /// were handled, so they don't drift. If ticks are missed (say the
+    /// wall clock jumps forward) they all fire in turn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    fn periodic(&self, period: Duration) -> Stream<Time> {
+        assert!(period > Duration::ZERO, "period must not be zero");
+        let time = self.time();
+        let sodium_ctx = SodiumCtx {
+            impl_: time.impl_.sodium_ctx(),
+        };
+        sodium_ctx.transaction(|| {
+            let alarm: CellLoop<Option<Time>> = sodium_ctx.new_cell_loop();
+            let s_tick = self.at(&alarm.cell());
+            let first = time.sample() + period;
+            alarm.loop_(
+                &s_tick
+                    .map(move |t: &Time| Some(*t + period))
+                    .hold(Some(first)),
+            );
+            s_tick
+        })
+    }
+}
+
+/// A [`TimerSystem`] whose time starts at zero and only moves when it
+/// is advanced.
+///
+/// Meant for tests, where it makes logic depending on time run the same
+/// way every time, without waiting.
+pub struct VirtualTimerSystem {
+    pub impl_: VirtualTimerSystemImpl,
+}
+
+impl Clone for VirtualTimerSystem {
+    fn clone(&self) -> Self {
+        VirtualTimerSystem {
+            impl_: self.impl_.clone(),
+        }
+    }
+}
+
+impl VirtualTimerSystem {
+    /// Create a new `VirtualTimerSystem` in the given context, with its
+    /// time at zero.
+    pub fn new(sodium_ctx: &SodiumCtx) -> VirtualTimerSystem {
+        VirtualTimerSystem {
+            impl_: VirtualTimerSystemImpl::new(&sodium_ctx.impl_),
+        }
+    }
+
+    /// Return the current virtual time.
+    pub fn now(&self) -> Time {
+        self.impl_.timer_system.now()
+    }
+
+    /// Move the time forward by the given duration.
+    ///
+    /// See [`advance_to`][VirtualTimerSystem::advance_to].
+    pub fn advance(&self, duration: Duration) {
+        self.advance_to(self.now() + duration);
+    }
+
+    /// Move the time forward to `t`, firing every alarm due on the way
+    /// in the order of their times.
+    ///
+    /// Alarms set while the time is being advanced also fire, if they
+    /// fall before `t`. Does nothing if `t` is not after the current
+    /// time. This method may not be called from inside a transaction.
+    pub fn advance_to(&self, t: Time) {
+        self.impl_.advance_to(t);
+    }
+}
+
+impl TimerSystem for VirtualTimerSystem {
+    fn time(&self) -> Cell<Time> {
+        Cell {
+            impl_: self.impl_.timer_system.time(),
+        }
+    }
+
+    fn at(&self, alarm: &Cell<Option<Time>>) -> Stream<Time> {
+        Stream {
+            impl_: self.impl_.timer_system.at(&alarm.impl_),
+        }
+    }
+}
+
+/// A [`TimerSystem`] following the wall clock, to the millisecond,
+/// measured from the Unix epoch.
+///
+/// A background thread starts a transaction whenever the next alarm is
+/// due. The thread exits once the timer system and all the streams
+/// returned from [`at`][TimerSystem::at] have been dropped.
+pub struct MillisecondsTimerSystem {
+    pub impl_: TimerSystemImpl,
+}
+
+impl Clone for MillisecondsTimerSystem {
+    fn clone(&self) -> Self {
+        MillisecondsTimerSystem {
+            impl_: self.impl_.clone(),
+        }
+    }
+}
+
+impl MillisecondsTimerSystem {
+    /// Create a new `MillisecondsTimerSystem` in the given context.
+    pub fn new(sodium_ctx: &SodiumCtx) -> MillisecondsTimerSystem {
+        MillisecondsTimerSystem {
+            impl_: milliseconds_timer_system(&sodium_ctx.impl_),
+        }
+    }
+
+    /// Return the current time.
+    pub fn now(&self) -> Time {
+        self.impl_.now()
+    }
+}
+
+impl TimerSystem for MillisecondsTimerSystem {
+    fn time(&self) -> Cell<Time> {
+        Cell {
+            impl_: self.impl_.time(),
+        }
+    }
+
+    fn at(&self, alarm: &Cell<Option<Time>>) -> Stream<Time> {
+        Stream {
+            impl_: self.impl_.at(&alarm.impl_),
+        }
+    }
+}
+
+// Whether to drop the oldest pending event, and an event to add, in one
+// transaction of `delay`.
+type DelayChange<A> = (bool, Option<(Time, A)>);
+
+pub(crate) fn delay<A, TS>(s: &Stream<A>, timer_system: &TS, duration: Duration) -> Stream<A>
+where
+    A: Clone + Send + 'static,
+    TS: TimerSystem + ?Sized,
+{
+    let sodium_ctx = SodiumCtx {
+        impl_: s.impl_.sodium_ctx(),
+    };
+    sodium_ctx.transaction(|| {
+        let time = timer_system.time();
+        let pending: CellLoop<VecDeque<(Time, A)>> = sodium_ctx.new_cell_loop();
+        let s_due = timer_system.at(&pending
+            .cell()
+            .map(|q: &VecDeque<(Time, A)>| q.front().map(|(t, _)| *t)));
+        let s_out = s_due
+            .snapshot(&pending.cell(), |_: &Time, q: &VecDeque<(Time, A)>| {
+                q.front().map(|(_, a)| a.clone())
+            })
+            .filter_option();
+        let s_pop = s_due.map(|_: &Time| -> DelayChange<A> { (true, None) });
+        let s_push = s.snapshot(&time, move |a: &A, t: &Time| -> DelayChange<A> {
+            (false, Some((*t + duration, a.clone())))
+        });
+        let s_change = s_pop.merge(&s_push, |l: &DelayChange<A>, r: &DelayChange<A>| {
+            (l.0 || r.0, r.1.clone())
+        });
+        pending.loop_(
+            &s_change
+                .snapshot(
+                    &pending.cell(),
+                    |c: &DelayChange<A>, q: &VecDeque<(Time, A)>| {
+                        let mut q = q.clone();
+                        if c.0 {
+                            q.pop_front();
+                        }
+                        if let Some(ref x) = c.1 {
+                            q.push_back(x.clone());
+                        }
+                        q
+                    },
+                )
+                .hold(VecDeque::new()),
+        );
+        s_out
+    })
+}
+
+pub(crate) fn debounce<A, TS>(s: &Stream<A>, timer_system: &TS, duration: Duration) -> Stream<A>
+where
+    A: Clone + Send + 'static,
+    TS: TimerSystem + ?Sized,
+{
+    let sodium_ctx = SodiumCtx {
+        impl_: s.impl_.sodium_ctx(),
+    };
+    sodium_ctx.transaction(|| {
+        let time = timer_system.time();
+        let pending: CellLoop<Option<(Time, A)>> = sodium_ctx.new_cell_loop();
+        let s_due = timer_system.at(&pending
+            .cell()
+            .map(|p: &Option<(Time, A)>| p.as_ref().map(|(t, _)| *t)));
+        let s_out = s_due
+            .snapshot(&pending.cell(), |_: &Time, p: &Option<(Time, A)>| {
+                p.as_ref().map(|(_, a)| a.clone())
+            })
+            .filter_option();
+        let s_set = s.snapshot(&time, move |a: &A, t: &Time| {
+            Some((*t + duration, a.clone()))
+        });
+        pending.loop_(&s_set.or_else(&s_due.map(|_: &Time| None)).hold(None));
+        s_out
+    })
+}
+
+// The end of the current window, with the last event held back during it.
+type ThrottleState<A> = Option<(Time, Option<A>)>;
+
+pub(crate) fn throttle<A, TS>(s: &Stream<A>, timer_system: &TS, duration: Duration) -> Stream<A>
+where
+    A: Clone + Send + 'static,
+    TS: TimerSystem + ?Sized,
+{
+    let sodium_ctx = SodiumCtx {
+        impl_: s.impl_.sodium_ctx(),
+    };
+    sodium_ctx.transaction(|| {
+        let time = timer_system.time();
+        let state: CellLoop<ThrottleState<A>> = sodium_ctx.new_cell_loop();
+        let s_window_end = timer_system
+            .at(&state
+                .cell()
+                .map(|st: &ThrottleState<A>| st.as_ref().map(|(end, _)| *end)))
+            .snapshot(
+                &state.cell(),
+                move |t: &Time, st: &ThrottleState<A>| match st {
+                    Some((_, Some(a))) => (Some(a.clone()), Some((*t + duration, None))),
+                    _ => (None, None),
+                },
+            );
+        let s_event = s.snapshot3(
+            &state.cell(),
+            &time,
+            move |a: &A, st: &ThrottleState<A>, t: &Time| match st {
+                None => (Some(a.clone()), Some((*t + duration, None))),
+                Some((end, _)) => (None, Some((*end, Some(a.clone())))),
+            },
+        );
+        let s_step = s_window_end.or_else(&s_event);
+        state.loop_(
+            &s_step
+                .map(|(_, st): &(Option<A>, ThrottleState<A>)| st.clone())
+                .hold(None),
+        );
+        s_step
+            .map(|(out, _): &(Option<A>, ThrottleState<A>)| out.clone())
+            .filter_option()
+    })
+}
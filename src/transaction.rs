@@ -6,6 +6,9 @@ use crate::SodiumCtx;
 /// An alternative to [`SodiumCtx::transaction`] that creates a struct
 /// that will create a new transaction in the given [`SodiumCtx`] and
 /// hold it open until the `Transaction` is dropped.
+///
+/// A `Transaction` dropped while a panic unwinds rolls its transaction
+/// back rather than closing it, as [`SodiumCtx::transaction`] does.
 pub struct Transaction {
     impl_: TransactionImpl,
 }